@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use svc_utils::bench::criterion::{criterion_group, criterion_main, Criterion};
+use svc_utils::bench::{handler_router, StackBench};
+use svc_utils::middleware::{
+    preset, BodyLimitLayer, CompressionLayer, CorsLayer, CpuTimeLayer, LogLayer, MetricsLayer,
+    OverheadLayer, OverheadMarkLayer, SlowPollLayer, TimeoutLayer,
+};
+
+fn layers(c: &mut Criterion) {
+    let mut bench = StackBench::new(c, "layer");
+    bench
+        .bench(
+            "body_limit",
+            handler_router().layer(BodyLimitLayer::new(1024 * 1024)),
+        )
+        .bench(
+            "compression",
+            handler_router().layer(CompressionLayer::new()),
+        )
+        .bench("cors", handler_router().layer(CorsLayer::new()))
+        .bench("cpu_time", handler_router().layer(CpuTimeLayer))
+        .bench("log", handler_router().layer(LogLayer::new()))
+        .bench("metrics", handler_router().layer(MetricsLayer::new("/")))
+        .bench(
+            "overhead",
+            handler_router()
+                .layer(OverheadMarkLayer)
+                .layer(OverheadLayer),
+        )
+        .bench(
+            "slow_poll",
+            handler_router().layer(SlowPollLayer::new(Duration::from_millis(10))),
+        )
+        .bench(
+            "timeout",
+            handler_router().layer(TimeoutLayer::new(Duration::from_secs(10))),
+        );
+    bench.finish();
+}
+
+fn stacks(c: &mut Criterion) {
+    let mut bench = StackBench::new(c, "stack");
+    bench
+        .bench("preset", handler_router().layer(preset("/", 1024 * 1024)))
+        .bench(
+            "preset_compression_timeout",
+            handler_router()
+                .layer(TimeoutLayer::new(Duration::from_secs(10)))
+                .layer(CompressionLayer::new())
+                .layer(preset("/", 1024 * 1024)),
+        );
+    bench.finish();
+}
+
+criterion_group!(benches, layers, stacks);
+criterion_main!(benches);
@@ -56,12 +56,11 @@ async fn main() {
     let mut signals_stream = signal_hook_tokio::Signals::new(TERM_SIGNALS)
         .unwrap()
         .fuse();
-    let signals = signals_stream.next();
 
-    axum::Server::bind(&"0.0.0.0:8080".parse().unwrap())
-        .serve(app.into_make_service())
-        .with_graceful_shutdown(async {
-            signals.await;
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
+    axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(async move {
+            signals_stream.next().await;
             eprintln!("\nServer shutting down...")
         })
         .await
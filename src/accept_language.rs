@@ -0,0 +1,29 @@
+use http::{header::ACCEPT_LANGUAGE, HeaderMap};
+
+/// Languages of `Accept-Language` from the most preferred
+pub(crate) fn accepted_languages(headers: &HeaderMap) -> Vec<String> {
+    let mut langs = headers
+        .get_all(ACCEPT_LANGUAGE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let lang = params.next()?.trim().to_ascii_lowercase();
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if lang.is_empty() || lang == "*" || quality <= 0.0 {
+                None
+            } else {
+                Some((lang, quality))
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // stable sort keeps the header order of languages with the same quality
+    langs.sort_by(|a, b| b.1.total_cmp(&a.1));
+    langs.into_iter().map(|(lang, _)| lang).collect()
+}
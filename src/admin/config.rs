@@ -0,0 +1,84 @@
+use axum::{routing, Json, Router};
+use serde::Serialize;
+use serde_json::Value;
+use url::Url;
+
+const MASK: &str = "********";
+
+const SECRET_KEYS: [&str; 8] = [
+    "password",
+    "passwd",
+    "secret",
+    "token",
+    "key",
+    "credential",
+    "private",
+    "auth",
+];
+
+/// Route serving the loaded configuration as JSON on `GET /admin/config`
+/// so on-call engineers can check what a pod is actually running.
+///
+/// Values of keys that look like secrets (`password`, `token`, `key` etc.) are masked
+/// whatever their type, so are passwords and secret query parameters in URLs. Meant to be
+/// passed to `admin_router`.
+pub fn config_dump_route<C: Serialize>(config: &C) -> Result<Router, serde_json::Error> {
+    let mut value = serde_json::to_value(config)?;
+    mask(&mut value);
+
+    Ok(Router::new().route(
+        "/admin/config",
+        routing::get(move || async move { Json(value) }),
+    ))
+}
+
+fn mask(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_secret(key) && !value.is_null() {
+                    *value = Value::String(MASK.to_owned());
+                } else {
+                    mask(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(mask),
+        Value::String(string) => {
+            if let Ok(mut url) = Url::parse(string) {
+                if mask_url(&mut url) {
+                    *string = url.to_string();
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Mask the password and secret query parameters, returns whether anything was masked
+fn mask_url(url: &mut Url) -> bool {
+    let mut masked = url.password().is_some() && url.set_password(Some(MASK)).is_ok();
+
+    if url.query_pairs().any(|(key, _)| is_secret(&key)) {
+        let pairs = url
+            .query_pairs()
+            .map(|(key, value)| {
+                let value = if is_secret(&key) {
+                    MASK.to_owned()
+                } else {
+                    value.into_owned()
+                };
+                (key.into_owned(), value)
+            })
+            .collect::<Vec<_>>();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+        masked = true;
+    }
+
+    masked
+}
+
+fn is_secret(key: &str) -> bool {
+    let key = key.to_lowercase();
+    SECRET_KEYS.iter().any(|secret| key.contains(secret))
+}
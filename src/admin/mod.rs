@@ -0,0 +1,5 @@
+pub use config::config_dump_route;
+pub use router::{admin_router, AdminConfig};
+
+mod config;
+mod router;
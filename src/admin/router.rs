@@ -0,0 +1,123 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::{
+    extract::{ConnectInfo, Json, Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use ipnet::IpNet;
+use svc_agent::AccountId;
+use svc_error::Error;
+use tracing::{info, warn};
+
+use crate::extractors::AccountIdExtractor;
+
+/// Access settings of the admin router
+pub struct AdminConfig {
+    accounts: Vec<AccountId>,
+    networks: Vec<IpNet>,
+}
+
+impl AdminConfig {
+    /// Allow requests authenticated as one of `accounts` coming from loopback
+    /// or private (RFC 1918, RFC 4193) networks.
+    pub fn new(accounts: Vec<AccountId>) -> Self {
+        let networks = [
+            "127.0.0.0/8",
+            "10.0.0.0/8",
+            "172.16.0.0/12",
+            "192.168.0.0/16",
+            "::1/128",
+            "fc00::/7",
+        ]
+        .iter()
+        .map(|net| net.parse().expect("Invalid builtin network"))
+        .collect();
+
+        Self { accounts, networks }
+    }
+
+    /// Replace networks requests are allowed from
+    pub fn with_networks(mut self, networks: Vec<IpNet>) -> Self {
+        self.networks = networks;
+        self
+    }
+
+    fn is_allowed_ip(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// Protect operational endpoints (cache flush, maintenance toggle, config dump etc.).
+///
+/// Every route of `routes` gets service-account-only authn, IP filtering and audit
+/// logging to the `audit` tracing target. Routes must be added to `routes` before calling
+/// this function since router layers only apply to existing routes. Don't add CORS to the result.
+///
+/// The server must be started with `into_make_service_with_connect_info::<SocketAddr>()`,
+/// otherwise every request is rejected. `AccountIdExtractor` requirements apply as well.
+pub fn admin_router(config: AdminConfig, routes: Router) -> Router {
+    routes.route_layer(middleware::from_fn_with_state(Arc::new(config), guard))
+}
+
+async fn guard(
+    State(config): State<Arc<AdminConfig>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    account_id: Result<AccountIdExtractor, (StatusCode, Json<Error>)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let ip = connect_info.map(|ConnectInfo(addr)| addr.ip());
+
+    match ip {
+        Some(ip) if config.is_allowed_ip(ip) => {}
+        _ => {
+            warn!(target: "audit", ?ip, %method, %path, "Admin request denied: address not allowed");
+            return forbidden();
+        }
+    }
+
+    let account_id = match account_id {
+        Ok(AccountIdExtractor(account_id)) => account_id,
+        Err(rejection) => {
+            warn!(target: "audit", ?ip, %method, %path, "Admin request denied: not authenticated");
+            return rejection.into_response();
+        }
+    };
+
+    if !config.accounts.contains(&account_id) {
+        warn!(target: "audit", ?ip, %account_id, %method, %path, "Admin request denied: account not allowed");
+        return forbidden();
+    }
+
+    let response = next.run(request).await;
+
+    info!(
+        target: "audit",
+        ?ip,
+        %account_id,
+        %method,
+        %path,
+        status_code = %response.status(),
+        "Admin request"
+    );
+
+    response
+}
+
+fn forbidden() -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        Json(Error::new(
+            "access_denied",
+            "Access denied",
+            http_02::StatusCode::FORBIDDEN,
+        )),
+    )
+        .into_response()
+}
@@ -0,0 +1,406 @@
+//! Operational alerts raised by background subsystems (consumers, schedulers, job workers),
+//! deduplicated and forwarded to sinks.
+//!
+//! ```ignore
+//! let alerting = Alerting::new()
+//!     .with_sink(LogSink)
+//!     .with_sink(WebhookSink::with_client(https_client, slack_webhook_url).slack())
+//!     .spawn();
+//! let alerts = alerting.alerts();
+//!
+//! alerts.raise(Alert::new(
+//!     "outbox_relay",
+//!     "kafka_unavailable",
+//!     AlertSeverity::Critical,
+//!     format!("Relay failed {} times in a row: {}", failures, err),
+//! ));
+//! // on shutdown
+//! alerting.shutdown(Duration::from_secs(5)).await;
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use futures::future;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info, warn};
+
+pub use self::webhook::WebhookSink;
+
+mod webhook;
+
+/// Tracing target of alerts written by `LogSink`
+pub const TARGET: &str = "alerts";
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    alerts_vec: IntCounterVec,
+    sink_failures_vec: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            alerts_vec: register_int_counter_vec!(
+                "alerts_total",
+                "Raised alerts by result: sent, suppressed as duplicates or dropped",
+                &["source", "result"]
+            )
+            .expect("Can't create stats metrics"),
+            sink_failures_vec: register_int_counter_vec!(
+                "alert_sink_failures_total",
+                "Alerts a sink failed to deliver",
+                &["sink"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertSeverity {
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    /// Component raising the alert, `source` label of the metrics
+    pub source: &'static str,
+    /// Alerts with the same source and key are duplicates
+    pub key: String,
+    pub severity: AlertSeverity,
+    pub summary: String,
+    /// Duplicates suppressed since the previous alert of the key was sent
+    pub suppressed: u64,
+}
+
+impl Alert {
+    pub fn new(source: &'static str, key: &str, severity: AlertSeverity, summary: String) -> Self {
+        Self {
+            source,
+            key: key.to_owned(),
+            severity,
+            summary,
+            suppressed: 0,
+        }
+    }
+}
+
+/// Destination of alerts, e.g. a chat webhook or an incident management system
+#[async_trait]
+pub trait AlertSink: Send + Sync + 'static {
+    /// `sink` label of the metrics
+    fn name(&self) -> &'static str;
+
+    async fn send(&self, alert: &Alert) -> Result<(), Box<dyn StdError + Send + Sync>>;
+}
+
+/// Writes alerts to the `alerts` tracing target, critical ones at the error level
+pub struct LogSink;
+
+#[async_trait]
+impl AlertSink for LogSink {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), Box<dyn StdError + Send + Sync>> {
+        match alert.severity {
+            AlertSeverity::Critical => error!(
+                target: TARGET,
+                source = alert.source,
+                key = %alert.key,
+                suppressed = alert.suppressed,
+                "{}",
+                alert.summary
+            ),
+            AlertSeverity::Warning => warn!(
+                target: TARGET,
+                source = alert.source,
+                key = %alert.key,
+                suppressed = alert.suppressed,
+                "{}",
+                alert.summary
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// Forwards alerts to every sink in a background task.
+///
+/// An alert is sent at most once per dedup window for its source and key, duplicates
+/// raised within the window are counted and reported with the next sent alert of the key,
+/// or with the last duplicate once the window is over. Sinks are sent to at once, each
+/// with a 10 seconds timeout by default, so a hanging sink doesn't hold up the others.
+/// Alerts are counted in `alerts_total{source,result}`, delivery failures and timeouts in
+/// `alert_sink_failures_total{sink}`.
+pub struct Alerting {
+    sinks: Vec<Box<dyn AlertSink>>,
+    dedup_window: Duration,
+    queue_size: usize,
+    sink_timeout: Duration,
+}
+
+impl Default for Alerting {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Alerting {
+    /// Create new alerting without sinks, sending an alert of a key at most once
+    /// in 10 minutes and queueing up to 100 alerts
+    pub fn new() -> Self {
+        Self {
+            sinks: Vec::new(),
+            dedup_window: Duration::from_secs(600),
+            queue_size: 100,
+            sink_timeout: Duration::from_secs(10),
+        }
+    }
+
+    pub fn with_sink<S: AlertSink>(mut self, sink: S) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    pub fn with_dedup_window(mut self, dedup_window: Duration) -> Self {
+        self.dedup_window = dedup_window;
+        self
+    }
+
+    /// Alerts queued before new ones are dropped, at least 1
+    pub fn with_queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size.max(1);
+        self
+    }
+
+    /// How long a sink may take to send an alert
+    pub fn with_sink_timeout(mut self, sink_timeout: Duration) -> Self {
+        self.sink_timeout = sink_timeout;
+        self
+    }
+
+    /// Start forwarding raised alerts in a separate tokio task
+    pub fn spawn(self) -> AlertingHandle {
+        let (tx, mut rx) = mpsc::channel::<Alert>(self.queue_size);
+        let (closer, mut closed) = oneshot::channel::<()>();
+
+        let join_handle = tokio::task::spawn(async move {
+            let mut dedup = Dedup {
+                window: self.dedup_window,
+                entries: HashMap::new(),
+            };
+            // duplicates are reported and expired keys forgotten in passing
+            let period = self
+                .dedup_window
+                .clamp(Duration::from_secs(1), Duration::from_secs(60));
+            let mut flush = tokio::time::interval(period);
+            flush.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            let mut closing = false;
+
+            loop {
+                let alert = tokio::select! {
+                    alert = rx.recv() => match alert {
+                        Some(alert) => alert,
+                        None => break,
+                    },
+                    // keep sending the queued alerts until the queue is empty
+                    _ = &mut closed, if !closing => {
+                        closing = true;
+                        rx.close();
+                        continue;
+                    }
+                    _ = flush.tick() => {
+                        for alert in dedup.flush() {
+                            self.dispatch(&alert).await;
+                        }
+                        continue;
+                    }
+                };
+
+                if let Some(alert) = dedup.check(alert) {
+                    self.dispatch(&alert).await;
+                }
+            }
+        });
+
+        AlertingHandle {
+            alerts: Alerts { tx },
+            join_handle,
+            closer,
+        }
+    }
+
+    async fn dispatch(&self, alert: &Alert) {
+        METRICS
+            .alerts_vec
+            .with_label_values(&[alert.source, "sent"])
+            .inc();
+
+        let sends = self.sinks.iter().map(|sink| async move {
+            let result = match tokio::time::timeout(self.sink_timeout, sink.send(alert)).await {
+                Ok(result) => result,
+                Err(_) => Err(format!("timed out after {:?}", self.sink_timeout).into()),
+            };
+
+            if let Err(err) = result {
+                METRICS
+                    .sink_failures_vec
+                    .with_label_values(&[sink.name()])
+                    .inc();
+                error!(
+                    sink = sink.name(),
+                    source = alert.source,
+                    key = %alert.key,
+                    "Failed to send alert, error = {}",
+                    err
+                );
+            }
+        });
+        future::join_all(sends).await;
+    }
+}
+
+struct DedupEntry {
+    sent: Instant,
+    suppressed: u64,
+    /// Last suppressed duplicate, reported when the window is over
+    latest: Option<Alert>,
+}
+
+struct Dedup {
+    window: Duration,
+    entries: HashMap<(&'static str, String), DedupEntry>,
+}
+
+impl Dedup {
+    /// The alert to send unless it's a duplicate
+    fn check(&mut self, mut alert: Alert) -> Option<Alert> {
+        match self.entries.get_mut(&(alert.source, alert.key.clone())) {
+            Some(entry) if entry.sent.elapsed() < self.window => {
+                METRICS
+                    .alerts_vec
+                    .with_label_values(&[alert.source, "suppressed"])
+                    .inc();
+                entry.suppressed += 1;
+                entry.latest = Some(alert);
+                None
+            }
+            Some(entry) => {
+                alert.suppressed = entry.suppressed;
+                *entry = DedupEntry {
+                    sent: Instant::now(),
+                    suppressed: 0,
+                    latest: None,
+                };
+                Some(alert)
+            }
+            None => {
+                let entry = DedupEntry {
+                    sent: Instant::now(),
+                    suppressed: 0,
+                    latest: None,
+                };
+                self.entries
+                    .insert((alert.source, alert.key.clone()), entry);
+                Some(alert)
+            }
+        }
+    }
+
+    /// The last duplicates of the keys with the window over, forgetting keys without them
+    fn flush(&mut self) -> Vec<Alert> {
+        let window = self.window;
+        let mut due = Vec::new();
+
+        self.entries.retain(|_, entry| {
+            if entry.sent.elapsed() < window {
+                return true;
+            }
+
+            match entry.latest.take() {
+                Some(mut alert) => {
+                    // the duplicate being sent isn't suppressed anymore
+                    alert.suppressed = entry.suppressed - 1;
+                    entry.sent = Instant::now();
+                    entry.suppressed = 0;
+                    due.push(alert);
+                    true
+                }
+                None => false,
+            }
+        });
+
+        due
+    }
+}
+
+/// Raises alerts to a running `Alerting`
+#[derive(Clone)]
+pub struct Alerts {
+    tx: mpsc::Sender<Alert>,
+}
+
+impl Alerts {
+    /// Queue the alert, it's dropped without waiting when the queue is full
+    pub fn raise(&self, alert: Alert) {
+        if let Err(err) = self.tx.try_send(alert) {
+            let alert = match err {
+                mpsc::error::TrySendError::Full(alert) => alert,
+                mpsc::error::TrySendError::Closed(alert) => alert,
+            };
+            METRICS
+                .alerts_vec
+                .with_label_values(&[alert.source, "dropped"])
+                .inc();
+            warn!(
+                source = alert.source,
+                key = %alert.key,
+                "Alert dropped: {}",
+                alert.summary
+            );
+        }
+    }
+}
+
+/// Handle of a running `Alerting`
+pub struct AlertingHandle {
+    alerts: Alerts,
+    join_handle: JoinHandle<()>,
+    closer: oneshot::Sender<()>,
+}
+
+impl AlertingHandle {
+    pub fn alerts(&self) -> Alerts {
+        self.alerts.clone()
+    }
+
+    /// Stop accepting alerts and send the queued ones, waiting up to `timeout`
+    pub async fn shutdown(self, timeout: Duration) {
+        let _ = self.closer.send(());
+
+        match tokio::time::timeout(timeout, self.join_handle).await {
+            Err(e) => {
+                error!("Alerting timed out during shutdown, error = {:?}", e);
+            }
+            Ok(Err(e)) => {
+                error!("Alerting failed during shutdown, error = {:?}", e);
+            }
+            Ok(Ok(())) => {
+                info!("Alerting successfully exited");
+            }
+        }
+    }
+}
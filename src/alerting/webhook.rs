@@ -0,0 +1,119 @@
+use std::error::Error as StdError;
+
+use bytes::Bytes;
+use http::{header, Request, Uri};
+use http_body_util::{BodyExt, Full};
+use hyper_util::client::legacy::connect::{Connect, HttpConnector};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::json;
+
+use super::{Alert, AlertSeverity, AlertSink};
+
+#[derive(Clone, Copy)]
+enum Format {
+    Json,
+    Slack,
+}
+
+/// Posts alerts to a webhook, either as JSON `{"source", "key", "severity", "summary",
+/// "suppressed"}` or as a Slack incoming webhook message
+pub struct WebhookSink<C = HttpConnector> {
+    client: Client<C, Full<Bytes>>,
+    endpoint: Uri,
+    format: Format,
+    headers: Vec<(header::HeaderName, header::HeaderValue)>,
+}
+
+impl WebhookSink {
+    /// Create new sink over http posting alerts as JSON, use `with_client` for https endpoints
+    pub fn new(endpoint: Uri) -> Self {
+        Self::with_client(Client::builder(TokioExecutor::new()).build_http(), endpoint)
+    }
+}
+
+impl<C> WebhookSink<C>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    pub fn with_client(client: Client<C, Full<Bytes>>, endpoint: Uri) -> Self {
+        Self {
+            client,
+            endpoint,
+            format: Format::Json,
+            headers: vec![],
+        }
+    }
+
+    /// Post alerts as Slack messages, Slack webhooks need a client with https support
+    pub fn slack(mut self) -> Self {
+        self.format = Format::Slack;
+        self
+    }
+
+    /// Add a header to every request, e.g. `Authorization`
+    pub fn with_header(mut self, name: header::HeaderName, value: header::HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    fn payload(&self, alert: &Alert) -> Result<Vec<u8>, serde_json::Error> {
+        match self.format {
+            Format::Json => serde_json::to_vec(alert),
+            Format::Slack => {
+                let icon = match alert.severity {
+                    AlertSeverity::Critical => ":red_circle:",
+                    AlertSeverity::Warning => ":warning:",
+                };
+                let mut text = format!("{} *{}*: {}", icon, alert.source, alert.summary);
+                if alert.suppressed > 0 {
+                    text.push_str(&format!(" ({} duplicates suppressed)", alert.suppressed));
+                }
+                serde_json::to_vec(&json!({ "text": text }))
+            }
+        }
+    }
+}
+
+#[axum::async_trait]
+impl<C> AlertSink for WebhookSink<C>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    fn name(&self) -> &'static str {
+        match self.format {
+            Format::Json => "webhook",
+            Format::Slack => "slack",
+        }
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), Box<dyn StdError + Send + Sync>> {
+        let payload = self.payload(alert)?;
+
+        let mut builder =
+            Request::post(self.endpoint.clone()).header(header::CONTENT_TYPE, "application/json");
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        let req = builder
+            .body(Full::from(payload))
+            .expect("alert request must be valid");
+
+        let res = self.client.request(req).await?;
+        let status = res.status();
+        // drain the body so the connection can be reused
+        let body = res.into_body().collect().await.map(|body| body.to_bytes());
+
+        if status.is_success() {
+            Ok(())
+        } else {
+            let body = body.unwrap_or_default();
+            Err(format!(
+                "webhook answered {}: {}",
+                status,
+                String::from_utf8_lossy(&body)
+            )
+            .into())
+        }
+    }
+}
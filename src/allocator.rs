@@ -0,0 +1,134 @@
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{Gauge, IntGauge};
+use tikv_jemalloc_ctl::{epoch, epoch_mib, stats};
+use tracing::warn;
+
+/// Collector of jemalloc statistics, refreshed on every scrape.
+///
+/// Works only when jemalloc is the global allocator, e.g. with `tikv-jemallocator`:
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+///
+/// prometheus::register(Box::new(JemallocCollector::new()?))?;
+/// ```
+pub struct JemallocCollector {
+    epoch: epoch_mib,
+    allocated_mib: stats::allocated_mib,
+    active_mib: stats::active_mib,
+    resident_mib: stats::resident_mib,
+    mapped_mib: stats::mapped_mib,
+    metadata_mib: stats::metadata_mib,
+    retained_mib: stats::retained_mib,
+    allocated: IntGauge,
+    active: IntGauge,
+    resident: IntGauge,
+    mapped: IntGauge,
+    metadata: IntGauge,
+    retained: IntGauge,
+    fragmentation: Gauge,
+}
+
+impl JemallocCollector {
+    pub fn new() -> Result<Self, tikv_jemalloc_ctl::Error> {
+        Ok(Self {
+            epoch: epoch::mib()?,
+            allocated_mib: stats::allocated::mib()?,
+            active_mib: stats::active::mib()?,
+            resident_mib: stats::resident::mib()?,
+            mapped_mib: stats::mapped::mib()?,
+            metadata_mib: stats::metadata::mib()?,
+            retained_mib: stats::retained::mib()?,
+            allocated: gauge(
+                "jemalloc_allocated_bytes",
+                "Bytes allocated by the application",
+            ),
+            active: gauge(
+                "jemalloc_active_bytes",
+                "Bytes in active pages allocated by the application",
+            ),
+            resident: gauge(
+                "jemalloc_resident_bytes",
+                "Bytes in physically resident data pages mapped by the allocator",
+            ),
+            mapped: gauge(
+                "jemalloc_mapped_bytes",
+                "Bytes in active extents mapped by the allocator",
+            ),
+            metadata: gauge(
+                "jemalloc_metadata_bytes",
+                "Bytes dedicated to allocator metadata",
+            ),
+            retained: gauge(
+                "jemalloc_retained_bytes",
+                "Bytes in virtual memory mappings retained by the allocator",
+            ),
+            fragmentation: Gauge::new(
+                "jemalloc_fragmentation_ratio",
+                "Share of active pages not allocated by the application",
+            )
+            .expect("Can't create stats metrics"),
+        })
+    }
+
+    fn refresh(&self) -> Result<(), tikv_jemalloc_ctl::Error> {
+        // most statistics are cached until the epoch is advanced
+        self.epoch.advance()?;
+
+        let allocated = self.allocated_mib.read()?;
+        let active = self.active_mib.read()?;
+
+        self.allocated.set(allocated as i64);
+        self.active.set(active as i64);
+        self.resident.set(self.resident_mib.read()? as i64);
+        self.mapped.set(self.mapped_mib.read()? as i64);
+        self.metadata.set(self.metadata_mib.read()? as i64);
+        self.retained.set(self.retained_mib.read()? as i64);
+
+        if active > 0 {
+            let fragmentation = active.saturating_sub(allocated) as f64 / active as f64;
+            self.fragmentation.set(fragmentation);
+        }
+
+        Ok(())
+    }
+
+    fn collectors(&self) -> Vec<&dyn Collector> {
+        vec![
+            &self.allocated,
+            &self.active,
+            &self.resident,
+            &self.mapped,
+            &self.metadata,
+            &self.retained,
+            &self.fragmentation,
+        ]
+    }
+}
+
+impl Collector for JemallocCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.collectors()
+            .into_iter()
+            .flat_map(|collector| collector.desc())
+            .collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        if let Err(err) = self.refresh() {
+            warn!("Jemalloc stats not gathered: {}", err);
+            return vec![];
+        }
+
+        self.collectors()
+            .into_iter()
+            .flat_map(|collector| collector.collect())
+            .collect()
+    }
+}
+
+fn gauge(name: &str, help: &str) -> IntGauge {
+    IntGauge::new(name, help).expect("Can't create stats metrics")
+}
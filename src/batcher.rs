@@ -0,0 +1,308 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, HistogramVec,
+    IntCounterVec, IntGauge, IntGaugeVec,
+};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    queue_depth_vec: IntGaugeVec,
+    flush_duration_vec: HistogramVec,
+    flushes_vec: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            queue_depth_vec: register_int_gauge_vec!(
+                "batcher_queue_depth",
+                "Items pushed to a batcher and not flushed yet",
+                &["name"]
+            )
+            .expect("Can't create stats metrics"),
+            flush_duration_vec: register_histogram_vec!(
+                "batcher_flush_duration",
+                "Batcher flush callback duration",
+                &["name"]
+            )
+            .expect("Can't create stats metrics"),
+            flushes_vec: register_int_counter_vec!(
+                "batcher_flushes_total",
+                "Batcher flushes by the limit which triggered them",
+                &["name", "reason"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Accumulates items and flushes them with a callback once the batch reaches
+/// `max_items`, `max_size` bytes or its oldest item is `max_age` old.
+///
+/// Items pushed before `shutdown` are always flushed, the callback is never called
+/// concurrently and never with an empty batch.
+///
+/// ```ignore
+/// let batcher = Batcher::new("events", move |batch: Vec<Event>| {
+///     let db = db.clone();
+///     async move {
+///         if let Err(err) = insert_events(&db, batch).await {
+///             error!("Failed to insert events: {}", err);
+///         }
+///     }
+/// })
+/// .with_max_items(500)
+/// .spawn();
+///
+/// batcher.sender().push(event).await;
+/// ```
+pub struct Batcher<T, F> {
+    name: String,
+    flush: F,
+    max_items: usize,
+    max_age: Duration,
+    max_size: Option<SizeLimit<T>>,
+    capacity: usize,
+}
+
+impl<T, F, Fut> Batcher<T, F>
+where
+    T: Send + 'static,
+    F: FnMut(Vec<T>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    /// Create new batcher flushing every 100 items or every second
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - value of the `name` label of the batcher metrics
+    /// * `flush` - callback receiving the batch, it should handle its own errors
+    pub fn new(name: impl Into<String>, flush: F) -> Self {
+        Self {
+            name: name.into(),
+            flush,
+            max_items: 100,
+            max_age: Duration::from_secs(1),
+            max_size: None,
+            capacity: 10_000,
+        }
+    }
+
+    pub fn with_max_items(mut self, max_items: usize) -> Self {
+        self.max_items = max_items.max(1);
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Also flush once the total size of the batch reaches `max_size` as measured by `size_of`
+    pub fn with_max_size(mut self, max_size: usize, size_of: fn(&T) -> usize) -> Self {
+        self.max_size = Some((max_size, size_of));
+        self
+    }
+
+    /// Number of pushed items waiting for the batch, `push` waits when it's exceeded
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Start batching in a separate tokio task
+    pub fn spawn(self) -> BatcherHandle<T> {
+        let (tx, rx) = mpsc::channel(self.capacity);
+        let (closer, closed) = oneshot::channel::<()>();
+        let queue_depth = METRICS.queue_depth_vec.with_label_values(&[&self.name]);
+
+        let sender = BatchSender {
+            tx,
+            queue_depth: queue_depth.clone(),
+        };
+        let join_handle = tokio::task::spawn(self.run(rx, closed, queue_depth));
+
+        BatcherHandle {
+            sender,
+            join_handle,
+            closer,
+        }
+    }
+
+    async fn run(
+        mut self,
+        mut rx: mpsc::Receiver<T>,
+        mut closed: oneshot::Receiver<()>,
+        queue_depth: IntGauge,
+    ) {
+        let mut batch = Batch::new(self.max_size);
+
+        loop {
+            // ages too long to add, like `Duration::MAX`, never flush by age
+            let deadline = batch
+                .started
+                .and_then(|started| started.checked_add(self.max_age));
+            let expired = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                    None => futures::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                item = rx.recv() => match item {
+                    Some(item) => {
+                        batch.push(item);
+                        if batch.items.len() >= self.max_items {
+                            self.flush(&mut batch, "items", &queue_depth).await;
+                        } else if batch.is_oversized() {
+                            self.flush(&mut batch, "size", &queue_depth).await;
+                        }
+                    }
+                    // every sender is dropped
+                    None => break,
+                },
+                _ = expired => {
+                    self.flush(&mut batch, "age", &queue_depth).await;
+                }
+                _ = &mut closed => {
+                    // flush items already pushed, new pushes are rejected
+                    rx.close();
+                    while let Some(item) = rx.recv().await {
+                        batch.push(item);
+                        if batch.items.len() >= self.max_items || batch.is_oversized() {
+                            self.flush(&mut batch, "shutdown", &queue_depth).await;
+                        }
+                    }
+                    break;
+                }
+            }
+        }
+
+        self.flush(&mut batch, "shutdown", &queue_depth).await;
+    }
+
+    async fn flush(&mut self, batch: &mut Batch<T>, reason: &str, queue_depth: &IntGauge) {
+        if batch.items.is_empty() {
+            return;
+        }
+
+        let items = batch.take();
+        let len = items.len() as i64;
+        let started = Instant::now();
+        (self.flush)(items).await;
+
+        queue_depth.sub(len);
+        METRICS
+            .flush_duration_vec
+            .with_label_values(&[&self.name])
+            .observe(started.elapsed().as_secs_f64());
+        METRICS
+            .flushes_vec
+            .with_label_values(&[&self.name, reason])
+            .inc();
+    }
+}
+
+/// Maximum batch size and the item size function
+type SizeLimit<T> = (usize, fn(&T) -> usize);
+
+struct Batch<T> {
+    items: Vec<T>,
+    started: Option<Instant>,
+    size: usize,
+    max_size: Option<SizeLimit<T>>,
+}
+
+impl<T> Batch<T> {
+    fn new(max_size: Option<SizeLimit<T>>) -> Self {
+        Self {
+            items: vec![],
+            started: None,
+            size: 0,
+            max_size,
+        }
+    }
+
+    fn push(&mut self, item: T) {
+        if let Some((_, size_of)) = self.max_size {
+            self.size += size_of(&item);
+        }
+        self.started.get_or_insert_with(Instant::now);
+        self.items.push(item);
+    }
+
+    fn is_oversized(&self) -> bool {
+        matches!(self.max_size, Some((max_size, _)) if self.size >= max_size)
+    }
+
+    fn take(&mut self) -> Vec<T> {
+        self.started = None;
+        self.size = 0;
+        std::mem::take(&mut self.items)
+    }
+}
+
+/// Pushes items to a running `Batcher`
+pub struct BatchSender<T> {
+    tx: mpsc::Sender<T>,
+    queue_depth: IntGauge,
+}
+
+impl<T> Clone for BatchSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            queue_depth: self.queue_depth.clone(),
+        }
+    }
+}
+
+impl<T> BatchSender<T> {
+    /// Push an item, waits while the batcher is at capacity.
+    /// The item is returned back if the batcher is shut down.
+    pub async fn push(&self, item: T) -> Result<(), T> {
+        self.queue_depth.inc();
+        self.tx.send(item).await.map_err(|err| {
+            self.queue_depth.dec();
+            err.0
+        })
+    }
+}
+
+/// Handle of a running `Batcher`
+pub struct BatcherHandle<T> {
+    sender: BatchSender<T>,
+    join_handle: JoinHandle<()>,
+    closer: oneshot::Sender<()>,
+}
+
+impl<T> BatcherHandle<T> {
+    pub fn sender(&self) -> BatchSender<T> {
+        self.sender.clone()
+    }
+
+    /// Flush pushed items and stop the batcher
+    pub async fn shutdown(self) {
+        let _ = self.closer.send(());
+
+        match tokio::time::timeout(Duration::from_secs(10), self.join_handle).await {
+            Err(e) => {
+                error!("Batcher timed out during shutdown, error = {:?}", e);
+            }
+            Ok(Err(e)) => {
+                error!("Batcher failed during shutdown, error = {:?}", e);
+            }
+            Ok(Ok(())) => {
+                info!("Batcher successfully exited");
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Criterion harness measuring per-request overhead of middleware stacks.
+//!
+//! Every stack wraps the same trivial handler, so the difference from the `baseline`
+//! benchmark is the overhead of the layers. In `benches/stack.rs` of a service with
+//! `harness = false`:
+//!
+//! ```ignore
+//! use svc_utils::bench::{criterion::Criterion, handler_router, StackBench};
+//!
+//! fn stacks(c: &mut Criterion) {
+//!     let mut bench = StackBench::new(c, "stack");
+//!     bench.bench("log", handler_router().layer(LogLayer::new()));
+//!     bench.bench("custom", handler_router().layer(my_stack()));
+//!     bench.finish();
+//! }
+//!
+//! svc_utils::bench::criterion::criterion_group!(benches, stacks);
+//! svc_utils::bench::criterion::criterion_main!(benches);
+//! ```
+//!
+//! The crate's own layers are measured by `cargo bench --all-features`.
+
+use axum::body::Body;
+use axum::{routing::get, Router};
+use criterion::measurement::WallTime;
+use criterion::{BenchmarkGroup, Criterion};
+use http::Request;
+use tokio::runtime::Runtime;
+use tower::ServiceExt;
+
+/// Criterion version the harness is built with, so benches don't depend on another one
+pub use criterion;
+
+/// Path served by `handler_router`
+pub const PATH: &str = "/bench";
+
+/// Router answering `GET /bench` with a short text body, the handler wrapped by stacks
+pub fn handler_router() -> Router {
+    Router::new().route(PATH, get(|| async { "ok" }))
+}
+
+/// Group of benchmarks sending the same request through different routers, starting
+/// with the bare `handler_router` as `baseline`
+pub struct StackBench<'a> {
+    group: BenchmarkGroup<'a, WallTime>,
+    runtime: Runtime,
+    make_request: Box<dyn Fn() -> Request<Body>>,
+    baseline: bool,
+}
+
+impl<'a> StackBench<'a> {
+    /// Create new group sending `GET /bench` requests
+    pub fn new(c: &'a mut Criterion, name: &str) -> Self {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build bench runtime");
+
+        Self {
+            group: c.benchmark_group(name),
+            runtime,
+            make_request: Box::new(|| {
+                Request::get(PATH)
+                    .body(Body::empty())
+                    .expect("bench request must be valid")
+            }),
+            baseline: false,
+        }
+    }
+
+    /// Replace the request, e.g. to add headers some layer expects
+    pub fn with_request<F>(mut self, make_request: F) -> Self
+    where
+        F: Fn() -> Request<Body> + 'static,
+    {
+        self.make_request = Box::new(make_request);
+        self
+    }
+
+    /// Measure a request through the router, including reading the response body
+    pub fn bench(&mut self, name: &str, router: Router) -> &mut Self {
+        if !self.baseline {
+            self.baseline = true;
+            self.bench("baseline", handler_router());
+        }
+
+        let make_request = &self.make_request;
+        let runtime = &self.runtime;
+        self.group.bench_function(name, |b| {
+            b.to_async(runtime).iter(|| {
+                let req = make_request();
+                let router = router.clone();
+                async move {
+                    let resp = match router.oneshot(req).await {
+                        Ok(resp) => resp,
+                        Err(infallible) => match infallible {},
+                    };
+                    axum::body::to_bytes(resp.into_body(), usize::MAX)
+                        .await
+                        .expect("failed to read bench response body")
+                }
+            })
+        });
+        self
+    }
+
+    pub fn finish(mut self) {
+        if !self.baseline {
+            self.bench("baseline", handler_router());
+        }
+        self.group.finish();
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, Histogram, HistogramVec, IntCounterVec,
+};
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    queue_wait_vec: HistogramVec,
+    duration_vec: HistogramVec,
+    deadline_exceeded_vec: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            queue_wait_vec: register_histogram_vec!(
+                "blocking_queue_wait",
+                "Time blocking tasks waited for a free slot of the pool",
+                &["name"]
+            )
+            .expect("Can't create stats metrics"),
+            duration_vec: register_histogram_vec!(
+                "blocking_duration",
+                "Blocking task duration",
+                &["name"]
+            )
+            .expect("Can't create stats metrics"),
+            deadline_exceeded_vec: register_int_counter_vec!(
+                "blocking_deadline_exceeded_total",
+                "Blocking tasks which missed the deadline while queued or running",
+                &["name", "phase"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Runs CPU-heavy work (report generation, image processing) with `spawn_blocking`,
+/// at most `max_concurrent` tasks at once so it can't take over the blocking thread pool.
+///
+/// ```ignore
+/// async fn report(Extension(pool): Extension<BlockingPool>, Extension(deadline): Extension<Deadline>) {
+///     let report = pool
+///         .run_until(deadline.get(), |cancellation| build_report(&cancellation))
+///         .await?;
+/// }
+/// ```
+#[derive(Clone)]
+pub struct BlockingPool {
+    semaphore: Arc<Semaphore>,
+    queue_wait: Histogram,
+    duration: Histogram,
+    name: String,
+}
+
+impl BlockingPool {
+    /// Create new pool
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - value of the `name` label of the pool metrics
+    /// * `max_concurrent` - number of tasks running at once, others wait in a queue
+    pub fn new(name: &str, max_concurrent: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent.max(1))),
+            queue_wait: METRICS.queue_wait_vec.with_label_values(&[name]),
+            duration: METRICS.duration_vec.with_label_values(&[name]),
+            name: name.to_owned(),
+        }
+    }
+
+    /// Run `f` once a slot of the pool is free
+    pub async fn run<F, R>(&self, f: F) -> Result<R, BlockingError>
+    where
+        F: FnOnce(Cancellation) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.run_(None, f).await
+    }
+
+    /// Run `f` once a slot of the pool is free, giving up when the `deadline` passes.
+    ///
+    /// A running task can't be aborted, it's signalled through `Cancellation` instead
+    /// and keeps its slot until it returns.
+    pub async fn run_until<F, R>(&self, deadline: Instant, f: F) -> Result<R, BlockingError>
+    where
+        F: FnOnce(Cancellation) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.run_(Some(deadline), f).await
+    }
+
+    async fn run_<F, R>(&self, deadline: Option<Instant>, f: F) -> Result<R, BlockingError>
+    where
+        F: FnOnce(Cancellation) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let queued = Instant::now();
+        let permit = match until(deadline, self.semaphore.clone().acquire_owned()).await {
+            Some(permit) => permit.expect("blocking pool semaphore is never closed"),
+            None => {
+                self.deadline_exceeded("queued");
+                return Err(BlockingError::DeadlineExceeded);
+            }
+        };
+        self.queue_wait.observe(queued.elapsed().as_secs_f64());
+
+        // cancel the task if the caller is dropped, e.g. by TimeoutLayer
+        let cancellation = CancelOnDrop(Cancellation::default());
+        let task_cancellation = cancellation.0.clone();
+        let duration = self.duration.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let started = Instant::now();
+            let res = f(task_cancellation);
+            duration.observe(started.elapsed().as_secs_f64());
+            res
+        });
+
+        match until(deadline, task).await {
+            Some(Ok(res)) => Ok(res),
+            Some(Err(_)) => Err(BlockingError::Panicked),
+            None => {
+                self.deadline_exceeded("running");
+                Err(BlockingError::DeadlineExceeded)
+            }
+        }
+    }
+
+    fn deadline_exceeded(&self, phase: &str) {
+        METRICS
+            .deadline_exceeded_vec
+            .with_label_values(&[&self.name, phase])
+            .inc();
+    }
+}
+
+async fn until<F: std::future::Future>(deadline: Option<Instant>, fut: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, fut).await.ok(),
+        None => Some(fut.await),
+    }
+}
+
+/// Signals a blocking task that its result is no longer awaited, long running tasks
+/// should check it periodically and bail out
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+}
+
+struct CancelOnDrop(Cancellation);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel()
+    }
+}
+
+#[derive(Debug)]
+pub enum BlockingError {
+    /// Deadline passed before the task was started or finished
+    DeadlineExceeded,
+    /// Task panicked
+    Panicked,
+}
+
+impl fmt::Display for BlockingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockingError::DeadlineExceeded => write!(f, "blocking task missed the deadline"),
+            BlockingError::Panicked => write!(f, "blocking task panicked"),
+        }
+    }
+}
+
+impl std::error::Error for BlockingError {}
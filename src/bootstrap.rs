@@ -0,0 +1,234 @@
+//! Startup steps (migrations, seeding, cache warmup) run by one replica at a time
+//! before the service is ready.
+//!
+//! ```ignore
+//! let bootstrap = Bootstrap::new(pool.clone());
+//! tokio::spawn(serve_probes(bootstrap.readiness_route()));
+//!
+//! bootstrap.migrate(&sqlx::migrate!()).await?;
+//! bootstrap.run("seed_roles", || seed_roles(&pool)).await?;
+//! bootstrap.finish();
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::{http::StatusCode, routing, Router};
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::pool::PoolConnection;
+use sqlx::{PgConnection, PgPool, Postgres};
+use tracing::{error, info, warn};
+
+/// Default key of the advisory lock, the same for every service
+const LOCK_KEY: i64 = 0x7376_635f_626f_6f74;
+
+#[derive(Debug)]
+pub enum BootstrapError {
+    /// Acquiring or releasing the lock failed
+    Lock(sqlx::Error),
+    Migrate(MigrateError),
+    /// A step returned an error
+    Step {
+        name: &'static str,
+        source: Box<dyn StdError + Send + Sync>,
+    },
+}
+
+impl fmt::Display for BootstrapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BootstrapError::Lock(err) => write!(f, "bootstrap lock failed: {}", err),
+            BootstrapError::Migrate(err) => write!(f, "migrations failed: {}", err),
+            BootstrapError::Step { name, source } => {
+                write!(f, "bootstrap step {} failed: {}", name, source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BootstrapError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            BootstrapError::Lock(err) => Some(err),
+            BootstrapError::Migrate(err) => Some(err),
+            BootstrapError::Step { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+/// Pool connection taking the advisory lock. It's closed instead of going back to the pool
+/// unless `release` unlocked it, e.g. when the step was cancelled or panicked, since the
+/// lock is held until the session ends.
+struct LockConn(Option<PoolConnection<Postgres>>);
+
+impl LockConn {
+    fn conn(&mut self) -> &mut PgConnection {
+        self.0
+            .as_mut()
+            .expect("connection is only taken on release")
+    }
+
+    async fn release(mut self, lock_key: i64) {
+        let result = sqlx::query("SELECT pg_advisory_unlock($1)")
+            .bind(lock_key)
+            .execute(self.conn())
+            .await;
+
+        match result {
+            // back to the pool
+            Ok(_) => drop(self.0.take()),
+            Err(err) => warn!("Failed to release bootstrap lock, error = {}", err),
+        }
+    }
+}
+
+impl Drop for LockConn {
+    fn drop(&mut self) {
+        if let Some(conn) = self.0.take() {
+            // closing the session releases the lock
+            drop(conn.detach());
+        }
+    }
+}
+
+/// Runs startup steps under a Postgres advisory lock so replicas starting together
+/// don't run them concurrently.
+///
+/// The lock is held by a pool connection for the duration of a step, so it's released
+/// when the process dies. The service stays not ready until `finish` is called.
+#[derive(Clone)]
+pub struct Bootstrap {
+    pool: PgPool,
+    lock_key: i64,
+    ready: Arc<AtomicBool>,
+}
+
+impl Bootstrap {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            lock_key: LOCK_KEY,
+            ready: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Key of the advisory lock, services sharing a database may set their own ones
+    /// to bootstrap independently
+    pub fn with_lock_key(mut self, lock_key: i64) -> Self {
+        self.lock_key = lock_key;
+        self
+    }
+
+    /// Apply pending migrations on the connection holding the lock
+    pub async fn migrate(&self, migrator: &Migrator) -> Result<(), BootstrapError> {
+        let name = "migrations";
+        let (mut conn, started) = self.lock(name).await?;
+        // not on another pool connection, which never comes with a pool of one
+        let result = migrator
+            .run(conn.conn())
+            .await
+            .map_err(BootstrapError::Migrate);
+        self.unlock(conn, name, started, result).await
+    }
+
+    /// Run a step, e.g. seeding reference data.
+    ///
+    /// The lock holds a pool connection while the step runs, so steps querying the pool
+    /// need it to have at least two connections.
+    pub async fn run<F, Fut, T, E>(&self, name: &'static str, step: F) -> Result<T, BootstrapError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        self.locked(name, || async {
+            step().await.map_err(|err| BootstrapError::Step {
+                name,
+                source: err.into(),
+            })
+        })
+        .await
+    }
+
+    async fn locked<F, Fut, T>(&self, name: &'static str, step: F) -> Result<T, BootstrapError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, BootstrapError>>,
+    {
+        let (conn, started) = self.lock(name).await?;
+        let result = step().await;
+        self.unlock(conn, name, started, result).await
+    }
+
+    async fn lock(&self, name: &'static str) -> Result<(LockConn, Instant), BootstrapError> {
+        let mut conn = LockConn(Some(
+            self.pool.acquire().await.map_err(BootstrapError::Lock)?,
+        ));
+
+        let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+            .bind(self.lock_key)
+            .fetch_one(conn.conn())
+            .await
+            .map_err(BootstrapError::Lock)?;
+        if !locked {
+            info!(
+                step = name,
+                "Waiting for another replica to finish bootstrap"
+            );
+            sqlx::query("SELECT pg_advisory_lock($1)")
+                .bind(self.lock_key)
+                .execute(conn.conn())
+                .await
+                .map_err(BootstrapError::Lock)?;
+        }
+
+        info!(step = name, "Bootstrap step started");
+        Ok((conn, Instant::now()))
+    }
+
+    async fn unlock<T>(
+        &self,
+        conn: LockConn,
+        name: &'static str,
+        started: Instant,
+        result: Result<T, BootstrapError>,
+    ) -> Result<T, BootstrapError> {
+        match &result {
+            Ok(_) => info!(step = name, elapsed = ?started.elapsed(), "Bootstrap step finished"),
+            Err(err) => error!(step = name, "Bootstrap step failed, error = {}", err),
+        }
+
+        conn.release(self.lock_key).await;
+
+        result
+    }
+
+    /// Mark the service as ready
+    pub fn finish(&self) {
+        self.ready.store(true, Ordering::Relaxed);
+        info!("Bootstrap finished");
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    /// Route answering `GET /readyz` with 503 until `finish` is called and 200 after
+    pub fn readiness_route(&self) -> Router {
+        let ready = self.ready.clone();
+        Router::new().route(
+            "/readyz",
+            routing::get(move || async move {
+                if ready.load(Ordering::Relaxed) {
+                    (StatusCode::OK, "ok")
+                } else {
+                    (StatusCode::SERVICE_UNAVAILABLE, "bootstrapping")
+                }
+            }),
+        )
+    }
+}
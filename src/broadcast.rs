@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_int_counter_vec, register_int_gauge_vec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec,
+};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    published: IntCounterVec,
+    lagged: IntCounterVec,
+    disconnected: IntCounterVec,
+    subscribers: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            published: register_int_counter_vec!(
+                "broadcast_published_total",
+                "Events published to the hub",
+                &["hub"]
+            )
+            .expect("Can't create stats metrics"),
+            lagged: register_int_counter_vec!(
+                "broadcast_lagged_total",
+                "Events skipped by subscribers that lagged behind",
+                &["hub"]
+            )
+            .expect("Can't create stats metrics"),
+            disconnected: register_int_counter_vec!(
+                "broadcast_slow_consumers_total",
+                "Subscribers disconnected for lagging behind",
+                &["hub"]
+            )
+            .expect("Can't create stats metrics"),
+            subscribers: register_int_gauge_vec!(
+                "broadcast_subscribers",
+                "Active hub subscriptions",
+                &["hub"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// What happens to a subscriber that fell more than `capacity` events behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowConsumer {
+    /// Skip missed events and continue with the oldest retained one
+    Skip,
+    /// End the subscription, `Subscription::recv` returns `None`
+    Disconnect,
+}
+
+struct Shared<T> {
+    name: String,
+    capacity: usize,
+    slow_consumer: SlowConsumer,
+    topics: Mutex<HashMap<String, broadcast::Sender<T>>>,
+    published: IntCounter,
+    lagged: IntCounter,
+    disconnected: IntCounter,
+    subscribers: IntGauge,
+}
+
+/// Topic-based in-process pub/sub hub for fanning events out to WebSocket/SSE connections.
+///
+/// Every topic is a bounded broadcast channel created on first subscription and removed
+/// when its last subscriber is dropped. Cloning is cheap, all clones share the same topics.
+pub struct Hub<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for Hub<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: Clone + Send + 'static> Hub<T> {
+    /// Create new hub
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - value of the `hub` label of the hub metrics
+    /// * `capacity` - number of events retained per topic for slow subscribers, at least 1
+    /// * `slow_consumer` - what to do with subscribers lagging more than `capacity` events
+    pub fn new(name: &str, capacity: usize, slow_consumer: SlowConsumer) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                name: name.to_owned(),
+                // broadcast channels panic without capacity
+                capacity: capacity.max(1),
+                slow_consumer,
+                topics: Mutex::new(HashMap::new()),
+                published: METRICS.published.with_label_values(&[name]),
+                lagged: METRICS.lagged.with_label_values(&[name]),
+                disconnected: METRICS.disconnected.with_label_values(&[name]),
+                subscribers: METRICS.subscribers.with_label_values(&[name]),
+            }),
+        }
+    }
+
+    /// Publish an event to the topic. Returns the number of subscribers it was delivered to.
+    pub fn publish(&self, topic: &str, event: T) -> usize {
+        self.shared.published.inc();
+
+        match self.lock().get(topic) {
+            Some(sender) => sender.send(event).unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    /// Subscribe to the topic
+    pub fn subscribe(&self, topic: &str) -> Subscription<T> {
+        let receiver = self
+            .lock()
+            .entry(topic.to_owned())
+            .or_insert_with(|| broadcast::channel(self.shared.capacity).0)
+            .subscribe();
+        self.shared.subscribers.inc();
+
+        Subscription {
+            topic: topic.to_owned(),
+            receiver,
+            shared: self.shared.clone(),
+            closed: false,
+        }
+    }
+
+    /// Value of the `hub` label of the hub metrics
+    pub fn name(&self) -> &str {
+        &self.shared.name
+    }
+
+    /// Number of topics with at least one subscriber
+    pub fn topics(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Number of subscribers of the topic
+    pub fn subscribers(&self, topic: &str) -> usize {
+        self.lock()
+            .get(topic)
+            .map(|sender| sender.receiver_count())
+            .unwrap_or(0)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, broadcast::Sender<T>>> {
+        lock(&self.shared)
+    }
+}
+
+/// Subscription to a hub topic, returned by `Hub::subscribe`
+pub struct Subscription<T> {
+    topic: String,
+    receiver: broadcast::Receiver<T>,
+    shared: Arc<Shared<T>>,
+    closed: bool,
+}
+
+impl<T: Clone> Subscription<T> {
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Receive the next event. Cancel safe.
+    ///
+    /// Returns `None` if the subscriber was disconnected as a slow consumer.
+    pub async fn recv(&mut self) -> Option<T> {
+        if self.closed {
+            return None;
+        }
+
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    self.shared.lagged.inc_by(skipped);
+
+                    if self.shared.slow_consumer == SlowConsumer::Disconnect {
+                        warn!(
+                            hub = %self.shared.name,
+                            topic = %self.topic,
+                            skipped,
+                            "Disconnecting slow consumer"
+                        );
+                        self.shared.disconnected.inc();
+                        self.closed = true;
+                        return None;
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        self.shared.subscribers.dec();
+
+        let mut topics = lock(&self.shared);
+        // receiver of this subscription is still alive here
+        let is_last = matches!(
+            topics.get(&self.topic),
+            Some(sender) if sender.receiver_count() <= 1
+        );
+        if is_last {
+            topics.remove(&self.topic);
+        }
+    }
+}
+
+fn lock<T>(shared: &Shared<T>) -> MutexGuard<'_, HashMap<String, broadcast::Sender<T>>> {
+    shared
+        .topics
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,247 @@
+//! Bounded tokio channels exporting their depth, blocked send durations and dropped
+//! messages under the `name` label.
+//!
+//! ```ignore
+//! let (tx, mut rx) = svc_utils::channel::mpsc::<Event>("events", 1000);
+//! ```
+
+use std::time::Instant;
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Histogram,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+};
+use tokio::sync::broadcast::error::{RecvError, SendError as BroadcastSendError};
+use tokio::sync::mpsc::error::{SendError, TryRecvError, TrySendError};
+use tokio::sync::{broadcast as tokio_broadcast, mpsc as tokio_mpsc, Semaphore};
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    depth_vec: IntGaugeVec,
+    send_wait_vec: HistogramVec,
+    dropped_vec: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            depth_vec: register_int_gauge_vec!(
+                "channel_depth",
+                "Messages sent to a channel and not received yet",
+                &["name"]
+            )
+            .expect("Can't create stats metrics"),
+            send_wait_vec: register_histogram_vec!(
+                "channel_send_wait",
+                "Time senders waited for a full channel",
+                &["name"]
+            )
+            .expect("Can't create stats metrics"),
+            dropped_vec: register_int_counter_vec!(
+                "channel_dropped_total",
+                "Messages dropped because a channel was full, closed or lagged behind",
+                &["name"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ChannelMetrics {
+    depth: IntGauge,
+    send_wait: Histogram,
+    dropped: IntCounter,
+}
+
+impl ChannelMetrics {
+    fn new(name: &str) -> Self {
+        Self {
+            depth: METRICS.depth_vec.with_label_values(&[name]),
+            send_wait: METRICS.send_wait_vec.with_label_values(&[name]),
+            dropped: METRICS.dropped_vec.with_label_values(&[name]),
+        }
+    }
+}
+
+/// Create bounded mpsc channel, see `tokio::sync::mpsc::channel`. Unlike tokio, a zero
+/// buffer is raised to 1 instead of panicking.
+pub fn mpsc<T>(name: &str, buffer: usize) -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = tokio_mpsc::channel(buffer.clamp(1, Semaphore::MAX_PERMITS));
+    let metrics = ChannelMetrics::new(name);
+
+    let tx = Sender {
+        tx,
+        metrics: metrics.clone(),
+    };
+    (tx, Receiver { rx, metrics })
+}
+
+pub struct Sender<T> {
+    tx: tokio_mpsc::Sender<T>,
+    metrics: ChannelMetrics,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Send a value, waiting for capacity when the channel is full
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        // counted before sending so a concurrent receive never takes depth below zero
+        self.metrics.depth.inc();
+        let res = match self.tx.try_send(value) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(value)) => {
+                let started = Instant::now();
+                let res = self.tx.send(value).await;
+                self.metrics
+                    .send_wait
+                    .observe(started.elapsed().as_secs_f64());
+                res
+            }
+            Err(TrySendError::Closed(value)) => Err(SendError(value)),
+        };
+
+        if res.is_err() {
+            self.metrics.depth.dec();
+            self.metrics.dropped.inc();
+        }
+        res
+    }
+
+    /// Send a value if there is capacity, values not sent are counted as dropped
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.metrics.depth.inc();
+        let res = self.tx.try_send(value);
+        if res.is_err() {
+            self.metrics.depth.dec();
+            self.metrics.dropped.inc();
+        }
+        res
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
+
+pub struct Receiver<T> {
+    rx: tokio_mpsc::Receiver<T>,
+    metrics: ChannelMetrics,
+}
+
+impl<T> Receiver<T> {
+    pub async fn recv(&mut self) -> Option<T> {
+        let value = self.rx.recv().await;
+        if value.is_some() {
+            self.metrics.depth.dec();
+        }
+        value
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let value = self.rx.try_recv();
+        if value.is_ok() {
+            self.metrics.depth.dec();
+        }
+        value
+    }
+
+    /// Stop accepting new values, the buffered ones can still be received
+    pub fn close(&mut self) {
+        self.rx.close()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // buffered values are lost with the receiver
+        let mut lost = 0;
+        self.rx.close();
+        while self.rx.try_recv().is_ok() {
+            lost += 1;
+        }
+        self.metrics.depth.sub(lost);
+        self.metrics.dropped.inc_by(lost as u64);
+    }
+}
+
+/// Create broadcast channel, see `tokio::sync::broadcast::channel`.
+///
+/// The depth is the number of values retained for the slowest receiver, updated on send.
+/// Unlike tokio, a zero capacity is raised to 1 instead of panicking.
+pub fn broadcast<T: Clone>(
+    name: &str,
+    capacity: usize,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    let (tx, rx) = tokio_broadcast::channel(capacity.clamp(1, usize::MAX / 2));
+    let metrics = ChannelMetrics::new(name);
+
+    let tx = BroadcastSender {
+        tx,
+        metrics: metrics.clone(),
+    };
+    (tx, BroadcastReceiver { rx, metrics })
+}
+
+pub struct BroadcastSender<T> {
+    tx: tokio_broadcast::Sender<T>,
+    metrics: ChannelMetrics,
+}
+
+impl<T> Clone for BroadcastSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+impl<T> BroadcastSender<T> {
+    /// Send a value to every receiver, values sent without receivers are counted as dropped
+    pub fn send(&self, value: T) -> Result<usize, BroadcastSendError<T>> {
+        let res = self.tx.send(value);
+        if res.is_err() {
+            self.metrics.dropped.inc();
+        }
+        self.metrics.depth.set(self.tx.len() as i64);
+        res
+    }
+
+    pub fn subscribe(&self) -> BroadcastReceiver<T> {
+        BroadcastReceiver {
+            rx: self.tx.subscribe(),
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.tx.receiver_count()
+    }
+}
+
+pub struct BroadcastReceiver<T> {
+    rx: tokio_broadcast::Receiver<T>,
+    metrics: ChannelMetrics,
+}
+
+impl<T: Clone> BroadcastReceiver<T> {
+    /// Receive the next value, values skipped by a lagging receiver are counted as dropped
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        let res = self.rx.recv().await;
+        if let Err(RecvError::Lagged(skipped)) = res {
+            self.metrics.dropped.inc_by(skipped);
+        }
+        res
+    }
+}
@@ -0,0 +1,199 @@
+//! Metrics of downstream HTTP calls, exported next to the inbound `request_duration` and
+//! `request_status_class_total`, so dashboards show outbound traffic the same way.
+//!
+//! ```ignore
+//! let client = Client::builder(TokioExecutor::new()).build_http::<Full<Bytes>>();
+//! let mut catalog = ServiceBuilder::new()
+//!     .layer(ClientMetricsLayer::new("catalog"))
+//!     .timeout(Duration::from_secs(5))
+//!     .service(client);
+//! ```
+
+use std::error::Error as StdError;
+use std::io;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use futures::future::BoxFuture;
+use http::{Request, Response};
+use once_cell::sync::Lazy;
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+use tower::{BoxError, Layer, Service};
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    duration_vec: HistogramVec,
+    class_vec: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            duration_vec: register_histogram_vec!(
+                "upstream_request_duration",
+                "Time from sending a downstream request to its response headers or error",
+                &["client", "method", "success"]
+            )
+            .expect("Can't create stats metrics"),
+            class_vec: register_int_counter_vec!(
+                "upstream_requests_total",
+                "Downstream requests by status class or error class",
+                &["client", "method", "class"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+// class of nonstandard statuses from 600 up
+const OTHER_STATUS: &str = "other";
+
+// label of requests without a host in `by_host` mode
+const UNKNOWN_HOST: &str = "unknown";
+
+#[derive(Clone)]
+enum Label {
+    Name(String),
+    Host,
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    label: Label,
+    service: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Middleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<BoxError>,
+    ReqBody: Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let client = match &self.label {
+            Label::Name(name) => name.clone(),
+            Label::Host => req.uri().host().unwrap_or(UNKNOWN_HOST).to_owned(),
+        };
+        let method = req.method().clone();
+
+        Box::pin(async move {
+            let started = Instant::now();
+            let res = inner.call(req).await.map_err(Into::into);
+            let elapsed = started.elapsed().as_secs_f64();
+
+            let (success, class) = match &res {
+                Ok(resp) => {
+                    let status = resp.status();
+                    // downstreams may answer any status up to 999
+                    let class = STATUS_CLASSES
+                        .get(usize::from(status.as_u16() / 100) - 1)
+                        .copied()
+                        .unwrap_or(OTHER_STATUS);
+                    let success = status.as_u16() < 400;
+                    (success, class)
+                }
+                Err(err) => (false, error_class(err.as_ref())),
+            };
+
+            METRICS
+                .duration_vec
+                .with_label_values(&[
+                    &client,
+                    method.as_str(),
+                    if success { "true" } else { "false" },
+                ])
+                .observe(elapsed);
+            METRICS
+                .class_vec
+                .with_label_values(&[&client, method.as_str(), class])
+                .inc();
+
+            res
+        })
+    }
+}
+
+/// `timeout`, `connect` or `error` by the first recognized error in the source chain
+fn error_class(err: &(dyn StdError + 'static)) -> &'static str {
+    let mut connect = false;
+    let mut source = Some(err);
+
+    while let Some(err) = source {
+        if err.is::<tokio::time::error::Elapsed>() || err.is::<tower::timeout::error::Elapsed>() {
+            return "timeout";
+        }
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            if err.kind() == io::ErrorKind::TimedOut {
+                return "timeout";
+            }
+        }
+        if let Some(err) = err.downcast_ref::<hyper_util::client::legacy::Error>() {
+            connect |= err.is_connect();
+        }
+        source = err.source();
+    }
+
+    if connect {
+        "connect"
+    } else {
+        "error"
+    }
+}
+
+/// Records the latency and outcome of every request of a downstream client, per dependency
+/// named explicitly or by the host of the request URI.
+///
+/// Latencies up to the response headers are observed in
+/// `upstream_request_duration{client,method,success}`, where 4xx and 5xx responses and
+/// errors are not successful, as for inbound requests, and so are nonstandard statuses
+/// from 600 up. Requests are counted in `upstream_requests_total{client,method,class}` by
+/// status class, from `1xx` to `5xx` or `other` over that, or by error class: `timeout`
+/// for tower and tokio timeouts and I/O timeouts, `connect` for failures to connect and
+/// `error` for the rest. Requests dropped unanswered aren't recorded, so timeouts go
+/// inside the layer. Errors of the inner service are boxed.
+#[derive(Clone)]
+pub struct ClientMetricsLayer {
+    label: Label,
+}
+
+impl ClientMetricsLayer {
+    /// Create new layer with `name` as the `client` label of the metrics
+    pub fn new(name: &str) -> Self {
+        Self {
+            label: Label::Name(name.to_owned()),
+        }
+    }
+
+    /// Create new layer with the host of the request URI as the `client` label, for clients
+    /// shared by a few downstream hosts
+    pub fn by_host() -> Self {
+        Self { label: Label::Host }
+    }
+}
+
+impl<S> Layer<S> for ClientMetricsLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            label: self.label.clone(),
+            service,
+        }
+    }
+}
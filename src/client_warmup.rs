@@ -0,0 +1,312 @@
+//! Warm-up of downstream connections, so the first requests after a deploy don't pay
+//! for DNS lookups and connection setup.
+//!
+//! ```ignore
+//! let warmup = ClientWarmup::new()
+//!     .with_host("http://catalog.svc.cluster.local/healthz".parse()?, 8)
+//!     .with_host("http://billing.svc.cluster.local/healthz".parse()?, 2);
+//!
+//! let connector = HttpConnector::new_with_resolver(warmup.resolver());
+//! let client = Client::builder(TokioExecutor::new()).build::<_, Empty<Bytes>>(connector);
+//! let warmup = warmup.spawn(client.clone()).await;
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future::{self, BoxFuture};
+use http::{Request, Uri};
+use http_body::Body as HttpBody;
+use http_body_util::BodyExt;
+use hyper_util::client::legacy::connect::dns::{GaiResolver, Name};
+use hyper_util::client::legacy::connect::Connect;
+use hyper_util::client::legacy::Client;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use tokio::time::MissedTickBehavior;
+use tokio::{sync::oneshot, task::JoinHandle};
+use tower::Service;
+use tracing::{error, info, warn};
+
+// rounds are cheap only while the connections are already open
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    resolved: IntCounterVec,
+    warmed: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            resolved: register_int_counter_vec!(
+                "client_warmup_resolutions_total",
+                "DNS resolutions of warmed up hosts by result",
+                &["host", "result"]
+            )
+            .expect("Can't create stats metrics"),
+            warmed: register_int_counter_vec!(
+                "client_warmup_requests_total",
+                "Warm-up requests to downstream hosts by result",
+                &["host", "result"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+type Addrs = Arc<RwLock<HashMap<String, Vec<SocketAddr>>>>;
+
+/// DNS resolver answering the warmed up hosts from addresses resolved in advance
+/// and resolving other hosts with `getaddrinfo` as usual. Pass it to
+/// `HttpConnector::new_with_resolver`.
+#[derive(Clone)]
+pub struct PreResolver {
+    addrs: Addrs,
+    fallback: GaiResolver,
+}
+
+impl Service<Name> for PreResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let cached = self
+            .addrs
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(name.as_str())
+            .cloned();
+
+        match cached {
+            Some(addrs) => Box::pin(future::ready(Ok(addrs.into_iter()))),
+            None => {
+                let fut = self.fallback.call(name);
+                Box::pin(async move { Ok(fut.await?.collect::<Vec<_>>().into_iter()) })
+            }
+        }
+    }
+}
+
+struct Host {
+    uri: Uri,
+    connections: usize,
+}
+
+/// Resolves the downstream hosts and opens connections to them at startup and then
+/// periodically, every 30 seconds by default.
+///
+/// Connections are opened by sending concurrent `GET` requests to the given URI of every
+/// host, a cheap endpoint like a healthcheck. They stay in the client pool as idle
+/// connections, so the interval must be shorter than the pool idle timeout, 90 seconds
+/// by default. A round is given up after 10 seconds by default, so an unresponsive host
+/// doesn't hold up startup or the next rounds. Failures are logged and counted in
+/// `client_warmup_resolutions_total` and `client_warmup_requests_total`, the previously
+/// resolved addresses are kept.
+pub struct ClientWarmup {
+    hosts: Vec<Host>,
+    interval: Duration,
+    timeout: Duration,
+    resolver: PreResolver,
+}
+
+impl Default for ClientWarmup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientWarmup {
+    pub fn new() -> Self {
+        Self {
+            hosts: vec![],
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+            resolver: PreResolver {
+                addrs: Arc::new(RwLock::new(HashMap::new())),
+                fallback: GaiResolver::new(),
+            },
+        }
+    }
+
+    /// Keep `connections` open to the host of `uri`, sending warm-up requests to `uri`
+    pub fn with_host(mut self, uri: Uri, connections: usize) -> Self {
+        self.hosts.push(Host { uri, connections });
+        self
+    }
+
+    /// Interval between warm-up rounds, at least a second
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(MIN_INTERVAL);
+        self
+    }
+
+    /// How long a round of resolving and warming up all hosts may take
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Resolver of the client connector answering the hosts from the resolved addresses
+    pub fn resolver(&self) -> PreResolver {
+        self.resolver.clone()
+    }
+
+    /// Warm up the client once and keep doing it in a separate tokio task
+    pub async fn spawn<C, B>(self, client: Client<C, B>) -> ClientWarmupHandle
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+        B: HttpBody + Default + Send + Unpin + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        self.run(&client).await;
+        info!(hosts = self.hosts.len(), "Downstream connections warmed up");
+
+        let (closer, mut rx) = oneshot::channel::<()>();
+
+        let join_handle = tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            interval.tick().await;
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = &mut rx => break,
+                }
+                tokio::select! {
+                    _ = self.run(&client) => {}
+                    _ = &mut rx => break,
+                }
+            }
+        });
+
+        ClientWarmupHandle {
+            join_handle,
+            closer,
+        }
+    }
+
+    /// Resolve every host and open its connections, giving up after the timeout
+    pub async fn run<C, B>(&self, client: &Client<C, B>)
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+        B: HttpBody + Default + Send + Unpin + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        let round = future::join_all(self.hosts.iter().map(|host| async move {
+            self.resolve(host).await;
+            self.warm_up(client, host).await;
+        }));
+
+        if tokio::time::timeout(self.timeout, round).await.is_err() {
+            warn!(timeout = ?self.timeout, "Downstream connections warm-up timed out");
+        }
+    }
+
+    async fn resolve(&self, host: &Host) {
+        let name = match host.uri.host() {
+            Some(name) => name,
+            None => return,
+        };
+        // IP addresses are connected to as is
+        if name.parse::<std::net::IpAddr>().is_ok() {
+            return;
+        }
+
+        let mut fallback = self.resolver.fallback.clone();
+        let resolved = match name.parse::<Name>() {
+            Ok(parsed) => fallback.call(parsed).await.map(Iterator::collect::<Vec<_>>),
+            Err(err) => Err(io::Error::new(io::ErrorKind::InvalidInput, err)),
+        };
+
+        match resolved {
+            Ok(addrs) if !addrs.is_empty() => {
+                METRICS.resolved.with_label_values(&[name, "ok"]).inc();
+                self.resolver
+                    .addrs
+                    .write()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(name.to_owned(), addrs);
+            }
+            Ok(_) => {
+                METRICS.resolved.with_label_values(&[name, "error"]).inc();
+                warn!(host = name, "Downstream host resolved to no addresses");
+            }
+            Err(err) => {
+                METRICS.resolved.with_label_values(&[name, "error"]).inc();
+                warn!(host = name, "Downstream host not resolved: {}", err);
+            }
+        }
+    }
+
+    async fn warm_up<C, B>(&self, client: &Client<C, B>, host: &Host)
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+        B: HttpBody + Default + Send + Unpin + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        let name = host.uri.host().unwrap_or_default();
+
+        // requests in flight at once can't share a connection, so each opens its own
+        let requests = (0..host.connections).map(|_| async {
+            let req = Request::get(host.uri.clone())
+                .body(B::default())
+                .expect("warm-up request must be valid");
+            let res = client.request(req).await?;
+            // drain the body so the connection goes back to the pool
+            let _ = res.into_body().collect().await;
+            Ok::<_, hyper_util::client::legacy::Error>(())
+        });
+
+        for res in future::join_all(requests).await {
+            match res {
+                Ok(()) => METRICS.warmed.with_label_values(&[name, "ok"]).inc(),
+                Err(err) => {
+                    METRICS.warmed.with_label_values(&[name, "error"]).inc();
+                    warn!(host = name, "Warm-up request failed: {}", err);
+                }
+            }
+        }
+    }
+}
+
+/// Handle of a running `ClientWarmup`
+pub struct ClientWarmupHandle {
+    join_handle: JoinHandle<()>,
+    closer: oneshot::Sender<()>,
+}
+
+impl ClientWarmupHandle {
+    /// Stop warming up
+    pub async fn shutdown(self) {
+        let _ = self.closer.send(());
+
+        match tokio::time::timeout(Duration::from_secs(10), self.join_handle).await {
+            Err(e) => {
+                error!("Client warm-up timed out during shutdown, error = {:?}", e);
+            }
+            Ok(Err(e)) => {
+                error!("Client warm-up failed during shutdown, error = {:?}", e);
+            }
+            Ok(Ok(())) => {
+                info!("Client warm-up successfully exited");
+            }
+        }
+    }
+}
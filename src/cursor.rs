@@ -0,0 +1,220 @@
+//! Opaque pagination cursors, so services don't expose raw offsets or keys and clients
+//! can't forge them.
+//!
+//! A cursor is the base64 of the JSON payload with its expiration, signed with
+//! HMAC-SHA256 together with a context, e.g. the route, so a cursor issued for one list
+//! isn't accepted by another. Decoding checks the signature and expiration:
+//!
+//! ```ignore
+//! let router = router.layer(Extension(Arc::new(CursorCodec::new(&config.cursor_key))));
+//!
+//! async fn list_rooms(
+//!     meta: Meta,
+//!     path: MatchedPath,
+//!     Extension(codec): Extension<Arc<CursorCodec>>,
+//!     Cursor(after): Cursor<RoomsAfter>,
+//! ) -> Result<Envelope<Vec<Room>>, AppError> {
+//!     let rooms = db::list_rooms(after).await?;
+//!     let next = rooms
+//!         .last()
+//!         .map(|room| codec.encode(path.as_str(), &RoomsAfter::from(room)))
+//!         .transpose()?;
+//!     Ok(Envelope::new(rooms).with_meta(meta.with_next_cursor(next)))
+//! }
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequestParts, MatchedPath},
+    http::{request::Parts, StatusCode},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Query parameter the `Cursor` extractor reads
+pub const CURSOR_PARAM: &str = "cursor";
+
+#[derive(Serialize, Deserialize)]
+struct Payload<T> {
+    exp: u64,
+    data: T,
+}
+
+#[derive(Debug)]
+pub enum CursorError {
+    /// Not a cursor issued by the codec
+    Malformed,
+    /// Signed with an unknown key or altered
+    InvalidSignature,
+    Expired,
+    /// Signed payload doesn't deserialize into the cursor type
+    Payload(serde_json::Error),
+}
+
+impl fmt::Display for CursorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CursorError::Malformed => write!(f, "malformed cursor"),
+            CursorError::InvalidSignature => write!(f, "invalid cursor signature"),
+            CursorError::Expired => write!(f, "cursor expired"),
+            CursorError::Payload(err) => write!(f, "invalid cursor payload: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CursorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CursorError::Payload(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Signs and verifies cursors. Should be added to the router as
+/// `Extension(Arc<CursorCodec>)` for the `Cursor` extractor.
+pub struct CursorCodec {
+    keys: Vec<Vec<u8>>,
+    ttl: Duration,
+}
+
+impl CursorCodec {
+    /// Create new codec with 1 day TTL signing with `key`, at least 32 random bytes
+    pub fn new(key: &[u8]) -> Self {
+        Self {
+            keys: vec![key.to_vec()],
+            ttl: Duration::from_secs(24 * 3600),
+        }
+    }
+
+    /// Also accept cursors signed with a previous key, for key rotation
+    pub fn with_previous_key(mut self, key: &[u8]) -> Self {
+        self.keys.push(key.to_vec());
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sign the cursor for `context`, it's only decoded with the same context
+    pub fn encode<T: Serialize>(
+        &self,
+        context: &str,
+        data: &T,
+    ) -> Result<String, serde_json::Error> {
+        let payload = Payload {
+            exp: unix_now().saturating_add(self.ttl.as_secs()),
+            data,
+        };
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&payload)?);
+        let signature = URL_SAFE_NO_PAD.encode(
+            mac(&self.keys[0], context, &payload)
+                .finalize()
+                .into_bytes(),
+        );
+
+        Ok(format!("{}.{}", payload, signature))
+    }
+
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        context: &str,
+        cursor: &str,
+    ) -> Result<T, CursorError> {
+        let (payload, signature) = cursor.split_once('.').ok_or(CursorError::Malformed)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| CursorError::Malformed)?;
+
+        let signed = self
+            .keys
+            .iter()
+            .any(|key| mac(key, context, payload).verify_slice(&signature).is_ok());
+        if !signed {
+            return Err(CursorError::InvalidSignature);
+        }
+
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| CursorError::Malformed)?;
+        let payload =
+            serde_json::from_slice::<Payload<T>>(&payload).map_err(CursorError::Payload)?;
+
+        if payload.exp <= unix_now() {
+            return Err(CursorError::Expired);
+        }
+        Ok(payload.data)
+    }
+}
+
+fn mac(key: &[u8], context: &str, payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any size");
+    // length-prefixed, so the context and the payload can't be shifted into each other
+    mac.update(&(context.len() as u64).to_be_bytes());
+    mac.update(context.as_bytes());
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Extracts the cursor from the `cursor` query parameter, `None` without one.
+///
+/// The context of the cursor is the matched route path, so handlers must encode the next
+/// cursor with `MatchedPath::as_str`. Needs `Extension<Arc<CursorCodec>>`. Answers
+/// 400 Bad Request to invalid or expired cursors and cursors of other routes.
+pub struct Cursor<T>(pub Option<T>);
+
+#[async_trait]
+impl<S, T> FromRequestParts<S> for Cursor<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        use axum::RequestPartsExt;
+        let Extension(codec) = parts
+            .extract::<Extension<Arc<CursorCodec>>>()
+            .await
+            .ok()
+            .ok_or((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "No cursor codec".to_owned(),
+            ))?;
+
+        let context = parts
+            .extensions
+            .get::<MatchedPath>()
+            .map(MatchedPath::as_str)
+            .unwrap_or_default();
+
+        let cursor = url::form_urlencoded::parse(parts.uri.query().unwrap_or("").as_bytes())
+            .find(|(key, _)| key == CURSOR_PARAM)
+            .map(|(_, val)| val);
+
+        match cursor {
+            Some(cursor) => codec
+                .decode(context, &cursor)
+                .map(|data| Self(Some(data)))
+                .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string())),
+            None => Ok(Self(None)),
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,197 @@
+//! Standard shape of API responses, so every service answers the same way and clients
+//! can be generated against one schema:
+//!
+//! ```json
+//! {
+//!   "data": [{"id": "..."}],
+//!   "meta": {"version": 1, "request_id": "...", "next_cursor": "..."},
+//!   "errors": []
+//! }
+//! ```
+//!
+//! ```ignore
+//! async fn list_rooms(meta: Meta, Query(query): Query<ListQuery>) -> Envelope<Vec<Room>> {
+//!     let (rooms, next) = db::list_rooms(&query).await;
+//!     Envelope::new(rooms).with_meta(meta.with_next_cursor(next))
+//! }
+//! ```
+
+use std::convert::Infallible;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderName, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+/// Version of the envelope schema, answered in `meta.version`
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Header with the request id set by the ingress
+pub static REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Response metadata. Extracting it from a request fills `request_id` from `X-Request-Id`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Meta {
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Cursor of the next page, missing on the last one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev_cursor: Option<String>,
+}
+
+impl Default for Meta {
+    fn default() -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            request_id: None,
+            next_cursor: None,
+            prev_cursor: None,
+        }
+    }
+}
+
+impl Meta {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_request_id(mut self, request_id: &str) -> Self {
+        self.request_id = Some(request_id.to_owned());
+        self
+    }
+
+    pub fn with_next_cursor<C: Into<String>>(mut self, cursor: Option<C>) -> Self {
+        self.next_cursor = cursor.map(Into::into);
+        self
+    }
+
+    pub fn with_prev_cursor<C: Into<String>>(mut self, cursor: Option<C>) -> Self {
+        self.prev_cursor = cursor.map(Into::into);
+        self
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Meta {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let meta = Meta::new();
+        let request_id = parts
+            .headers
+            .get(&REQUEST_ID)
+            .and_then(|value| value.to_str().ok());
+
+        Ok(match request_id {
+            Some(request_id) => meta.with_request_id(request_id),
+            None => meta,
+        })
+    }
+}
+
+/// Error in the envelope, same fields as `svc-error` and `AppError` bodies
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvelopeError {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Path of the invalid request field, e.g. `items[2].title`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl EnvelopeError {
+    pub fn new(kind: &str, title: &str) -> Self {
+        Self {
+            kind: kind.to_owned(),
+            title: title.to_owned(),
+            detail: None,
+            path: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_owned());
+        self
+    }
+
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_owned());
+        self
+    }
+}
+
+/// Response envelope answered as JSON, 200 OK by default.
+///
+/// Clients deserialize it too. `data` is missing on errors, `errors` is always present.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<T>,
+    #[serde(default)]
+    pub meta: Meta,
+    #[serde(default)]
+    pub errors: Vec<EnvelopeError>,
+    #[serde(skip, default = "ok")]
+    status: StatusCode,
+}
+
+fn ok() -> StatusCode {
+    StatusCode::OK
+}
+
+impl<T> Envelope<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            data: Some(data),
+            meta: Meta::default(),
+            errors: vec![],
+            status: StatusCode::OK,
+        }
+    }
+
+    /// Envelope without data answered with the error status
+    pub fn error(status: StatusCode, error: EnvelopeError) -> Self {
+        Self {
+            data: None,
+            meta: Meta::default(),
+            errors: vec![error],
+            status,
+        }
+    }
+
+    pub fn with_meta(mut self, meta: Meta) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    pub fn with_error(mut self, error: EnvelopeError) -> Self {
+        self.errors.push(error);
+        self
+    }
+
+    /// Answer another status, e.g. 201 Created
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+}
+
+impl<T: Serialize> IntoResponse for Envelope<T> {
+    fn into_response(self) -> Response {
+        (self.status, Json(self)).into_response()
+    }
+}
@@ -0,0 +1,261 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use axum::{
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use tracing::{error, info, warn};
+
+use super::catalog::ErrorBody;
+use super::{AsErrorKind, ErrorKind, Severity, ValidationErrors};
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    errors: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            errors: register_int_counter_vec!(
+                "error_total",
+                "Errors answered by kind",
+                &["kind", "severity"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+impl Severity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// Error answered to clients according to its kind.
+///
+/// Converting it into a response counts it in `error_total{kind,severity}` and logs it
+/// with the source error chain at the level of the kind severity. The response body
+/// has the `svc-error` shape: `{"type": ..., "title": ..., "detail": ...}`. Details
+/// of server errors are only logged unless set explicitly with `with_detail`.
+pub struct AppError {
+    kind: ErrorKind,
+    detail: Option<String>,
+    source: Option<Box<dyn StdError + Send + Sync>>,
+}
+
+impl AppError {
+    pub fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            detail: None,
+            source: None,
+        }
+    }
+
+    /// Detail reported to the client
+    pub fn with_detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_owned());
+        self
+    }
+
+    pub fn with_source<E>(mut self, source: E) -> Self
+    where
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    fn detail(&self) -> Option<String> {
+        match (&self.detail, &self.source) {
+            (Some(detail), _) => Some(detail.clone()),
+            (None, Some(source)) if !self.kind.status.is_server_error() => Some(source.to_string()),
+            _ => None,
+        }
+    }
+
+    fn log(&self) {
+        let source = self
+            .source
+            .as_ref()
+            .map(|source| Chain(source.as_ref()).to_string());
+        let source = source.as_deref();
+        let kind = self.kind.kind;
+        let status = self.kind.status.as_u16();
+
+        match self.kind.severity {
+            Severity::Info => info!(kind, status, error = source, "{}", self.kind.title),
+            Severity::Warning => warn!(kind, status, error = source, "{}", self.kind.title),
+            Severity::Error => error!(kind, status, error = source, "{}", self.kind.title),
+        }
+    }
+}
+
+impl fmt::Debug for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AppError")
+            .field("kind", &self.kind.kind)
+            .field("detail", &self.detail)
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{}: {}", self.kind.title, source),
+            None => write!(f, "{}", self.kind.title),
+        }
+    }
+}
+
+impl StdError for AppError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_ref()
+            .map(|source| source.as_ref() as &(dyn StdError + 'static))
+    }
+}
+
+impl<E> From<E> for AppError
+where
+    E: AsErrorKind + StdError + Send + Sync + 'static,
+{
+    fn from(err: E) -> Self {
+        AppError::new(err.error_kind()).with_source(err)
+    }
+}
+
+/// Answers 500 unless the error or its context is an `AppError` or an `ErrorKind`:
+///
+/// ```ignore
+/// let room = db::find_room(id).await.context(ErrorKind::NOT_FOUND)?;
+/// ```
+///
+/// The whole chain is logged. The detail of a found `AppError` is answered, otherwise only
+/// client errors answer the outermost message as the detail, as with other sources.
+#[cfg(feature = "app-errors-anyhow")]
+impl From<anyhow::Error> for AppError {
+    fn from(err: anyhow::Error) -> Self {
+        let app_error = err.downcast_ref::<AppError>();
+        let kind = app_error
+            .map(|app_error| app_error.kind)
+            .or_else(|| err.downcast_ref::<ErrorKind>().copied())
+            .unwrap_or(ErrorKind::INTERNAL);
+        let detail = app_error.and_then(|app_error| app_error.detail.clone());
+
+        AppError {
+            kind,
+            detail,
+            source: Some(err.into()),
+        }
+    }
+}
+
+/// Same as the `anyhow::Error` conversion
+#[cfg(feature = "app-errors-eyre")]
+impl From<eyre::Report> for AppError {
+    fn from(err: eyre::Report) -> Self {
+        let app_error = err.downcast_ref::<AppError>();
+        let kind = app_error
+            .map(|app_error| app_error.kind)
+            .or_else(|| err.downcast_ref::<ErrorKind>().copied())
+            .unwrap_or(ErrorKind::INTERNAL);
+        let detail = app_error.and_then(|app_error| app_error.detail.clone());
+
+        AppError {
+            kind,
+            detail,
+            source: Some(err.into()),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        METRICS
+            .errors
+            .with_label_values(&[self.kind.kind, self.kind.severity.as_str()])
+            .inc();
+        self.log();
+
+        let mut body = serde_json::json!({
+            "type": self.kind.kind,
+            "title": self.kind.title,
+        });
+        if let Some(detail) = self.detail() {
+            body["detail"] = detail.into();
+        }
+
+        let error = Extension(ErrorBody(body.clone()));
+        (self.kind.status, error, Json(body)).into_response()
+    }
+}
+
+impl IntoResponse for ValidationErrors {
+    fn into_response(self) -> Response {
+        let kind = ErrorKind::VALIDATION_FAILED;
+        METRICS
+            .errors
+            .with_label_values(&[kind.kind, kind.severity.as_str()])
+            .inc();
+        info!(kind = kind.kind, status = kind.status.as_u16(), "{}", self);
+
+        let body = serde_json::json!({
+            "type": kind.kind,
+            "title": kind.title,
+            "errors": self.errors,
+        });
+
+        let error = Extension(ErrorBody(body.clone()));
+        (kind.status, error, Json(body)).into_response()
+    }
+}
+
+/// Converts errors of any type into `AppError` of the kind:
+///
+/// ```ignore
+/// let room = db::find_room(id).await.error(ErrorKind::INTERNAL)?;
+/// ```
+pub trait ResultExt<T> {
+    fn error(self, kind: ErrorKind) -> Result<T, AppError>;
+}
+
+impl<T, E> ResultExt<T> for Result<T, E>
+where
+    E: Into<Box<dyn StdError + Send + Sync>>,
+{
+    fn error(self, kind: ErrorKind) -> Result<T, AppError> {
+        self.map_err(|err| AppError::new(kind).with_source(err))
+    }
+}
+
+/// Formats an error with its sources
+struct Chain<'a>(&'a (dyn StdError + 'static));
+
+impl fmt::Display for Chain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(err) = source {
+            write!(f, ": {}", err)?;
+            source = err.source();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::body::Body;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use http::{
+    header::{CONTENT_LANGUAGE, CONTENT_LENGTH},
+    HeaderValue, Request, Response,
+};
+use http_body::Body as HttpBody;
+use tower::{BoxError, Layer, Service};
+
+use crate::accept_language::accepted_languages;
+#[cfg(feature = "locale-extractor")]
+use crate::extractors::SupportedLocales;
+
+/// JSON body of an error response, kept in the response extensions so the body
+/// can be localized without parsing it back
+#[derive(Clone)]
+pub(crate) struct ErrorBody(pub(crate) serde_json::Value);
+
+/// Localized error messages keyed by language and error type.
+///
+/// ```ignore
+/// let catalog = MessageCatalog::new("ru")
+///     .with_message("ru", "room_closed", "Комната закрыта")
+///     .with_message("en", "room_closed", "The room is closed");
+/// ```
+#[derive(Debug, Clone)]
+pub struct MessageCatalog {
+    default_lang: String,
+    messages: HashMap<String, HashMap<String, String>>,
+}
+
+impl MessageCatalog {
+    /// Create new catalog, messages in `default_lang` are answered when the client
+    /// accepts none of the catalog languages
+    pub fn new(default_lang: &str) -> Self {
+        Self {
+            default_lang: default_lang.to_ascii_lowercase(),
+            messages: HashMap::new(),
+        }
+    }
+
+    pub fn with_message(mut self, lang: &str, kind: &str, message: &str) -> Self {
+        self.messages
+            .entry(lang.to_ascii_lowercase())
+            .or_default()
+            .insert(kind.to_owned(), message.to_owned());
+        self
+    }
+
+    /// Message of the error type in the most preferred of `langs` it's translated to,
+    /// tags like `ru-RU` fall back to the primary language `ru`
+    pub fn message<'a>(&'a self, kind: &str, langs: &[String]) -> Option<(&'a str, &'a str)> {
+        langs
+            .iter()
+            .flat_map(|lang| {
+                let primary = lang.split('-').next().filter(|primary| primary != lang);
+                std::iter::once(lang.as_str()).chain(primary)
+            })
+            .chain(std::iter::once(self.default_lang.as_str()))
+            .find_map(|lang| {
+                let (lang, messages) = self.messages.get_key_value(lang)?;
+                let message = messages.get(kind)?;
+                Some((lang.as_str(), message.as_str()))
+            })
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    catalog: Arc<MessageCatalog>,
+    #[cfg(feature = "locale-extractor")]
+    locales: Option<Arc<SupportedLocales>>,
+    service: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Middleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let langs = accepted_languages(req.headers());
+        #[cfg(feature = "locale-extractor")]
+        let langs = match &self.locales {
+            Some(locales) => vec![locales.negotiate(&langs).to_owned()],
+            None => langs,
+        };
+        let catalog = self.catalog.clone();
+        let fut = inner.call(req);
+
+        Box::pin(async move {
+            let (mut parts, body) = fut.await?.into_parts();
+
+            let localized =
+                parts
+                    .extensions
+                    .remove::<ErrorBody>()
+                    .and_then(|ErrorBody(mut error)| {
+                        let kind = error.get("type")?.as_str()?.to_owned();
+                        let (lang, message) = catalog.message(&kind, &langs)?;
+                        error["detail"] = message.into();
+                        Some((HeaderValue::from_str(lang).ok()?, error))
+                    });
+
+            match localized {
+                Some((lang, error)) => {
+                    parts.headers.insert(CONTENT_LANGUAGE, lang);
+                    parts.headers.remove(CONTENT_LENGTH);
+                    let body = serde_json::to_vec(&error).expect("json values serialize");
+                    Ok(Response::from_parts(parts, Body::from(body)))
+                }
+                None => Ok(Response::from_parts(parts, Body::new(body))),
+            }
+        })
+    }
+}
+
+/// Replaces `detail` of `AppError` and `ValidationErrors` responses with the message
+/// of their error type from the catalog, in the language negotiated with `Accept-Language`.
+///
+/// Responses of error types missing from the catalog are answered as is.
+#[derive(Clone)]
+pub struct LocalizedErrorsLayer {
+    catalog: Arc<MessageCatalog>,
+    #[cfg(feature = "locale-extractor")]
+    locales: Option<Arc<SupportedLocales>>,
+}
+
+impl LocalizedErrorsLayer {
+    pub fn new(catalog: MessageCatalog) -> Self {
+        Self {
+            catalog: Arc::new(catalog),
+            #[cfg(feature = "locale-extractor")]
+            locales: None,
+        }
+    }
+
+    /// Localize errors in the locale the `Locale` extractor negotiates, so errors and
+    /// the rest of the response use the same language
+    #[cfg(feature = "locale-extractor")]
+    pub fn with_locales(mut self, locales: Arc<SupportedLocales>) -> Self {
+        self.locales = Some(locales);
+        self
+    }
+}
+
+impl<S> Layer<S> for LocalizedErrorsLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            catalog: self.catalog.clone(),
+            #[cfg(feature = "locale-extractor")]
+            locales: self.locales.clone(),
+            service,
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "app-errors")]
+pub use app::{AppError, ResultExt};
+#[cfg(feature = "app-errors")]
+pub use catalog::{LocalizedErrorsLayer, MessageCatalog};
+
+#[cfg(feature = "app-errors")]
+mod app;
+#[cfg(feature = "app-errors")]
+mod catalog;
+
+/// How bad an error is, sets the level errors of the kind are logged with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Expected errors like missing entities, logged with info level
+    Info,
+    /// Client errors worth looking into, logged with warn level
+    Warning,
+    /// Failures of the service or its dependencies, logged with error level
+    Error,
+}
+
+/// Kind of an application error: the response status code, the error type string
+/// reported to clients and the severity.
+///
+/// Services declare their kinds as constants next to the common ones:
+///
+/// ```ignore
+/// const ROOM_CLOSED: ErrorKind = ErrorKind::new(
+///     "room_closed",
+///     "Room is closed",
+///     StatusCode::CONFLICT,
+///     Severity::Info,
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorKind {
+    kind: &'static str,
+    title: &'static str,
+    status: StatusCode,
+    severity: Severity,
+}
+
+impl ErrorKind {
+    pub const BAD_REQUEST: ErrorKind = ErrorKind::new(
+        "bad_request",
+        "Bad request",
+        StatusCode::BAD_REQUEST,
+        Severity::Info,
+    );
+    pub const UNAUTHORIZED: ErrorKind = ErrorKind::new(
+        "unauthorized",
+        "Unauthorized",
+        StatusCode::UNAUTHORIZED,
+        Severity::Info,
+    );
+    pub const FORBIDDEN: ErrorKind = ErrorKind::new(
+        "forbidden",
+        "Access denied",
+        StatusCode::FORBIDDEN,
+        Severity::Warning,
+    );
+    pub const NOT_FOUND: ErrorKind = ErrorKind::new(
+        "not_found",
+        "Not found",
+        StatusCode::NOT_FOUND,
+        Severity::Info,
+    );
+    pub const CONFLICT: ErrorKind =
+        ErrorKind::new("conflict", "Conflict", StatusCode::CONFLICT, Severity::Info);
+    pub const INTERNAL: ErrorKind = ErrorKind::new(
+        "internal_error",
+        "Internal error",
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Severity::Error,
+    );
+    pub const UNAVAILABLE: ErrorKind = ErrorKind::new(
+        "service_unavailable",
+        "Service unavailable",
+        StatusCode::SERVICE_UNAVAILABLE,
+        Severity::Error,
+    );
+    pub const VALIDATION_FAILED: ErrorKind = ErrorKind::new(
+        "validation_failed",
+        "Validation failed",
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Severity::Info,
+    );
+
+    pub const fn new(
+        kind: &'static str,
+        title: &'static str,
+        status: StatusCode,
+        severity: Severity,
+    ) -> Self {
+        Self {
+            kind,
+            title,
+            status,
+            severity,
+        }
+    }
+
+    /// Error type string, the `type` field of the response and the `kind` metric label
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    pub fn title(&self) -> &'static str {
+        self.title
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.title)
+    }
+}
+
+/// Error of a service error enum, maps its variants to kinds so `?` converts them
+/// into `AppError`
+pub trait AsErrorKind {
+    fn error_kind(&self) -> ErrorKind;
+}
+
+/// Invalid field of a request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    /// Path of the field, e.g. `items[2].title`, empty for errors of the whole request
+    pub path: String,
+    /// Machine readable code, e.g. `too_long`
+    pub code: String,
+    pub message: String,
+}
+
+/// Every validation error of a request, the single format validation errors are
+/// answered in.
+///
+/// Answers 422 with `{"type": "validation_failed", "title": ..., "errors": [...]}`,
+/// clients deserialize the body into `ValidationErrors` to get the field errors.
+/// Counted in `error_total` as `validation_failed`.
+///
+/// ```ignore
+/// let mut errors = ValidationErrors::new();
+/// if payload.title.len() > 256 {
+///     errors.add("title", "too_long", "Title is longer than 256 characters");
+/// }
+/// errors.into_result()?;
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationErrors {
+    errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, path: &str, code: &str, message: &str) {
+        self.errors.push(FieldError {
+            path: path.to_owned(),
+            code: code.to_owned(),
+            message: message.to_owned(),
+        });
+    }
+
+    /// Add errors of a nested object with their paths prefixed by `path`
+    pub fn merge(&mut self, path: &str, other: ValidationErrors) {
+        for mut error in other.errors {
+            error.path = match (path.is_empty(), error.path.is_empty()) {
+                (true, _) => error.path,
+                (false, true) => path.to_owned(),
+                (false, false) if error.path.starts_with('[') => {
+                    format!("{}{}", path, error.path)
+                }
+                (false, false) => format!("{}.{}", path, error.path),
+            };
+            self.errors.push(error);
+        }
+    }
+
+    pub fn errors(&self) -> &[FieldError] {
+        &self.errors
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// `Err` with the errors unless there are none
+    pub fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", ErrorKind::VALIDATION_FAILED.title)?;
+        for (i, error) in self.errors.iter().enumerate() {
+            let separator = if i == 0 { ": " } else { ", " };
+            if error.path.is_empty() {
+                write!(f, "{}{}", separator, error.code)?;
+            } else {
+                write!(f, "{}{} {}", separator, error.path, error.code)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StdError for ValidationErrors {}
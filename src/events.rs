@@ -0,0 +1,65 @@
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use serde::Serialize;
+use tracing::{info, warn};
+
+/// Tracing target of domain events, route it to the analytics sink with a target filter
+pub const TARGET: &str = "domain_events";
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    events: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            events: register_int_counter_vec!(
+                "domain_events_total",
+                "Emitted domain events",
+                &["name"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Domain event with a fixed name, e.g.
+///
+/// ```ignore
+/// #[derive(Serialize)]
+/// struct RoomClosed {
+///     room_id: Uuid,
+///     duration_secs: u64,
+/// }
+///
+/// impl DomainEvent for RoomClosed {
+///     const NAME: &'static str = "room_closed";
+/// }
+///
+/// events::emit(&RoomClosed { room_id, duration_secs });
+/// ```
+pub trait DomainEvent: Serialize {
+    const NAME: &'static str;
+}
+
+pub fn emit<E: DomainEvent>(event: &E) {
+    emit_event(E::NAME, event)
+}
+
+/// Write the event to the `domain_events` tracing target with its fields serialized
+/// as JSON in the `fields` field and count it in `domain_events_total{name}`.
+///
+/// The name is static to keep the metric cardinality bounded.
+pub fn emit_event<T: Serialize + ?Sized>(name: &'static str, fields: &T) {
+    METRICS.events.with_label_values(&[name]).inc();
+
+    match serde_json::to_string(fields) {
+        Ok(fields) => info!(target: TARGET, event = name, fields = %fields),
+        Err(err) => warn!(
+            event = name,
+            "Failed to serialize domain event fields: {}", err
+        ),
+    }
+}
@@ -1,17 +1,67 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
 
 use axum::{
     async_trait,
     extract::{Extension, FromRequestParts, Json},
-    http::{request::Parts, StatusCode},
+    http::{header::HeaderName, request::Parts, HeaderValue, Request, Response, StatusCode},
 };
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use serde::Deserialize;
 use svc_agent::{AccountId, AgentId};
 use svc_authn::{
     jose::ConfigMap as AuthnConfig, token::jws_compact::extract::decode_jws_compact_with_config,
 };
 use svc_error::Error;
+use tower::{Layer, Service};
 use tracing::{field, Span};
 
+static ANONYMOUS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "anonymous_requests_total",
+        "Anonymous requests by the anonymous access mode",
+        &["mode"]
+    )
+    .expect("Can't create stats metrics")
+});
+
+static DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+
+/// Whether `AccountIdExtractor` lets requests without a token in as `anonymous`,
+/// set with `AnonymousAccessLayer`. Deserializes from `allow`, `warn` or `deny`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnonymousAccess {
+    #[default]
+    Allow,
+    /// Allow, but answer with `Deprecation: true`
+    Warn,
+    /// Answer 401 Unauthorized
+    Deny,
+}
+
+impl AnonymousAccess {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AnonymousAccess::Allow => "allow",
+            AnonymousAccess::Warn => "warn",
+            AnonymousAccess::Deny => "deny",
+        }
+    }
+}
+
+// account already extracted for the request, e.g. by `QuotaLayer`, so the token is
+// decoded and anonymous access counted once
+#[derive(Clone)]
+struct Authenticated(AccountId);
+
+// set by the extractor for `AnonymousAccessLayer` to add the deprecation header
+#[derive(Clone)]
+struct AnonymousWarned(Arc<AtomicBool>);
+
 /// Extracts `AccountId` from "Authorization: Bearer ..." headers.
 pub struct AccountIdExtractor(pub AccountId);
 
@@ -21,6 +71,10 @@ impl<S: Send + Sync> FromRequestParts<S> for AccountIdExtractor {
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
         use axum::RequestPartsExt;
+        if let Some(Authenticated(account_id)) = parts.extensions.get() {
+            return Ok(Self(account_id.clone()));
+        }
+
         let Extension(authn) = parts
             .extract::<Extension<Arc<AuthnConfig>>>()
             .await
@@ -30,7 +84,7 @@ impl<S: Send + Sync> FromRequestParts<S> for AccountIdExtractor {
                 Json(Error::new(
                     "no_authn_config",
                     "No authn config",
-                    StatusCode::UNAUTHORIZED,
+                    http_02::StatusCode::UNAUTHORIZED,
                 )),
             ))?;
 
@@ -56,11 +110,38 @@ impl<S: Send + Sync> FromRequestParts<S> for AccountIdExtractor {
                         Json(Error::new(
                             "no_authentication_token",
                             "No application account id for anonymous access",
-                            StatusCode::UNAUTHORIZED,
+                            http_02::StatusCode::UNAUTHORIZED,
                         )),
                     ))?;
-                let audience = application_id.audience();
-                return Ok(Self(AccountId::new("anonymous", audience)));
+                let mode = parts
+                    .extensions
+                    .get::<AnonymousAccess>()
+                    .copied()
+                    .unwrap_or_default();
+                ANONYMOUS.with_label_values(&[mode.as_str()]).inc();
+
+                match mode {
+                    AnonymousAccess::Allow => {}
+                    AnonymousAccess::Warn => {
+                        if let Some(AnonymousWarned(warned)) = parts.extensions.get() {
+                            warned.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    AnonymousAccess::Deny => {
+                        return Err((
+                            StatusCode::UNAUTHORIZED,
+                            Json(Error::new(
+                                "anonymous_access_denied",
+                                "Anonymous access is not allowed",
+                                http_02::StatusCode::UNAUTHORIZED,
+                            )),
+                        ))
+                    }
+                }
+
+                let account_id = AccountId::new("anonymous", application_id.audience());
+                parts.extensions.insert(Authenticated(account_id.clone()));
+                return Ok(Self(account_id));
             }
         }
         .map_err(|e| {
@@ -70,7 +151,7 @@ impl<S: Send + Sync> FromRequestParts<S> for AccountIdExtractor {
                 Json(Error::new(
                     "invalid_authentication",
                     &err,
-                    StatusCode::UNAUTHORIZED,
+                    http_02::StatusCode::UNAUTHORIZED,
                 )),
             )
         })?
@@ -78,12 +159,85 @@ impl<S: Send + Sync> FromRequestParts<S> for AccountIdExtractor {
 
         let account_id = AccountId::new(claims.subject(), claims.audience());
 
-        Span::current().record("account_id", &field::display(&account_id));
+        Span::current().record("account_id", field::display(&account_id));
+        parts.extensions.insert(Authenticated(account_id.clone()));
 
         Ok(Self(account_id))
     }
 }
 
+#[derive(Clone)]
+pub struct Middleware<S> {
+    mode: AnonymousAccess,
+    service: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Middleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let warned = Arc::new(AtomicBool::new(false));
+        req.extensions_mut().insert(self.mode);
+        req.extensions_mut().insert(AnonymousWarned(warned.clone()));
+
+        Box::pin(async move {
+            let mut resp = inner.call(req).await?;
+            if warned.load(Ordering::Relaxed) {
+                resp.headers_mut()
+                    .insert(DEPRECATION.clone(), HeaderValue::from_static("true"));
+            }
+            Ok(resp)
+        })
+    }
+}
+
+/// Sets the anonymous access mode of `AccountIdExtractor` for staged rollouts
+/// of authentication: `warn` to measure who is still anonymous, then `deny`.
+///
+/// Anonymous requests are counted in `anonymous_requests_total{mode}` in every mode.
+/// Without the layer anonymous access is allowed.
+///
+/// ```ignore
+/// let router = router.layer(AnonymousAccessLayer::new(config.authn.anonymous));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct AnonymousAccessLayer {
+    mode: AnonymousAccess,
+}
+
+impl AnonymousAccessLayer {
+    pub fn new(mode: AnonymousAccess) -> Self {
+        Self { mode }
+    }
+}
+
+impl<S> Layer<S> for AnonymousAccessLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            mode: self.mode,
+            service,
+        }
+    }
+}
+
 /// Extracts `AgentId`. User should provide 2 headers to make this work:
 ///
 /// * "Authorization: Bearer <token>"
@@ -117,7 +271,7 @@ impl<S: Send + Sync> FromRequestParts<S> for AgentIdExtractor {
 
         let agent_id = AgentId::new(agent_label, account_id);
 
-        Span::current().record("agent_id", &field::display(&agent_id));
+        Span::current().record("agent_id", field::display(&agent_id));
 
         Ok(Self(agent_id))
     }
@@ -0,0 +1,203 @@
+#[cfg(feature = "client-info-extractor")]
+use std::collections::HashSet;
+#[cfg(feature = "client-info-extractor")]
+use std::convert::Infallible;
+#[cfg(feature = "client-info-extractor")]
+use std::sync::Mutex;
+
+#[cfg(feature = "client-info-extractor")]
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use http::{header::USER_AGENT, HeaderMap};
+#[cfg(feature = "client-info-extractor")]
+use once_cell::sync::Lazy;
+#[cfg(feature = "client-info-extractor")]
+use prometheus::{register_int_counter_vec, IntCounterVec};
+#[cfg(feature = "client-info-extractor")]
+use tracing::{field, Span};
+
+/// Header with the name of our app sending the request, e.g. `web` or `ios`
+pub const APP_LABEL: &str = "ulms-app-label";
+/// Header with the version of our app sending the request
+pub const APP_VERSION: &str = "ulms-app-version";
+
+const UNKNOWN: &str = "unknown";
+#[cfg(feature = "client-info-extractor")]
+const OTHER: &str = "other";
+
+// distinct (platform, app, version) label sets of `client_requests_total`
+#[cfg(feature = "client-info-extractor")]
+const MAX_LABEL_SETS: usize = 200;
+
+#[cfg(feature = "client-info-extractor")]
+static REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "client_requests_total",
+        "Requests by client platform, app and version",
+        &["platform", "app", "version"]
+    )
+    .expect("Can't create stats metrics")
+});
+
+#[cfg(feature = "client-info-extractor")]
+type LabelSet = (&'static str, String, String);
+
+#[cfg(feature = "client-info-extractor")]
+static LABEL_SETS: Lazy<Mutex<HashSet<LabelSet>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+// browser product tokens in the order they are checked, as Chrome based browsers
+// also send `Chrome/` and everything sends `Safari/`
+const BROWSERS: [(&str, &str); 6] = [
+    ("Edg/", "edge"),
+    ("OPR/", "opera"),
+    ("YaBrowser/", "yandex"),
+    ("Firefox/", "firefox"),
+    ("Chrome/", "chrome"),
+    ("Version/", "safari"),
+];
+
+const PLATFORMS: [(&str, &str); 8] = [
+    ("Android", "android"),
+    ("iPhone", "ios"),
+    ("iPad", "ios"),
+    ("iOS", "ios"),
+    ("Windows", "windows"),
+    ("Macintosh", "macos"),
+    ("CrOS", "chromeos"),
+    ("Linux", "linux"),
+];
+
+/// Client platform, app and version parsed from `User-Agent` and the `ulms-app-label`
+/// and `ulms-app-version` headers sent by our apps, which take precedence.
+///
+/// Browsers are recognized by their product tokens, `Mozilla/5.0 (...) Chrome/120.0.0.0
+/// Safari/537.36` is `chrome` 120.0.0.0. Other clients are named by their first product
+/// token, `okhttp/4.9.0` is `okhttp` 4.9.0. Missing parts are `unknown`.
+///
+/// With the `client-info-extractor` feature, extracting it records `platform/app/version`
+/// to the `client` field of the request span and counts the request in
+/// `client_requests_total{platform,app,version}`. The version label is cut to
+/// `major.minor`, and label sets over the first 200 are counted as `other` to bound the
+/// metric cardinality. The `client-info` feature alone builds for wasm32.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub platform: &'static str,
+    pub app: String,
+    pub version: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+
+        let mut info = Self::parse(header(USER_AGENT.as_str()).unwrap_or(""));
+        if let Some(label) = header(APP_LABEL) {
+            info.app = label.to_ascii_lowercase();
+            info.version = None;
+        }
+        if let Some(version) = header(APP_VERSION) {
+            info.version = Some(version.to_owned());
+        }
+        info
+    }
+
+    /// Parse `User-Agent` only
+    pub fn parse(user_agent: &str) -> Self {
+        let platform = PLATFORMS
+            .iter()
+            .find(|(token, _)| user_agent.contains(token))
+            .map(|(_, platform)| *platform)
+            .unwrap_or(UNKNOWN);
+
+        let product = user_agent.split_whitespace().next().unwrap_or("");
+        let (app, version) = if product.is_empty() || product.starts_with("Mozilla/") {
+            BROWSERS
+                .iter()
+                .find_map(|(token, app)| {
+                    let version = product_version(user_agent, token)?;
+                    Some(((*app).to_owned(), Some(version)))
+                })
+                .unwrap_or_else(|| (UNKNOWN.to_owned(), None))
+        } else {
+            let mut parts = product.splitn(2, '/');
+            let app = parts.next().unwrap_or(UNKNOWN).to_ascii_lowercase();
+            let version = parts.next().filter(|v| !v.is_empty()).map(str::to_owned);
+            (app, version)
+        };
+
+        Self {
+            platform,
+            app,
+            version,
+        }
+    }
+
+    /// `major.minor` of the version, or the whole version without a minor part
+    pub fn short_version(&self) -> Option<&str> {
+        let version = self.version.as_deref()?;
+        let end = version
+            .match_indices('.')
+            .nth(1)
+            .map(|(idx, _)| idx)
+            .unwrap_or(version.len());
+        Some(&version[..end])
+    }
+
+    #[cfg(feature = "client-info-extractor")]
+    /// Metric labels `[platform, app, version]`, `other` over the label set limit
+    pub fn labels(&self) -> [String; 3] {
+        let labels = (
+            self.platform,
+            self.app.clone(),
+            self.short_version().unwrap_or(UNKNOWN).to_owned(),
+        );
+
+        let mut seen = LABEL_SETS.lock().unwrap_or_else(|e| e.into_inner());
+        if seen.contains(&labels) || seen.len() < MAX_LABEL_SETS {
+            seen.insert(labels.clone());
+            [labels.0.to_owned(), labels.1, labels.2]
+        } else {
+            [OTHER.to_owned(), OTHER.to_owned(), OTHER.to_owned()]
+        }
+    }
+}
+
+fn product_version(user_agent: &str, token: &str) -> Option<String> {
+    let start = user_agent.find(token)? + token.len();
+    let version = user_agent[start..]
+        .split(|c: char| c.is_whitespace() || c == ';' || c == ')')
+        .next()?;
+    Some(version.to_owned()).filter(|version| !version.is_empty())
+}
+
+#[cfg(feature = "client-info-extractor")]
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let info = ClientInfo::from_headers(&parts.headers);
+
+        Span::current().record(
+            "client",
+            field::display(format_args!(
+                "{}/{}/{}",
+                info.platform,
+                info.app,
+                info.version.as_deref().unwrap_or(UNKNOWN)
+            )),
+        );
+
+        let labels = info.labels();
+        REQUESTS
+            .with_label_values(&[&labels[0], &labels[1], &labels[2]])
+            .inc();
+
+        Ok(info)
+    }
+}
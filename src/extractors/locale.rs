@@ -0,0 +1,114 @@
+use std::fmt;
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderMap, StatusCode},
+};
+use tracing::{field, Span};
+
+use crate::accept_language::accepted_languages;
+
+/// Locales supported by the service, shared with `Locale` as `Extension<Arc<SupportedLocales>>`.
+///
+/// ```ignore
+/// let locales = SupportedLocales::new("ru").with_locale("en").with_locale("pt-br");
+/// let router = router.layer(Extension(Arc::new(locales)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SupportedLocales {
+    default: String,
+    locales: Vec<String>,
+}
+
+impl SupportedLocales {
+    /// Create new list with `default` chosen when the client accepts none of the locales
+    pub fn new(default: &str) -> Self {
+        let default = default.to_ascii_lowercase();
+        Self {
+            locales: vec![default.clone()],
+            default,
+        }
+    }
+
+    pub fn with_locale(mut self, locale: &str) -> Self {
+        let locale = locale.to_ascii_lowercase();
+        if !self.locales.contains(&locale) {
+            self.locales.push(locale);
+        }
+        self
+    }
+
+    /// The supported locale matching the most preferred of the languages: exactly, by the
+    /// primary language of the accepted tag (`ru-RU` matches `ru`) or by the primary
+    /// language of the supported one (`pt` matches `pt-br`)
+    pub fn negotiate(&self, langs: &[String]) -> &str {
+        langs
+            .iter()
+            .find_map(|lang| {
+                let primary = primary_language(lang);
+                self.locales
+                    .iter()
+                    .find(|locale| *locale == lang)
+                    .or_else(|| self.locales.iter().find(|locale| *locale == primary))
+                    .or_else(|| {
+                        self.locales
+                            .iter()
+                            .find(|locale| primary_language(locale) == primary)
+                    })
+            })
+            .unwrap_or(&self.default)
+    }
+
+    /// Negotiate the locale with `Accept-Language` of the request
+    pub fn negotiate_headers(&self, headers: &HeaderMap) -> &str {
+        self.negotiate(&accepted_languages(headers))
+    }
+}
+
+fn primary_language(lang: &str) -> &str {
+    lang.split('-').next().unwrap_or(lang)
+}
+
+/// Extracts the supported locale negotiated with `Accept-Language`, lowercase.
+///
+/// Needs `Extension<Arc<SupportedLocales>>`. The locale is recorded to the `locale` field
+/// of the request span. Pass the same `SupportedLocales` to
+/// `LocalizedErrorsLayer::with_locales` to localize errors in the same locale.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locale(pub String);
+
+impl Locale {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Locale {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let locales = parts
+            .extensions
+            .get::<Arc<SupportedLocales>>()
+            .cloned()
+            .ok_or((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Missing request extension: Arc<SupportedLocales>",
+            ))?;
+
+        let locale = locales.negotiate_headers(&parts.headers).to_owned();
+
+        Span::current().record("locale", field::display(&locale));
+
+        Ok(Self(locale))
+    }
+}
@@ -1,5 +1,17 @@
 #[cfg(feature = "authn-extractor")]
-pub use authn::{AccountIdExtractor, AgentIdExtractor};
+pub use authn::{AccountIdExtractor, AgentIdExtractor, AnonymousAccess, AnonymousAccessLayer};
+
+#[cfg(feature = "client-info")]
+pub use client_info::{ClientInfo, APP_LABEL, APP_VERSION};
+
+#[cfg(feature = "locale-extractor")]
+pub use locale::{Locale, SupportedLocales};
 
 #[cfg(feature = "authn-extractor")]
 mod authn;
+
+#[cfg(feature = "client-info")]
+mod client_info;
+
+#[cfg(feature = "locale-extractor")]
+mod locale;
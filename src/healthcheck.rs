@@ -0,0 +1,135 @@
+//! Exec-style health probe for Docker `HEALTHCHECK` and orchestrators without HTTP probes.
+//!
+//! Runs the probe and exits when the binary is started with `--healthcheck`, so it must be
+//! called first thing in `main`, before the config is loaded and the runtime is started:
+//!
+//! ```ignore
+//! fn main() {
+//!     Healthcheck::new(([0, 0, 0, 0], 8080).into(), "/healthz").run_if_requested();
+//!     // ...
+//! }
+//! ```
+//!
+//! ```text
+//! HEALTHCHECK CMD ["/app/service", "--healthcheck"]
+//! ```
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+pub const FLAG: &str = "--healthcheck";
+
+#[derive(Debug)]
+pub enum HealthcheckError {
+    Io(io::Error),
+    /// The endpoint answered with a non 2xx status
+    Status(u16),
+    InvalidResponse,
+}
+
+impl fmt::Display for HealthcheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthcheckError::Io(err) => write!(f, "request failed: {}", err),
+            HealthcheckError::Status(status) => write!(f, "endpoint answered {}", status),
+            HealthcheckError::InvalidResponse => write!(f, "invalid HTTP response"),
+        }
+    }
+}
+
+impl std::error::Error for HealthcheckError {}
+
+impl From<io::Error> for HealthcheckError {
+    fn from(err: io::Error) -> Self {
+        HealthcheckError::Io(err)
+    }
+}
+
+/// `GET` request to the service's own readiness endpoint
+pub struct Healthcheck {
+    addr: SocketAddr,
+    path: String,
+    timeout: Duration,
+}
+
+impl Healthcheck {
+    /// Create new healthcheck of the endpoint at `path` of the server listening on `addr`
+    /// with a 5 seconds timeout. An unspecified address (`0.0.0.0`, `::`) is probed on
+    /// loopback.
+    pub fn new(addr: SocketAddr, path: &str) -> Self {
+        let mut addr = addr;
+        match addr.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+            IpAddr::V6(ip) if ip.is_unspecified() => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+            _ => {}
+        }
+
+        Self {
+            addr,
+            path: path.to_owned(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run the check and exit with 0 when healthy and 1 otherwise if the binary is
+    /// started with `--healthcheck`, do nothing otherwise
+    pub fn run_if_requested(&self) {
+        if !std::env::args().skip(1).any(|arg| arg == FLAG) {
+            return;
+        }
+
+        match self.check() {
+            Ok(()) => std::process::exit(0),
+            Err(err) => {
+                eprintln!("Healthcheck of {}{} failed: {}", self.addr, self.path, err);
+                std::process::exit(1)
+            }
+        }
+    }
+
+    /// Request the endpoint, succeeds on a 2xx status
+    pub fn check(&self) -> Result<(), HealthcheckError> {
+        let mut stream = TcpStream::connect_timeout(&self.addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            self.path, self.addr
+        )?;
+
+        // only the status line is needed
+        let mut buf = [0; 64];
+        let mut len = 0;
+        while len < buf.len() {
+            match stream.read(&mut buf[len..])? {
+                0 => break,
+                n => len += n,
+            }
+            if buf[..len].contains(&b'\n') {
+                break;
+            }
+        }
+
+        let status = std::str::from_utf8(&buf[..len])
+            .ok()
+            .and_then(|response| response.strip_prefix("HTTP/1."))
+            .and_then(|response| response.split(' ').nth(1))
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or(HealthcheckError::InvalidResponse)?;
+
+        if (200..300).contains(&status) {
+            Ok(())
+        } else {
+            Err(HealthcheckError::Status(status))
+        }
+    }
+}
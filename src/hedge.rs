@@ -0,0 +1,309 @@
+//! Hedged requests for idempotent downstream calls: when the request hasn't been answered
+//! within the recent latency percentile, a second attempt is sent and the first response
+//! wins, trading a few percent of extra load for the downstream tail latency.
+//!
+//! ```ignore
+//! let client = Client::builder(TokioExecutor::new()).build_http::<Full<Bytes>>();
+//! let mut catalog = ServiceBuilder::new()
+//!     .layer(HedgeLayer::new("catalog"))
+//!     .service(client);
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::future::{self, BoxFuture, Either};
+use http::{Method, Request};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, IntCounter, IntCounterVec};
+use tower::{Layer, Service};
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    fired: IntCounterVec,
+    wins: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            fired: register_int_counter_vec!(
+                "hedged_requests_total",
+                "Second attempts sent for requests slower than the latency percentile",
+                &["client"]
+            )
+            .expect("Can't create stats metrics"),
+            wins: register_int_counter_vec!(
+                "hedged_wins_total",
+                "Requests answered by the second attempt first",
+                &["client"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+// latencies of the last requests the percentile is computed over
+const WINDOW: usize = 1000;
+// requests between recomputations of the percentile
+const RECOMPUTE_EVERY: usize = 100;
+// second attempts that can be saved up during quiet periods and sent in a row
+const MAX_BALANCE: f64 = 10.0;
+
+struct Samples {
+    latencies: VecDeque<Duration>,
+    since_recompute: usize,
+    delay: Option<Duration>,
+}
+
+struct Latencies {
+    percentile: f64,
+    min_samples: usize,
+    samples: Mutex<Samples>,
+}
+
+impl Latencies {
+    fn record(&self, latency: Duration) {
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        if samples.latencies.len() == WINDOW {
+            samples.latencies.pop_front();
+        }
+        samples.latencies.push_back(latency);
+        samples.since_recompute += 1;
+
+        let warmed_up = samples.latencies.len() >= self.min_samples;
+        if warmed_up && (samples.delay.is_none() || samples.since_recompute >= RECOMPUTE_EVERY) {
+            let mut sorted = samples.latencies.iter().copied().collect::<Vec<_>>();
+            sorted.sort_unstable();
+            let idx = ((sorted.len() as f64 * self.percentile) as usize).min(sorted.len() - 1);
+            samples.delay = Some(sorted[idx]);
+            samples.since_recompute = 0;
+        }
+    }
+
+    /// Delay of the second attempt, `None` until there are enough samples
+    fn delay(&self) -> Option<Duration> {
+        self.samples.lock().unwrap_or_else(|e| e.into_inner()).delay
+    }
+}
+
+/// Share of requests allowed to be hedged, so a slow downstream doesn't get twice the load
+struct Budget {
+    ratio: f64,
+    balance: Mutex<f64>,
+}
+
+impl Budget {
+    fn deposit(&self) {
+        let mut balance = self.balance.lock().unwrap_or_else(|e| e.into_inner());
+        *balance = (*balance + self.ratio).min(MAX_BALANCE);
+    }
+
+    fn withdraw(&self) -> bool {
+        let mut balance = self.balance.lock().unwrap_or_else(|e| e.into_inner());
+        if *balance >= 1.0 {
+            *balance -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Records the latency of the first attempt when it's answered or dropped for the second
+/// one, so requests slow enough to be hedged aren't left out of the percentile
+struct Timer {
+    started: Instant,
+    latencies: Arc<Latencies>,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.latencies.record(self.started.elapsed());
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    latencies: Arc<Latencies>,
+    budget: Arc<Budget>,
+    fired: IntCounter,
+    wins: IntCounter,
+    service: S,
+}
+
+impl<S, B> Service<Request<B>> for Middleware<S>
+where
+    S: Service<Request<B>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Response: Send + 'static,
+    S::Error: Send + 'static,
+    B: Clone + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let hedge = match self.latencies.delay() {
+            Some(delay) if is_idempotent(req.method()) => {
+                Some((delay, clone_request(&req), self.service.clone()))
+            }
+            _ => None,
+        };
+
+        if is_idempotent(req.method()) {
+            self.budget.deposit();
+        }
+        let budget = self.budget.clone();
+        let fired = self.fired.clone();
+        let wins = self.wins.clone();
+
+        let primary = timed(inner.call(req), self.latencies.clone());
+        let (delay, req, mut hedged) = match hedge {
+            Some(hedge) => hedge,
+            None => return Box::pin(primary),
+        };
+
+        Box::pin(async move {
+            let sleep = tokio::time::sleep(delay);
+            futures::pin_mut!(primary, sleep);
+
+            let primary = match future::select(primary, sleep).await {
+                Either::Left((res, _)) => return res,
+                Either::Right((_, primary)) => primary,
+            };
+
+            if !budget.withdraw() {
+                return primary.await;
+            }
+
+            fired.inc();
+            let second = async move {
+                future::poll_fn(|cx| hedged.poll_ready(cx)).await?;
+                hedged.call(req).await
+            };
+            futures::pin_mut!(second);
+
+            match future::select(primary, second).await {
+                Either::Left((res, _)) => res,
+                Either::Right((Ok(res), _)) => {
+                    wins.inc();
+                    Ok(res)
+                }
+                // the second attempt failing leaves the first one to answer
+                Either::Right((Err(_), primary)) => primary.await,
+            }
+        })
+    }
+}
+
+async fn timed<F, T, E>(fut: F, latencies: Arc<Latencies>) -> Result<T, E>
+where
+    F: std::future::Future<Output = Result<T, E>>,
+{
+    let _timer = Timer {
+        started: Instant::now(),
+        latencies,
+    };
+    fut.await
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+fn clone_request<B: Clone>(req: &Request<B>) -> Request<B> {
+    let mut clone = Request::new(req.body().clone());
+    *clone.method_mut() = req.method().clone();
+    *clone.uri_mut() = req.uri().clone();
+    *clone.version_mut() = req.version();
+    *clone.headers_mut() = req.headers().clone();
+    *clone.extensions_mut() = req.extensions().clone();
+    clone
+}
+
+/// Hedges `GET`, `HEAD` and `OPTIONS` requests of a downstream client: if the request
+/// hasn't been answered within the latency percentile, 95th by default, a second attempt
+/// is sent and the first response is returned, the other attempt is dropped.
+///
+/// The percentile is computed over the latencies of the last 1000 first attempts, nothing
+/// is hedged until there are 100 of them. At most 10% of the requests are hedged by
+/// default, unused budget is saved up for no more than 10 second attempts in a row.
+/// Second attempts are counted in `hedged_requests_total{client}`, the ones answering
+/// first in `hedged_wins_total{client}`. Request bodies must be `Clone`, e.g.
+/// `Full<Bytes>` or `Empty<Bytes>`.
+#[derive(Clone)]
+pub struct HedgeLayer {
+    name: String,
+    percentile: f64,
+    min_samples: usize,
+    budget: f64,
+}
+
+impl HedgeLayer {
+    /// Create new layer with `name` as the `client` label of the metrics
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            percentile: 0.95,
+            min_samples: 100,
+            budget: 0.1,
+        }
+    }
+
+    /// Latency percentile in `0.0..1.0` after which the second attempt is sent
+    pub fn with_percentile(mut self, percentile: f64) -> Self {
+        self.percentile = percentile.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Number of attempts to measure before hedging starts, up to 1000
+    pub fn with_min_samples(mut self, min_samples: usize) -> Self {
+        self.min_samples = min_samples.clamp(1, WINDOW);
+        self
+    }
+
+    /// Share of the requests in `0.0..1.0` allowed to be hedged
+    pub fn with_budget(mut self, budget: f64) -> Self {
+        self.budget = budget.clamp(0.0, 1.0);
+        self
+    }
+}
+
+impl<S> Layer<S> for HedgeLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            latencies: Arc::new(Latencies {
+                percentile: self.percentile,
+                min_samples: self.min_samples,
+                samples: Mutex::new(Samples {
+                    latencies: VecDeque::with_capacity(WINDOW),
+                    since_recompute: 0,
+                    delay: None,
+                }),
+            }),
+            budget: Arc::new(Budget {
+                ratio: self.budget,
+                balance: Mutex::new(0.0),
+            }),
+            fired: METRICS.fired.with_label_values(&[&self.name]),
+            wins: METRICS.wins.with_label_values(&[&self.name]),
+            service,
+        }
+    }
+}
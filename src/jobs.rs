@@ -0,0 +1,469 @@
+//! Persistent background jobs stored in Postgres, with delays, retries and bounded
+//! concurrency.
+//!
+//! Create the table with `SCHEMA` in a migration first, then:
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct SendRecording {
+//!     room_id: Uuid,
+//! }
+//!
+//! impl Job for SendRecording {
+//!     const KIND: &'static str = "send_recording";
+//! }
+//!
+//! let queue = JobQueue::new(pool.clone());
+//! let worker = JobWorker::new(queue.clone())
+//!     .with_handler(move |job: SendRecording| {
+//!         let storage = storage.clone();
+//!         async move { send_recording(&storage, job.room_id).await }
+//!     })
+//!     .spawn();
+//!
+//! queue.enqueue_in(&SendRecording { room_id }, Duration::from_secs(60)).await?;
+//! // on shutdown
+//! worker.shutdown(Duration::from_secs(30)).await;
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, HistogramVec,
+    IntCounterVec, IntGaugeVec,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use tokio::sync::{oneshot, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Table of the jobs, apply it with the service migrations
+pub const SCHEMA: &str = "\
+CREATE TABLE IF NOT EXISTS svc_jobs (
+    id BIGSERIAL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    attempts INT NOT NULL DEFAULT 0,
+    run_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    locked_until TIMESTAMPTZ,
+    failed_at TIMESTAMPTZ,
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS svc_jobs_run_at_idx ON svc_jobs (run_at) WHERE failed_at IS NULL;
+";
+
+// longest delay before a retry, however many attempts there were
+const MAX_BACKOFF: Duration = Duration::from_secs(24 * 60 * 60);
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    jobs_vec: IntCounterVec,
+    duration_vec: HistogramVec,
+    in_flight_vec: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            jobs_vec: register_int_counter_vec!(
+                "job_queue_jobs_total",
+                "Job runs by result: success, retry, failed or lease_lost",
+                &["kind", "result"]
+            )
+            .expect("Can't create stats metrics"),
+            duration_vec: register_histogram_vec!(
+                "job_queue_job_duration",
+                "Job handler duration",
+                &["kind"]
+            )
+            .expect("Can't create stats metrics"),
+            in_flight_vec: register_int_gauge_vec!(
+                "job_queue_jobs_in_flight",
+                "Jobs being handled",
+                &["kind"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Job payload with a fixed kind, the kind selects the handler and labels the metrics
+pub trait Job: Serialize + DeserializeOwned + Send + 'static {
+    const KIND: &'static str;
+}
+
+#[derive(Debug)]
+pub enum JobQueueError {
+    Database(sqlx::Error),
+    Serialize(serde_json::Error),
+}
+
+impl fmt::Display for JobQueueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobQueueError::Database(err) => write!(f, "job queue query failed: {}", err),
+            JobQueueError::Serialize(err) => write!(f, "failed to serialize job: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for JobQueueError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            JobQueueError::Database(err) => Some(err),
+            JobQueueError::Serialize(err) => Some(err),
+        }
+    }
+}
+
+/// Enqueues jobs to the `svc_jobs` table
+#[derive(Clone)]
+pub struct JobQueue {
+    pool: PgPool,
+}
+
+impl JobQueue {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Enqueue the job to run as soon as possible, returns its id
+    pub async fn enqueue<J: Job>(&self, job: &J) -> Result<i64, JobQueueError> {
+        self.enqueue_in(job, Duration::ZERO).await
+    }
+
+    /// Enqueue the job to run after `delay`, returns its id
+    pub async fn enqueue_in<J: Job>(&self, job: &J, delay: Duration) -> Result<i64, JobQueueError> {
+        let payload = serde_json::to_value(job).map_err(JobQueueError::Serialize)?;
+
+        sqlx::query_scalar(
+            "INSERT INTO svc_jobs (kind, payload, run_at) \
+             VALUES ($1, $2, now() + make_interval(secs => $3)) \
+             RETURNING id",
+        )
+        .bind(J::KIND)
+        .bind(payload)
+        .bind(delay.as_secs_f64())
+        .fetch_one(&self.pool)
+        .await
+        .map_err(JobQueueError::Database)
+    }
+}
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+type Handler = Arc<dyn Fn(Value) -> BoxFuture<'static, Result<(), BoxError>> + Send + Sync>;
+
+/// Runs queued jobs with their handlers.
+///
+/// Jobs are claimed with `FOR UPDATE SKIP LOCKED`, so any number of replicas can run
+/// workers. A claimed job is leased: if the worker dies, the job is run again once the
+/// lease expires, so handlers must be idempotent and finish within the lease. The result of
+/// a run that outlived its lease is dropped, as the job was claimed again. Failed jobs
+/// are retried with exponential backoff, after the last attempt they're kept in the table
+/// with `failed_at` and `last_error` set.
+///
+/// Runs are counted in `job_queue_jobs_total{kind,result}`, timed in
+/// `job_queue_job_duration{kind}` and the running ones in `job_queue_jobs_in_flight{kind}`.
+pub struct JobWorker {
+    queue: JobQueue,
+    handlers: HashMap<&'static str, Handler>,
+    concurrency: usize,
+    poll_interval: Duration,
+    lease: Duration,
+    max_attempts: i32,
+    backoff: Duration,
+}
+
+impl JobWorker {
+    /// Create new worker running up to 10 jobs at once, polling every second,
+    /// leasing jobs for 5 minutes and making up to 5 attempts of each job
+    pub fn new(queue: JobQueue) -> Self {
+        Self {
+            queue,
+            handlers: HashMap::new(),
+            concurrency: 10,
+            poll_interval: Duration::from_secs(1),
+            lease: Duration::from_secs(300),
+            max_attempts: 5,
+            backoff: Duration::from_secs(10),
+        }
+    }
+
+    /// Handle jobs of the `J::KIND` kind, jobs of kinds without a handler are left
+    /// to other workers
+    pub fn with_handler<J, F, Fut, E>(mut self, handler: F) -> Self
+    where
+        J: Job,
+        F: Fn(J) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        let handler = Arc::new(handler);
+        let erased: Handler = Arc::new(move |payload| {
+            let handler = handler.clone();
+            Box::pin(async move {
+                let job = serde_json::from_value::<J>(payload)?;
+                handler(job).await.map_err(Into::into)
+            })
+        });
+        self.handlers.insert(J::KIND, erased);
+        self
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.clamp(1, Semaphore::MAX_PERMITS);
+        self
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn with_lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    /// Attempts of a job and the delay before the first retry, doubled for every next one
+    /// up to a day
+    pub fn with_retries(mut self, max_attempts: i32, backoff: Duration) -> Self {
+        self.max_attempts = max_attempts;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Start polling for jobs in a separate tokio task
+    pub fn spawn(self) -> JobWorkerHandle {
+        let (closer, mut closed) = oneshot::channel::<()>();
+        let kinds: Vec<String> = self.handlers.keys().map(|kind| kind.to_string()).collect();
+        let worker = Arc::new(self);
+
+        let join_handle = tokio::task::spawn(async move {
+            let in_flight = Arc::new(Semaphore::new(worker.concurrency));
+
+            loop {
+                let available = in_flight.available_permits();
+                let claimed = if available > 0 {
+                    match worker.claim(&kinds, available).await {
+                        Ok(jobs) => jobs,
+                        Err(err) => {
+                            error!("Failed to claim jobs, error = {}", err);
+                            Vec::new()
+                        }
+                    }
+                } else {
+                    Vec::new()
+                };
+
+                let claimed_all = available > 0 && claimed.len() == available;
+                for job in claimed {
+                    let permit = in_flight
+                        .clone()
+                        .try_acquire_owned()
+                        .expect("claimed no more jobs than permits available");
+                    let worker = worker.clone();
+                    tokio::task::spawn(async move {
+                        worker.run(job).await;
+                        drop(permit);
+                    });
+                }
+
+                // more jobs are likely ready, poll again once a permit is free
+                let wait = if claimed_all {
+                    Duration::ZERO
+                } else {
+                    worker.poll_interval
+                };
+
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {}
+                    _ = &mut closed => break,
+                }
+                if claimed_all {
+                    tokio::select! {
+                        permit = in_flight.acquire() => drop(permit),
+                        _ = &mut closed => break,
+                    }
+                }
+            }
+
+            // wait for the jobs in flight
+            let _ = in_flight.acquire_many(worker.concurrency as u32).await;
+        });
+
+        JobWorkerHandle {
+            join_handle,
+            closer,
+        }
+    }
+
+    async fn claim(&self, kinds: &[String], limit: usize) -> Result<Vec<Claimed>, sqlx::Error> {
+        let rows: Vec<(i64, String, Value, i32)> = sqlx::query_as(
+            "UPDATE svc_jobs \
+             SET locked_until = now() + make_interval(secs => $1), attempts = attempts + 1 \
+             WHERE id IN ( \
+                 SELECT id FROM svc_jobs \
+                 WHERE failed_at IS NULL AND run_at <= now() AND kind = ANY($2) \
+                     AND (locked_until IS NULL OR locked_until < now()) \
+                 ORDER BY run_at \
+                 LIMIT $3 \
+                 FOR UPDATE SKIP LOCKED \
+             ) \
+             RETURNING id, kind, payload, attempts",
+        )
+        .bind(self.lease.as_secs_f64())
+        .bind(kinds)
+        .bind(limit as i64)
+        .fetch_all(&self.queue.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(id, kind, payload, attempts)| Claimed {
+                id,
+                kind,
+                payload,
+                attempts,
+            })
+            .collect())
+    }
+
+    async fn run(&self, job: Claimed) {
+        let (kind, handler) = match self.handlers.get_key_value(job.kind.as_str()) {
+            Some((kind, handler)) => (*kind, handler.clone()),
+            None => return,
+        };
+
+        let in_flight = METRICS.in_flight_vec.with_label_values(&[kind]);
+        in_flight.inc();
+        let started = Instant::now();
+        let result = handler(job.payload).await;
+        METRICS
+            .duration_vec
+            .with_label_values(&[kind])
+            .observe(started.elapsed().as_secs_f64());
+        in_flight.dec();
+
+        let (outcome, query) = match &result {
+            Ok(()) => (
+                "success",
+                sqlx::query("DELETE FROM svc_jobs WHERE id = $1 AND attempts = $2")
+                    .bind(job.id)
+                    .bind(job.attempts),
+            ),
+            Err(err) if job.attempts < self.max_attempts => {
+                let delay = self
+                    .backoff
+                    .saturating_mul(2_u32.saturating_pow(job.attempts as u32 - 1))
+                    .min(MAX_BACKOFF);
+                warn!(
+                    job_id = job.id,
+                    kind,
+                    attempt = job.attempts,
+                    "Job failed, retrying in {:?}, error = {}",
+                    delay,
+                    err
+                );
+                (
+                    "retry",
+                    sqlx::query(
+                        "UPDATE svc_jobs \
+                         SET run_at = now() + make_interval(secs => $2), locked_until = NULL, \
+                             last_error = $3 \
+                         WHERE id = $1 AND attempts = $4",
+                    )
+                    .bind(job.id)
+                    .bind(delay.as_secs_f64())
+                    .bind(err.to_string())
+                    .bind(job.attempts),
+                )
+            }
+            Err(err) => {
+                error!(
+                    job_id = job.id,
+                    kind,
+                    attempts = job.attempts,
+                    "Job failed after retries, error = {}",
+                    err
+                );
+                (
+                    "failed",
+                    sqlx::query(
+                        "UPDATE svc_jobs \
+                         SET failed_at = now(), locked_until = NULL, last_error = $2 \
+                         WHERE id = $1 AND attempts = $3",
+                    )
+                    .bind(job.id)
+                    .bind(err.to_string())
+                    .bind(job.attempts),
+                )
+            }
+        };
+
+        // claims bump the attempts, so the job is still ours only if they're unchanged
+        let outcome = match query.execute(&self.queue.pool).await {
+            Ok(done) if done.rows_affected() == 0 => {
+                warn!(
+                    job_id = job.id,
+                    kind, "Job lease lost before the run finished, result dropped"
+                );
+                "lease_lost"
+            }
+            Ok(_) => outcome,
+            Err(err) => {
+                // the job runs again after the lease
+                error!(
+                    job_id = job.id,
+                    kind, "Failed to store job result, error = {}", err
+                );
+                outcome
+            }
+        };
+
+        METRICS.jobs_vec.with_label_values(&[kind, outcome]).inc();
+    }
+}
+
+struct Claimed {
+    id: i64,
+    kind: String,
+    payload: Value,
+    attempts: i32,
+}
+
+/// Handle of a running `JobWorker`
+pub struct JobWorkerHandle {
+    join_handle: JoinHandle<()>,
+    closer: oneshot::Sender<()>,
+}
+
+impl JobWorkerHandle {
+    /// Stop claiming jobs and wait up to `timeout` for the running ones
+    pub async fn shutdown(self, timeout: Duration) {
+        let _ = self.closer.send(());
+
+        match tokio::time::timeout(timeout, self.join_handle).await {
+            Err(e) => {
+                error!("Job worker timed out during shutdown, error = {:?}", e);
+            }
+            Ok(Err(e)) => {
+                error!("Job worker failed during shutdown, error = {:?}", e);
+            }
+            Ok(Ok(())) => {
+                info!("Job worker successfully exited");
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Instrumented Kafka producer.
+//!
+//! ```ignore
+//! let mut config = ClientConfig::new();
+//! config.set("bootstrap.servers", "kafka:9092");
+//! let producer = KafkaProducer::new(&config)?.with_max_in_flight(10_000);
+//!
+//! producer.send("analytics", Some(room_id.as_bytes()), &payload).await?;
+//! // on shutdown
+//! producer.shutdown(Duration::from_secs(10)).await;
+//! ```
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, HistogramVec,
+    IntCounterVec, IntGauge,
+};
+use rdkafka::error::{KafkaError, KafkaResult};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rdkafka::ClientConfig;
+use tokio::sync::Semaphore;
+use tracing::{error, info};
+
+pub use rdkafka;
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    produced_vec: IntCounterVec,
+    duration_vec: HistogramVec,
+    in_flight: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            produced_vec: register_int_counter_vec!(
+                "kafka_produce_total",
+                "Messages sent to Kafka by delivery result",
+                &["topic", "result"]
+            )
+            .expect("Can't create stats metrics"),
+            duration_vec: register_histogram_vec!(
+                "kafka_produce_duration",
+                "Time from sending a message to Kafka to its delivery report",
+                &["topic"]
+            )
+            .expect("Can't create stats metrics"),
+            in_flight: register_int_gauge!(
+                "kafka_produce_in_flight",
+                "Messages sent to Kafka and not delivered yet"
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Kafka producer bounding messages in flight and reporting every delivery.
+///
+/// Deliveries are counted in `kafka_produce_total{topic,result}` with `success` or
+/// `failure` result and timed in `kafka_produce_duration{topic}`. Failed deliveries are
+/// logged, so lost messages don't go unnoticed even if the caller ignores the error.
+#[derive(Clone)]
+pub struct KafkaProducer {
+    producer: FutureProducer,
+    in_flight: Arc<Semaphore>,
+    queue_timeout: Duration,
+}
+
+impl KafkaProducer {
+    /// Create new producer with up to 1000 messages in flight and 5 seconds timeout
+    /// of the local producer queue
+    pub fn new(config: &ClientConfig) -> KafkaResult<Self> {
+        Ok(Self {
+            producer: config.create()?,
+            in_flight: Arc::new(Semaphore::new(1000)),
+            queue_timeout: Duration::from_secs(5),
+        })
+    }
+
+    /// Senders wait once `max_in_flight` messages aren't delivered yet, at least 1
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        // no permits would make every send wait forever
+        let max_in_flight = max_in_flight.clamp(1, Semaphore::MAX_PERMITS);
+        self.in_flight = Arc::new(Semaphore::new(max_in_flight));
+        self
+    }
+
+    /// How long to wait for space in the local producer queue when it's full
+    pub fn with_queue_timeout(mut self, timeout: Duration) -> Self {
+        self.queue_timeout = timeout;
+        self
+    }
+
+    /// Send the message and wait for its delivery report, returns the partition
+    /// and the offset of the delivered message
+    pub async fn send(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        payload: &[u8],
+    ) -> Result<(i32, i64), KafkaError> {
+        let _permit = self
+            .in_flight
+            .acquire()
+            .await
+            .expect("in flight semaphore is never closed");
+
+        let mut record = FutureRecord::<[u8], [u8]>::to(topic).payload(payload);
+        if let Some(key) = key {
+            record = record.key(key);
+        }
+
+        let started = Instant::now();
+        let in_flight = InFlight::new();
+        let result = self.producer.send(record, self.queue_timeout).await;
+        drop(in_flight);
+
+        METRICS
+            .duration_vec
+            .with_label_values(&[topic])
+            .observe(started.elapsed().as_secs_f64());
+
+        match result {
+            Ok(delivered) => {
+                METRICS
+                    .produced_vec
+                    .with_label_values(&[topic, "success"])
+                    .inc();
+                Ok(delivered)
+            }
+            Err((err, _message)) => {
+                METRICS
+                    .produced_vec
+                    .with_label_values(&[topic, "failure"])
+                    .inc();
+                error!(topic, "Failed to deliver message to Kafka, error = {}", err);
+                Err(err)
+            }
+        }
+    }
+
+    /// Underlying rdkafka producer, e.g. for transactions
+    pub fn producer(&self) -> &FutureProducer {
+        &self.producer
+    }
+
+    /// Wait until the messages in flight are delivered, call it on shutdown
+    /// so queued messages aren't lost
+    pub async fn shutdown(self, timeout: Duration) {
+        let producer = self.producer;
+        let flush = tokio::task::spawn_blocking(move || producer.flush(timeout));
+
+        match flush.await {
+            Err(e) => {
+                error!("Kafka producer failed during shutdown, error = {:?}", e);
+            }
+            Ok(Err(e)) => {
+                error!(
+                    "Kafka producer failed to deliver queued messages during shutdown, error = {}",
+                    e
+                );
+            }
+            Ok(Ok(())) => {
+                info!("Kafka producer successfully flushed");
+            }
+        }
+    }
+}
+
+/// Counts a message in flight until dropped, also when the send is cancelled
+struct InFlight;
+
+impl InFlight {
+    fn new() -> Self {
+        METRICS.in_flight.inc();
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        METRICS.in_flight.dec();
+    }
+}
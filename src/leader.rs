@@ -0,0 +1,187 @@
+//! Leader election over a Postgres advisory lock, so singleton background jobs
+//! (schedulers, outbox relays) run on one replica.
+//!
+//! ```ignore
+//! let mut leader = LeaderElection::new(pool.clone(), "outbox_relay").spawn();
+//!
+//! loop {
+//!     if leader.is_leader() {
+//!         relay.run_once().await?;
+//!     }
+//!     tokio::select! {
+//!         _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+//!         _ = leader.changed() => {}
+//!     }
+//! }
+//! ```
+
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
+use sqlx::{Connection, PgConnection, PgPool};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    leader_vec: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            leader_vec: register_int_gauge_vec!(
+                "leader_election_is_leader",
+                "1 on the instance holding the leadership, 0 on the others",
+                &["election"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Election of a single leader among replicas sharing a database.
+///
+/// The leader holds a session advisory lock on a dedicated connection, the others
+/// try to take it every interval. Leadership is lost as soon as the connection fails
+/// or a check doesn't complete within the interval, so a partitioned leader steps down
+/// within two intervals, dropping the connection to release the lock.
+pub struct LeaderElection {
+    pool: PgPool,
+    name: &'static str,
+    lock_key: i64,
+    interval: Duration,
+}
+
+impl LeaderElection {
+    /// Create new election checking the lock every 5 seconds, the lock key is derived
+    /// from `name`
+    pub fn new(pool: PgPool, name: &'static str) -> Self {
+        Self {
+            pool,
+            name,
+            lock_key: lock_key(name),
+            interval: Duration::from_secs(5),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Start campaigning in a separate tokio task, which stops and steps down once
+    /// every handle is dropped
+    pub fn spawn(self) -> LeaderHandle {
+        let (tx, rx) = watch::channel(false);
+        let gauge = METRICS.leader_vec.with_label_values(&[self.name]);
+        gauge.set(0);
+
+        tokio::task::spawn(async move {
+            let mut conn = None;
+            let mut interval = tokio::time::interval(self.interval);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = tx.closed() => break,
+                }
+
+                let was_leader = *tx.borrow();
+                // a partitioned leader must step down before another replica can take
+                // the lock, not after the TCP timeout
+                let campaign = self.campaign(&mut conn, was_leader);
+                let is_leader = match tokio::time::timeout(self.interval, campaign).await {
+                    Ok(Ok(is_leader)) => is_leader,
+                    Ok(Err(err)) => {
+                        warn!(
+                            election = self.name,
+                            "Leader election check failed, error = {}", err
+                        );
+                        conn = None;
+                        false
+                    }
+                    Err(_) => {
+                        warn!(
+                            election = self.name,
+                            "Leader election check timed out after {:?}", self.interval
+                        );
+                        conn = None;
+                        false
+                    }
+                };
+
+                if is_leader != was_leader {
+                    if is_leader {
+                        info!(election = self.name, "Became the leader");
+                    } else {
+                        warn!(election = self.name, "Lost the leadership");
+                    }
+                    gauge.set(is_leader as i64);
+                    tx.send_replace(is_leader);
+                }
+            }
+
+            // closing the session releases the lock
+            if let Some(conn) = conn {
+                let _ = conn.close().await;
+            }
+            gauge.set(0);
+        });
+
+        LeaderHandle { rx }
+    }
+
+    async fn campaign(
+        &self,
+        conn: &mut Option<PgConnection>,
+        was_leader: bool,
+    ) -> Result<bool, sqlx::Error> {
+        let conn = match conn {
+            Some(conn) => conn,
+            None => conn.insert(self.pool.acquire().await?.detach()),
+        };
+
+        if was_leader {
+            // the lock is held as long as the session is alive
+            conn.ping().await?;
+            Ok(true)
+        } else {
+            sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+                .bind(self.lock_key)
+                .fetch_one(conn)
+                .await
+        }
+    }
+}
+
+// FNV-1a, std hashers aren't stable between Rust versions
+fn lock_key(name: &str) -> i64 {
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash as i64
+}
+
+/// Leadership state of a running `LeaderElection`
+#[derive(Clone)]
+pub struct LeaderHandle {
+    rx: watch::Receiver<bool>,
+}
+
+impl LeaderHandle {
+    pub fn is_leader(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Wait until the leadership is gained or lost
+    pub async fn changed(&mut self) -> Result<(), watch::error::RecvError> {
+        self.rx.changed().await
+    }
+
+    pub fn watch(&self) -> watch::Receiver<bool> {
+        self.rx.clone()
+    }
+}
@@ -1,3 +1,115 @@
+#[cfg(any(feature = "app-errors", feature = "locale-extractor"))]
+mod accept_language;
+#[cfg(feature = "admin-router")]
+pub mod admin;
+#[cfg(feature = "alerting")]
+pub mod alerting;
+#[cfg(feature = "jemalloc-metrics")]
+pub mod allocator;
+#[cfg(feature = "batcher")]
+pub mod batcher;
+#[cfg(feature = "bench-harness")]
+pub mod bench;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "bootstrap")]
+pub mod bootstrap;
+#[cfg(feature = "broadcast-hub")]
+pub mod broadcast;
+#[cfg(feature = "channel-metrics")]
+pub mod channel;
+#[cfg(feature = "client-metrics")]
+pub mod client_metrics;
+#[cfg(feature = "client-warmup")]
+pub mod client_warmup;
+#[cfg(feature = "cursor")]
+pub mod cursor;
+#[cfg(feature = "response-envelope")]
+pub mod envelope;
+#[cfg(feature = "error-types")]
+pub mod errors;
+#[cfg(feature = "domain-events")]
+pub mod events;
 pub mod extractors;
+#[cfg(feature = "healthcheck")]
+pub mod healthcheck;
+#[cfg(feature = "hedging")]
+pub mod hedge;
+#[cfg(feature = "job-queue")]
+pub mod jobs;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "leader-election")]
+pub mod leader;
+#[cfg(feature = "loadgen")]
+pub mod loadgen;
+#[cfg(feature = "long-poll")]
+pub mod long_poll;
+#[cfg(feature = "metrics-server")]
 pub mod metrics;
 pub mod middleware;
+#[cfg(feature = "notify")]
+pub mod notify;
+#[cfg(feature = "pii-scrubbing")]
+pub mod pii;
+#[cfg(feature = "quota")]
+pub mod quota;
+#[cfg(feature = "request-recording")]
+pub mod recording;
+#[cfg(feature = "remote-write")]
+pub mod remote_write;
+#[cfg(feature = "replay-protection")]
+pub mod replay;
+#[cfg(feature = "request-scope")]
+pub mod request_scope;
+#[cfg(feature = "route-table")]
+pub mod routes;
+#[cfg(feature = "secrets")]
+pub mod secrets;
+#[cfg(feature = "session")]
+pub mod session;
+#[cfg(feature = "sharding")]
+pub mod sharding;
+#[cfg(feature = "statsd-exporter")]
+pub mod statsd;
+#[cfg(feature = "storage")]
+pub mod storage;
+#[cfg(feature = "subject-data")]
+pub mod subject_data;
+#[cfg(feature = "test-helpers")]
+pub mod test_helpers;
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
+#[cfg(feature = "write-throttle")]
+pub mod write_throttle;
+pub mod ws;
+
+#[cfg(feature = "macros")]
+pub use svc_utils_macros::svc_route;
+
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::__svc_route_with_authn as with_authn;
+    pub use axum;
+}
+
+#[cfg(all(feature = "macros", feature = "authn-extractor"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __svc_route_with_authn {
+    ($spec:expr) => {
+        $spec.with_authn()
+    };
+}
+
+#[cfg(all(feature = "macros", not(feature = "authn-extractor")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __svc_route_with_authn {
+    ($spec:expr) => {
+        ::std::compile_error!(
+            "`auth = \"required\"` needs the `authn-extractor` feature of svc-utils"
+        )
+    };
+}
@@ -0,0 +1,202 @@
+//! In-process load generator for benchmarking a `Router` with its middleware stack.
+//!
+//! ```ignore
+//! let report = LoadGen::new(|| Request::get("/api/v1/rooms").body(Body::empty()).unwrap())
+//!     .with_rps(2000)
+//!     .with_duration(Duration::from_secs(10))
+//!     .run(router)
+//!     .await;
+//! println!("{}", report);
+//! ```
+//!
+//! Requests are sent at the fixed rate regardless of how fast they are answered, so slow
+//! responses show up in the percentiles instead of lowering the rate. Latencies are
+//! measured from the time a request was due, so requests sent late because the runtime
+//! was busy count the delay too.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use axum::Router;
+use http::Request;
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+use tower::ServiceExt;
+
+// a request every microsecond, far over what a single process can answer
+const MAX_RPS: u32 = 1_000_000;
+
+/// Sends requests built by a closure to a `Router` at a fixed rate
+pub struct LoadGen<F> {
+    make_request: Arc<F>,
+    rps: u32,
+    duration: Duration,
+    warmup: Duration,
+    max_in_flight: usize,
+}
+
+impl<F> LoadGen<F>
+where
+    F: Fn() -> Request<Body> + Send + Sync + 'static,
+{
+    /// Create new load generator sending 100 requests per second for 5 seconds,
+    /// with up to 1000 requests in flight
+    pub fn new(make_request: F) -> Self {
+        Self {
+            make_request: Arc::new(make_request),
+            rps: 100,
+            duration: Duration::from_secs(5),
+            warmup: Duration::ZERO,
+            max_in_flight: 1000,
+        }
+    }
+
+    /// Requests per second, from 1 to 1000000
+    pub fn with_rps(mut self, rps: u32) -> Self {
+        self.rps = rps.clamp(1, MAX_RPS);
+        self
+    }
+
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Send requests for `warmup` before the measured duration without reporting them
+    pub fn with_warmup(mut self, warmup: Duration) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    /// Requests due while `max_in_flight` requests are waiting for responses
+    /// are skipped and counted in `LoadReport::skipped`
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    pub async fn run(self, router: Router) -> LoadReport {
+        if !self.warmup.is_zero() {
+            self.send(&router, self.warmup).await;
+        }
+
+        let started = Instant::now();
+        let mut report = self.send(&router, self.duration).await;
+        report.elapsed = started.elapsed();
+        report.latencies.sort_unstable();
+        report
+    }
+
+    async fn send(&self, router: &Router, duration: Duration) -> LoadReport {
+        let mut report = LoadReport::default();
+        let mut in_flight = JoinSet::new();
+
+        let mut interval = tokio::time::interval(Duration::from_secs(1) / self.rps);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Burst);
+        // no deadline for durations too long to add to the current time
+        let deadline = tokio::time::Instant::now().checked_add(duration);
+
+        loop {
+            tokio::select! {
+                tick = interval.tick() => {
+                    if matches!(deadline, Some(deadline) if tick >= deadline) {
+                        break;
+                    }
+                    if in_flight.len() >= self.max_in_flight {
+                        report.skipped += 1;
+                        continue;
+                    }
+
+                    let req = (self.make_request)();
+                    let router = router.clone();
+                    report.sent += 1;
+                    in_flight.spawn(async move {
+                        let resp = match router.oneshot(req).await {
+                            Ok(resp) => resp,
+                            Err(infallible) => match infallible {},
+                        };
+                        // the response is complete once its body is read
+                        let (parts, body) = resp.into_parts();
+                        let body = axum::body::to_bytes(body, usize::MAX).await;
+                        (parts.status.is_server_error() || body.is_err(), tick.elapsed())
+                    });
+                }
+                Some(result) = in_flight.join_next() => report.record(result),
+            }
+        }
+
+        while let Some(result) = in_flight.join_next().await {
+            report.record(result);
+        }
+
+        report
+    }
+}
+
+/// Results of a `LoadGen` run
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    pub sent: u64,
+    /// Requests not sent because too many were in flight
+    pub skipped: u64,
+    /// Responses with 5xx status or failed bodies, handler panics
+    pub errors: u64,
+    /// Latencies of the answered requests in ascending order
+    pub latencies: Vec<Duration>,
+    pub elapsed: Duration,
+}
+
+impl LoadReport {
+    fn record(&mut self, result: Result<(bool, Duration), tokio::task::JoinError>) {
+        match result {
+            Ok((failed, latency)) => {
+                if failed {
+                    self.errors += 1;
+                }
+                self.latencies.push(latency);
+            }
+            Err(_) => self.errors += 1,
+        }
+    }
+
+    /// Latency at the percentile from 0 to 100, zero without answered requests
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * self.latencies.len() as f64).ceil();
+        let idx = (rank as usize).clamp(1, self.latencies.len()) - 1;
+        self.latencies[idx]
+    }
+
+    /// Answered requests per second
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.latencies.len() as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "sent = {}, skipped = {}, errors = {}, throughput = {:.1} rps, \
+             p50 = {:?}, p90 = {:?}, p99 = {:?}, max = {:?}",
+            self.sent,
+            self.skipped,
+            self.errors,
+            self.throughput(),
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            self.percentile(100.0),
+        )
+    }
+}
@@ -0,0 +1,130 @@
+//! Long polling on hub topics for clients that can't use WebSockets or SSE.
+//!
+//! Subscribe before reading the current state, so changes made in between aren't missed:
+//!
+//! ```ignore
+//! async fn poll_room(
+//!     State(state): State<AppState>,
+//!     Path(room_id): Path<Uuid>,
+//!     Query(query): Query<PollQuery>,
+//! ) -> Result<LongPollResponse<Room>, AppError> {
+//!     let waiter = long_poll(&state.rooms_hub, &room_id.to_string());
+//!
+//!     let room = db::find_room(room_id).await?;
+//!     if room.version > query.since {
+//!         return Ok(LongPollResponse::Changed(room));
+//!     }
+//!     Ok(waiter.wait(Duration::from_secs(25)).await)
+//! }
+//! ```
+
+use std::time::Duration;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGauge, IntGaugeVec,
+};
+use serde::Serialize;
+
+use crate::broadcast::{Hub, Subscription};
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    waiting: IntGaugeVec,
+    completed: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            waiting: register_int_gauge_vec!(
+                "long_poll_waiting",
+                "Long poll requests waiting for a change",
+                &["hub"]
+            )
+            .expect("Can't create stats metrics"),
+            completed: register_int_counter_vec!(
+                "long_poll_completed_total",
+                "Long poll waits completed by result",
+                &["hub", "result"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Subscribe to the topic of the hub to wait for its next event
+pub fn long_poll<T: Clone + Send + 'static>(hub: &Hub<T>, topic: &str) -> Waiter<T> {
+    Waiter {
+        hub: hub.name().to_owned(),
+        subscription: hub.subscribe(topic),
+    }
+}
+
+/// Subscription waiting for a change, returned by `long_poll`
+pub struct Waiter<T> {
+    hub: String,
+    subscription: Subscription<T>,
+}
+
+impl<T: Clone> Waiter<T> {
+    /// Wait for the next event of the topic up to `timeout`.
+    ///
+    /// A subscriber disconnected by the hub as a slow consumer is answered 304 as well,
+    /// clients poll again and get the current state.
+    pub async fn wait(mut self, timeout: Duration) -> LongPollResponse<T> {
+        let _waiting = Waiting::new(METRICS.waiting.with_label_values(&[&self.hub]));
+
+        let (response, result) = match tokio::time::timeout(timeout, self.subscription.recv()).await
+        {
+            Ok(Some(event)) => (LongPollResponse::Changed(event), "changed"),
+            Ok(None) => (LongPollResponse::NotModified, "closed"),
+            Err(_) => (LongPollResponse::NotModified, "timeout"),
+        };
+
+        METRICS
+            .completed
+            .with_label_values(&[&self.hub, result])
+            .inc();
+        response
+    }
+}
+
+/// Waiting request counted in the gauge until dropped, also when the client disconnects
+/// and the request is dropped while waiting
+struct Waiting(IntGauge);
+
+impl Waiting {
+    fn new(gauge: IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Result of a long poll, answers 200 with the JSON of the event or 304 Not Modified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LongPollResponse<T> {
+    Changed(T),
+    NotModified,
+}
+
+impl<T: Serialize> IntoResponse for LongPollResponse<T> {
+    fn into_response(self) -> Response {
+        match self {
+            LongPollResponse::Changed(event) => Json(event).into_response(),
+            LongPollResponse::NotModified => StatusCode::NOT_MODIFIED.into_response(),
+        }
+    }
+}
@@ -1,8 +1,19 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use axum::{extract::Extension, routing, routing::Router, Server};
-use hyper::{Body, Request, Response};
-use prometheus::{Encoder, Registry, TextEncoder};
+use axum::{
+    body::Body,
+    extract::Extension,
+    http::{Request, Response},
+    routing,
+    routing::Router,
+};
+use prometheus::{proto::MetricFamily, Encoder, Gauge, IntCounter, Registry, TextEncoder};
 use tokio::{sync::oneshot, task::JoinHandle};
 use tower_http::trace::TraceLayer;
 use tracing::{error, field::Empty, info, warn, Span};
@@ -11,41 +22,70 @@ use tracing::{error, field::Empty, info, warn, Span};
 ///
 /// Runs in a separate tokio task
 pub struct MetricsServer {
-    join_handle: JoinHandle<Result<(), hyper::Error>>,
+    join_handle: JoinHandle<Result<(), std::io::Error>>,
     closer: oneshot::Sender<()>,
 }
 
 impl MetricsServer {
     /// Create new server with prometheus default registry. This will spawn a new tokio task.
     ///
+    /// Registers `instance_start_time_seconds` gauge in the registry.
+    ///
     /// # Arguments
     ///
     /// * `registry` - prometheus registry to gather metrics from
     /// * `bind_addr` - address to bind server to
     pub fn new(bind_addr: SocketAddr) -> Self {
-        let app = Router::new().route("/metrics", routing::get(metrics_handler));
+        register_start_time(prometheus::default_registry());
 
-        Self::new_(app, bind_addr)
+        Self::new_(Source::Default, bind_addr)
     }
 
     /// Create new server with a given registry. This will spawn a new tokio task.
     ///
+    /// Registers `instance_start_time_seconds` gauge in the registry.
+    ///
     /// # Arguments
     ///
     /// * `registry` - prometheus registry to gather metrics from
     /// * `bind_addr` - address to bind server to
     pub fn new_with_registry(registry: Registry, bind_addr: SocketAddr) -> Self {
-        let app = Router::new();
+        register_start_time(&registry);
 
-        let app = app
-            .route("/metrics", routing::get(metrics_handler_with_registry))
-            .layer(Extension(registry));
+        Self::new_(Source::Registry(registry), bind_addr)
+    }
+
+    /// Create new server serving the union of several registries from a single `/metrics`.
+    /// This will spawn a new tokio task.
+    ///
+    /// Metric families with the same name are merged, series present in several registries
+    /// are taken from the first one. Conflicting families found at startup are logged.
+    ///
+    /// # Arguments
+    ///
+    /// * `registries` - prometheus registries to gather metrics from, in order of precedence
+    /// * `bind_addr` - address to bind server to
+    pub fn new_with_registries(registries: Vec<Registry>, bind_addr: SocketAddr) -> Self {
+        if let Some(registry) = registries.first() {
+            register_start_time(registry);
+        }
+        report_conflicts(&registries);
 
-        Self::new_(app, bind_addr)
+        Self::new_(Source::Registries(registries), bind_addr)
     }
 
-    fn new_(app: Router, bind_addr: SocketAddr) -> Self {
-        let app = app.layer(
+    fn new_(source: Source, bind_addr: SocketAddr) -> Self {
+        let app = Router::new().route("/metrics", routing::get(metrics_handler));
+
+        #[cfg(feature = "metrics-json")]
+        let app = app.route("/metrics.json", routing::get(json_metrics_handler));
+
+        #[cfg(feature = "metrics-debug")]
+        let app = app
+            .route("/debug/metrics/delta", routing::get(delta_metrics_handler))
+            .layer(Extension(Arc::new(DeltaState::default())));
+
+        let app = app.layer(Extension(Arc::new(source))).layer(
             TraceLayer::new_for_http()
                 .make_span_with(|request: &Request<_>| {
                     // TODO: Option will be recorded simpler
@@ -59,12 +99,12 @@ impl MetricsServer {
                     );
                     if let Some(query) = request.uri().query() {
                         // clippy in CI doesn't like the simple '&query' here
-                        span.record("query", &tracing::field::display(query));
+                        span.record("query", tracing::field::display(query));
                     }
                     span
                 })
                 .on_response(|response: &Response<_>, latency: Duration, span: &Span| {
-                    span.record("status_code", &tracing::field::display(response.status()));
+                    span.record("status_code", tracing::field::display(response.status()));
                     info!("response generated in {:?}", latency)
                 }),
         );
@@ -72,8 +112,13 @@ impl MetricsServer {
         let (closer, rx) = oneshot::channel::<()>();
 
         let join_handle = tokio::task::spawn(async move {
-            Server::bind(&bind_addr)
-                .serve(app.into_make_service())
+            let listener = tokio::net::TcpListener::bind(bind_addr)
+                .await
+                .map_err(|err| {
+                    error!("Metrics server failed to bind {}: {}", bind_addr, err);
+                    err
+                })?;
+            axum::serve(listener, app.into_make_service())
                 .with_graceful_shutdown(async {
                     rx.await.ok();
                 })
@@ -90,42 +135,359 @@ impl MetricsServer {
     pub async fn shutdown(self) {
         info!("Received signal, triggering metrics server shutdown");
 
+        let started = Instant::now();
         let _ = self.closer.send(());
         let fut = tokio::time::timeout(Duration::from_secs(3), self.join_handle);
 
         match fut.await {
             Err(e) => {
-                error!("Metrics server timed out during shutdown, error = {:?}", e);
+                error!(
+                    shutdown_time = ?started.elapsed(),
+                    "Metrics server timed out during shutdown, error = {:?}", e
+                );
             }
             Ok(Err(e)) => {
-                error!("Metrics server failed during shutdown, error = {:?}", e);
+                error!(
+                    shutdown_time = ?started.elapsed(),
+                    "Metrics server failed during shutdown, error = {:?}", e
+                );
             }
             Ok(Ok(_)) => {
-                info!("Metrics server successfully exited");
+                info!(
+                    shutdown_time = ?started.elapsed(),
+                    "Metrics server successfully exited"
+                );
             }
         }
     }
 }
 
-async fn metrics_handler() -> Response<Body> {
-    let mut buffer = vec![];
-    let encoder = TextEncoder::new();
-    let metric_families = prometheus::gather();
-    match encoder.encode(&metric_families, &mut buffer) {
-        Ok(_) => Response::builder().status(200).body(buffer.into()).unwrap(),
+/// Constant labels attached to every metric of a registry, e.g. pod name or git SHA
+///
+/// Labels are taken from environment variables or files (like Kubernetes downward API volumes),
+/// missing sources are skipped. Only metrics registered in `ConstLabels::registry`
+/// get the labels, the default registry can't be changed.
+#[derive(Debug, Clone, Default)]
+pub struct ConstLabels {
+    labels: HashMap<String, String>,
+}
+
+impl ConstLabels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.insert(label.into(), value.into());
+        self
+    }
+
+    /// Take label value from the environment variable if it is set
+    pub fn with_env(self, label: impl Into<String>, var: &str) -> Self {
+        match std::env::var(var) {
+            Ok(value) if !value.is_empty() => self.with_label(label, value),
+            _ => {
+                warn!(var, "Environment variable for metrics label is not set");
+                self
+            }
+        }
+    }
+
+    /// Take label value from the file contents if it exists
+    pub fn with_file(self, label: impl Into<String>, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(value) if !value.trim().is_empty() => self.with_label(label, value.trim()),
+            Ok(_) => self,
+            Err(err) => {
+                warn!(path = %path.display(), "Metrics label file not read: {}", err);
+                self
+            }
+        }
+    }
+
+    pub fn labels(&self) -> &HashMap<String, String> {
+        &self.labels
+    }
+
+    /// Create new registry attaching the labels to all its metrics
+    pub fn registry(self) -> Result<Registry, prometheus::Error> {
+        Registry::new_custom(None, Some(self.labels))
+    }
+}
+
+/// Uptime is `time() - instance_start_time_seconds`, restarts are counted
+/// with `changes(instance_start_time_seconds[1h])`. Not named `process_start_time_seconds`
+/// to not collide with the prometheus process collector.
+fn register_start_time(registry: &Registry) {
+    let start_time = process_start_time().unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64()
+    });
+
+    let gauge = Gauge::new(
+        "instance_start_time_seconds",
+        "Start time of the process since unix epoch in seconds",
+    )
+    .expect("Can't create stats metrics");
+    gauge.set(start_time);
+
+    match registry.register(Box::new(gauge)) {
+        // another server has registered it already
+        Ok(()) | Err(prometheus::Error::AlreadyReg) => {}
         Err(err) => {
-            warn!("Metrics not gathered: {:?}", err);
-            Response::builder().status(500).body(vec![].into()).unwrap()
+            warn!("Start time metric not registered: {:?}", err);
         }
     }
 }
 
-async fn metrics_handler_with_registry(state: Extension<Registry>) -> Response<Body> {
-    let registry = state.0;
+/// Start time of the process from procfs, not of the metrics server which may be
+/// created long after the startup
+#[cfg(target_os = "linux")]
+fn process_start_time() -> Option<f64> {
+    // clock ticks the kernel reports process times in, 100 on all common architectures
+    const USER_HZ: f64 = 100.0;
+
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // the command name in parentheses may contain spaces, `starttime` is the 22nd field
+    let (_, fields) = stat.rsplit_once(')')?;
+    let ticks = fields.split_whitespace().nth(19)?.parse::<u64>().ok()?;
+
+    let boot_time = std::fs::read_to_string("/proc/stat")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+
+    Some(boot_time as f64 + ticks as f64 / USER_HZ)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_start_time() -> Option<f64> {
+    None
+}
+
+/// Registers `instance_restarts_total` counter in the registry, counting the starts
+/// recorded in `state_file` before this one.
+///
+/// The file must outlive the container but not the instance, e.g. be on an `emptyDir`
+/// volume of the pod, so crash loops show up without kube-state-metrics.
+pub fn register_restart_counter(
+    registry: &Registry,
+    state_file: impl AsRef<Path>,
+) -> Result<(), prometheus::Error> {
+    let state_file = state_file.as_ref();
+    let restarts = match std::fs::read_to_string(state_file) {
+        Ok(content) => content.trim().parse::<u64>().unwrap_or(0) + 1,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(err) => return Err(err.into()),
+    };
+    std::fs::write(state_file, restarts.to_string())?;
+
+    let counter = IntCounter::new(
+        "instance_restarts_total",
+        "Restarts of the instance since it was created",
+    )
+    .expect("Can't create stats metrics");
+    counter.inc_by(restarts);
+
+    registry.register(Box::new(counter))
+}
+
+/// Registries served by `MetricsServer`
+enum Source {
+    Default,
+    Registry(Registry),
+    Registries(Vec<Registry>),
+}
+
+impl Source {
+    fn gather(&self) -> Vec<MetricFamily> {
+        match self {
+            Source::Default => prometheus::gather(),
+            Source::Registry(registry) => registry.gather(),
+            Source::Registries(registries) => gather_union(registries),
+        }
+    }
+}
+
+async fn metrics_handler(state: Extension<Arc<Source>>) -> Response<Body> {
+    encode(&state.0.gather())
+}
+
+/// Metric families as JSON, for tools without a prometheus parser
+#[cfg(feature = "metrics-json")]
+async fn json_metrics_handler(state: Extension<Arc<Source>>) -> axum::Json<Vec<serde_json::Value>> {
+    use prometheus::proto::MetricType;
+    use serde_json::{json, Map, Value};
+
+    let families = state
+        .0
+        .gather()
+        .iter()
+        .map(|family| {
+            let metrics = family
+                .get_metric()
+                .iter()
+                .map(|metric| {
+                    let labels = metric
+                        .get_label()
+                        .iter()
+                        .map(|l| (l.get_name().to_owned(), Value::from(l.get_value())))
+                        .collect::<Map<_, _>>();
+
+                    let value = match family.get_field_type() {
+                        MetricType::COUNTER => json!(metric.get_counter().get_value()),
+                        MetricType::GAUGE => json!(metric.get_gauge().get_value()),
+                        // not produced by the prometheus crate
+                        MetricType::UNTYPED => Value::Null,
+                        MetricType::HISTOGRAM => {
+                            let histogram = metric.get_histogram();
+                            let buckets = histogram
+                                .get_bucket()
+                                .iter()
+                                .map(|b| {
+                                    json!({
+                                        "le": b.get_upper_bound(),
+                                        "count": b.get_cumulative_count(),
+                                    })
+                                })
+                                .collect::<Vec<_>>();
+                            json!({
+                                "count": histogram.get_sample_count(),
+                                "sum": histogram.get_sample_sum(),
+                                "buckets": buckets,
+                            })
+                        }
+                        MetricType::SUMMARY => {
+                            let summary = metric.get_summary();
+                            let quantiles = summary
+                                .get_quantile()
+                                .iter()
+                                .map(|q| {
+                                    json!({
+                                        "quantile": q.get_quantile(),
+                                        "value": q.get_value(),
+                                    })
+                                })
+                                .collect::<Vec<_>>();
+                            json!({
+                                "count": summary.get_sample_count(),
+                                "sum": summary.get_sample_sum(),
+                                "quantiles": quantiles,
+                            })
+                        }
+                    };
+
+                    json!({ "labels": labels, "value": value })
+                })
+                .collect::<Vec<_>>();
+
+            json!({
+                "name": family.get_name(),
+                "help": family.get_help(),
+                "type": format!("{:?}", family.get_field_type()).to_lowercase(),
+                "metrics": metrics,
+            })
+        })
+        .collect();
+
+    axum::Json(families)
+}
+
+/// Last seen counter values per caller token
+#[cfg(feature = "metrics-debug")]
+#[derive(Default)]
+struct DeltaState {
+    callers: std::sync::Mutex<HashMap<String, HashMap<String, f64>>>,
+}
+
+// callers are forgotten once there are more of them
+#[cfg(feature = "metrics-debug")]
+const MAX_DELTA_CALLERS: usize = 64;
+
+/// Counter deltas since the previous call with the same `token` query parameter,
+/// histograms and summaries are reported by their sample count.
+/// The first call of a token records the baseline.
+#[cfg(feature = "metrics-debug")]
+async fn delta_metrics_handler(
+    source: Extension<Arc<Source>>,
+    state: Extension<Arc<DeltaState>>,
+    uri: http::Uri,
+) -> String {
+    use prometheus::proto::MetricType;
+
+    let token = uri
+        .query()
+        .and_then(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "token")
+                .map(|(_, value)| value.into_owned())
+        })
+        .unwrap_or_default();
+
+    let mut current = HashMap::new();
+    for family in source.0.gather() {
+        for metric in family.get_metric() {
+            let (name, value) = match family.get_field_type() {
+                MetricType::COUNTER => (
+                    family.get_name().to_owned(),
+                    metric.get_counter().get_value(),
+                ),
+                MetricType::HISTOGRAM => (
+                    format!("{}_count", family.get_name()),
+                    metric.get_histogram().get_sample_count() as f64,
+                ),
+                MetricType::SUMMARY => (
+                    format!("{}_count", family.get_name()),
+                    metric.get_summary().get_sample_count() as f64,
+                ),
+                _ => continue,
+            };
+            let labels = metric
+                .get_label()
+                .iter()
+                .map(|l| format!("{}={:?}", l.get_name(), l.get_value()))
+                .collect::<Vec<_>>()
+                .join(",");
+            current.insert(format!("{}{{{}}}", name, labels), value);
+        }
+    }
+
+    let mut callers = state
+        .0
+        .callers
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if callers.len() >= MAX_DELTA_CALLERS && !callers.contains_key(&token) {
+        callers.clear();
+    }
+
+    let deltas = match callers.insert(token, current.clone()) {
+        Some(previous) => current
+            .into_iter()
+            .filter_map(|(series, value)| {
+                let delta = value - previous.get(&series).copied().unwrap_or(0.0);
+                (delta != 0.0).then_some((series, delta))
+            })
+            .collect::<BTreeMap<_, _>>(),
+        None => BTreeMap::new(),
+    };
+
+    deltas
+        .into_iter()
+        .map(|(series, delta)| format!("{} {:+}\n", series, delta))
+        .collect()
+}
+
+fn encode(metric_families: &[MetricFamily]) -> Response<Body> {
     let mut buffer = vec![];
     let encoder = TextEncoder::new();
-    let metric_families = registry.gather();
-    match encoder.encode(&metric_families, &mut buffer) {
+    match encoder.encode(metric_families, &mut buffer) {
         Ok(_) => Response::builder().status(200).body(buffer.into()).unwrap(),
         Err(err) => {
             warn!("Metrics not gathered: {:?}", err);
@@ -133,3 +495,74 @@ async fn metrics_handler_with_registry(state: Extension<Registry>) -> Response<B
         }
     }
 }
+
+/// Merges families by name, skipping families of a different type and already present series
+fn gather_union(registries: &[Registry]) -> Vec<MetricFamily> {
+    let mut union: BTreeMap<String, MetricFamily> = BTreeMap::new();
+
+    for family in registries.iter().flat_map(|registry| registry.gather()) {
+        match union.get_mut(family.get_name()) {
+            None => {
+                union.insert(family.get_name().to_owned(), family);
+            }
+            Some(existing) if existing.get_field_type() == family.get_field_type() => {
+                for metric in family.get_metric() {
+                    let duplicate = existing
+                        .get_metric()
+                        .iter()
+                        .any(|m| m.get_label() == metric.get_label());
+                    if !duplicate {
+                        existing.mut_metric().push(metric.clone());
+                    }
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    union.into_values().collect()
+}
+
+fn report_conflicts(registries: &[Registry]) {
+    let mut seen: BTreeMap<String, MetricFamily> = BTreeMap::new();
+
+    for (idx, registry) in registries.iter().enumerate() {
+        for family in registry.gather() {
+            let existing = match seen.get(family.get_name()) {
+                Some(existing) => existing,
+                None => {
+                    seen.insert(family.get_name().to_owned(), family);
+                    continue;
+                }
+            };
+
+            if existing.get_field_type() != family.get_field_type() {
+                error!(
+                    metric = family.get_name(),
+                    registry = idx,
+                    "Metric family type conflicts with another registry, it won't be served"
+                );
+                continue;
+            }
+
+            let duplicates = family
+                .get_metric()
+                .iter()
+                .filter(|metric| {
+                    existing
+                        .get_metric()
+                        .iter()
+                        .any(|m| m.get_label() == metric.get_label())
+                })
+                .count();
+            if duplicates > 0 {
+                warn!(
+                    metric = family.get_name(),
+                    registry = idx,
+                    duplicates,
+                    "Metric series are present in another registry, they won't be served"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::CONTENT_LENGTH;
+use actix_web::{Error, HttpResponse};
+use futures::future::{ready, LocalBoxFuture, Ready};
+
+pub struct Middleware<S> {
+    body_size_limit: u64,
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for Middleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let len = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse::<u64>().ok());
+
+        if let Some(len) = len {
+            if len > self.body_size_limit {
+                let resp = req
+                    .into_response(HttpResponse::PayloadTooLarge().finish())
+                    .map_into_right_body();
+                return Box::pin(ready(Ok(resp)));
+            }
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) })
+    }
+}
+
+/// Rejects requests with declared body size over the limit with 413 Payload Too Large,
+/// same as `BodyLimitLayer`
+#[derive(Debug, Clone)]
+pub struct BodyLimit {
+    body_size_limit: u64,
+}
+
+impl BodyLimit {
+    pub fn new(body_size_limit: u64) -> Self {
+        Self { body_size_limit }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for BodyLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = Middleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(Middleware {
+            body_size_limit: self.body_size_limit,
+            service,
+        }))
+    }
+}
@@ -0,0 +1,81 @@
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{
+    HeaderValue, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, VARY,
+};
+use actix_web::http::Method;
+use actix_web::{Error, HttpResponse};
+use futures::future::{ready, LocalBoxFuture, Ready};
+
+use crate::middleware::cors::{ALLOW_HEADERS, ALLOW_METHODS, MAX_AGE};
+
+const VARY_HEADERS: &str = "origin,access-control-request-method,access-control-request-headers";
+
+pub struct Middleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for Middleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // every OPTIONS request is a preflight, same as in `CorsLayer`
+        if req.method() == Method::OPTIONS {
+            let resp = HttpResponse::Ok()
+                .insert_header((ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+                .insert_header((ACCESS_CONTROL_ALLOW_METHODS, ALLOW_METHODS.join(",")))
+                .insert_header((ACCESS_CONTROL_ALLOW_HEADERS, ALLOW_HEADERS.join(",")))
+                .insert_header((ACCESS_CONTROL_MAX_AGE, MAX_AGE.as_secs().to_string()))
+                .insert_header((VARY, VARY_HEADERS))
+                .finish();
+            let resp = req.into_response(resp).map_into_right_body();
+            return Box::pin(ready(Ok(resp)));
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut resp = fut.await?;
+            let headers = resp.headers_mut();
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+            headers.append(VARY, HeaderValue::from_static(VARY_HEADERS));
+            Ok(resp.map_into_left_body())
+        })
+    }
+}
+
+/// Allows cross-origin requests with the same policy as `CorsLayer`
+#[derive(Debug, Default, Clone)]
+pub struct Cors;
+
+impl Cors {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Cors
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = Middleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(Middleware { service }))
+    }
+}
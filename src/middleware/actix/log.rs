@@ -0,0 +1,148 @@
+use std::borrow::Cow;
+use std::time::Instant;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::CONTENT_LENGTH;
+use actix_web::http::{Method, StatusCode};
+use actix_web::Error;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use tracing::{
+    error,
+    field::{self, Empty},
+    info, Instrument, Span,
+};
+
+pub struct Middleware<S> {
+    scrub_pii: bool,
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for Middleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let mut path = Cow::Borrowed(req.path());
+        let mut query = Some(req.query_string())
+            .filter(|query| !query.is_empty())
+            .map(Cow::Borrowed);
+        if self.scrub_pii {
+            path = scrub(path);
+            query = query.map(scrub);
+        }
+
+        let span = tracing::error_span!(
+            "http-api-request",
+            status_code = Empty,
+            path = &*path,
+            query = query.as_deref(),
+            method = %req.method(),
+            account_id = Empty,
+            locale = Empty,
+            client = Empty,
+            bot_score = Empty,
+            clock_skew_ms = Empty,
+            body_size = Empty,
+            kind = Empty,
+            detail = Empty,
+        );
+
+        if req.method() != Method::GET && req.method() != Method::OPTIONS {
+            let body_size = req
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|len| len.to_str().ok())
+                .and_then(|len| len.parse::<u64>().ok());
+            span.record("body_size", field::debug(body_size));
+        }
+
+        let fut = {
+            let _enter = span.enter();
+            self.service.call(req)
+        };
+
+        Box::pin(
+            async move {
+                let res = fut.await;
+                let status = match &res {
+                    Ok(res) => res.status(),
+                    Err(err) => err.as_response_error().status_code(),
+                };
+                on_response(status, started, &Span::current());
+                res
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(feature = "pii-scrubbing")]
+fn scrub(value: Cow<'_, str>) -> Cow<'_, str> {
+    match crate::pii::scrub(&value) {
+        Cow::Borrowed(_) => value,
+        Cow::Owned(scrubbed) => Cow::Owned(scrubbed),
+    }
+}
+
+#[cfg(not(feature = "pii-scrubbing"))]
+fn scrub(value: Cow<'_, str>) -> Cow<'_, str> {
+    value
+}
+
+fn on_response(status: StatusCode, started: Instant, span: &Span) {
+    let latency = started.elapsed();
+    span.record("status_code", field::debug(status));
+    if status.is_client_error() || status.is_server_error() {
+        error!("response generated in {:?}", latency)
+    } else {
+        info!("response generated in {:?}", latency)
+    }
+}
+
+/// Logs requests with the same span and fields as `LogLayer`
+#[derive(Debug, Default, Clone)]
+pub struct Log {
+    scrub_pii: bool,
+}
+
+impl Log {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mask emails, phone numbers and tokens in the logged path and query
+    #[cfg(feature = "pii-scrubbing")]
+    pub fn with_pii_scrubbing(mut self) -> Self {
+        self.scrub_pii = true;
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Log
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = Middleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(Middleware {
+            scrub_pii: self.scrub_pii,
+            service,
+        }))
+    }
+}
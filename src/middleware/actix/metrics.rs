@@ -0,0 +1,158 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::PayloadError;
+use actix_web::{Error, HttpMessage};
+use bytes::Bytes;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use futures::Stream;
+use http::{Method, StatusCode};
+use pin_project_lite::pin_project;
+
+use crate::middleware::metrics::{BodyObserver, MetricsConfig, RouteMetrics};
+
+pub struct Middleware<S> {
+    metrics: RouteMetrics,
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for Middleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<CountingBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        // actix-web is built on http 0.2
+        let method = Method::from_bytes(req.method().as_str().as_bytes())
+            .expect("request method must be valid");
+
+        let metrics = self.metrics.clone();
+        let observer = metrics.request_body_observer(&method);
+        let payload = CountingBody::new(req.take_payload(), observer);
+        let payload: Pin<Box<dyn Stream<Item = _>>> = Box::pin(payload);
+        req.set_payload(Payload::from(payload));
+
+        let started = Instant::now();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(err) => err.as_response_error().status_code(),
+            };
+            let status = StatusCode::from_u16(status.as_u16()).expect("status must be valid");
+            metrics.record(method.clone(), status, started);
+
+            let observer = metrics.response_body_observer(&method);
+            res.map(|res| res.map_body(|_, body| CountingBody::new(body, observer)))
+        })
+    }
+}
+
+pin_project! {
+    /// Request payload or response body counting bytes actually transferred through it
+    pub struct CountingBody<B> {
+        #[pin]
+        inner: B,
+        observer: BodyObserver,
+    }
+}
+
+impl<B> CountingBody<B> {
+    fn new(inner: B, observer: BodyObserver) -> Self {
+        Self { inner, observer }
+    }
+}
+
+impl Stream for CountingBody<Payload> {
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let poll = this.inner.poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(data))) => this.observer.add(data.len() as u64),
+            Poll::Ready(None) => this.observer.finish(),
+            _ => {}
+        }
+        poll
+    }
+}
+
+impl<B> MessageBody for CountingBody<B>
+where
+    B: MessageBody,
+{
+    type Error = B::Error;
+
+    fn size(&self) -> BodySize {
+        self.inner.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.project();
+        let poll = this.inner.poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(data))) => this.observer.add(data.len() as u64),
+            Poll::Ready(None) => this.observer.finish(),
+            _ => {}
+        }
+        poll
+    }
+}
+
+/// Records the same request metrics as `MeteredRoute` under the `path` label,
+/// wrap a resource or a scope with it
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    path: String,
+    config: MetricsConfig,
+}
+
+impl Metrics {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            config: MetricsConfig::default(),
+        }
+    }
+
+    pub fn with_config(mut self, config: MetricsConfig) -> Self {
+        self.config = config;
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Metrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<CountingBody<B>>;
+    type Error = Error;
+    type Transform = Middleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(Middleware {
+            metrics: RouteMetrics::new(&self.path, &self.config),
+            service,
+        }))
+    }
+}
@@ -0,0 +1,24 @@
+//! actix-web counterparts of the middlewares for services not moved to axum yet,
+//! they record the same metrics and log fields.
+//!
+//! ```ignore
+//! App::new()
+//!     .service(
+//!         web::resource("/api/v1/rooms")
+//!             .route(web::post().to(create_room))
+//!             .wrap(Metrics::new("/api/v1/rooms")),
+//!     )
+//!     .wrap(BodyLimit::new(1024 * 1024))
+//!     .wrap(Cors::new())
+//!     .wrap(Log::new());
+//! ```
+
+pub use body_limit::BodyLimit;
+pub use cors::Cors;
+pub use log::Log;
+pub use metrics::Metrics;
+
+mod body_limit;
+mod cors;
+mod log;
+mod metrics;
@@ -0,0 +1,274 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use http::{
+    header::{CONTENT_TYPE, RETRY_AFTER},
+    request::Parts,
+    HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode,
+};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, register_int_gauge, IntCounterVec, IntGauge};
+use tokio::sync::Semaphore;
+use tower::{Layer, Service};
+
+static RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+static RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+static RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    queued: IntGauge,
+    rejected: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            queued: register_int_gauge!(
+                "admission_queued_requests",
+                "Requests waiting for admission over the in-flight limit"
+            )
+            .expect("Can't create stats metrics"),
+            rejected: register_int_counter_vec!(
+                "admission_rejected_total",
+                "Requests answered 503 over capacity by priority class and reason",
+                &["priority", "reason"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Priority class of a request. Under overload the lower classes are shed first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Traffic nobody waits for, e.g. analytics or prefetch
+    Low,
+    #[default]
+    Normal,
+    /// Interactive traffic
+    High,
+}
+
+impl Priority {
+    fn as_str(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+
+    /// Number of queued requests the class still queues after
+    fn max_queued(self, max_queued: usize) -> usize {
+        match self {
+            Priority::Low => 0,
+            Priority::Normal => max_queued / 2,
+            Priority::High => max_queued,
+        }
+    }
+}
+
+type PriorityFn = dyn Fn(&Parts) -> Priority + Send + Sync;
+
+#[derive(Clone)]
+struct Admission {
+    permits: Arc<Semaphore>,
+    max_in_flight: usize,
+    queued: Arc<AtomicUsize>,
+    max_queued: usize,
+    queue_timeout: Duration,
+    retry_after: u64,
+}
+
+impl Admission {
+    fn set_headers(&self, headers: &mut HeaderMap) {
+        let remaining = self.permits.available_permits();
+        headers.insert(
+            RATELIMIT_LIMIT.clone(),
+            HeaderValue::from(self.max_in_flight),
+        );
+        headers.insert(RATELIMIT_REMAINING.clone(), HeaderValue::from(remaining));
+        headers.insert(RATELIMIT_RESET.clone(), HeaderValue::from(self.retry_after));
+    }
+
+    fn reject<B: From<String>>(&self, priority: Priority, reason: &str) -> Response<B> {
+        METRICS
+            .rejected
+            .with_label_values(&[priority.as_str(), reason])
+            .inc();
+
+        let body = serde_json::json!({
+            "type": "over_capacity",
+            "title": "Service is over capacity, retry later",
+            "status": StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+        });
+        let mut resp = Response::new(B::from(body.to_string()));
+        *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+
+        let headers = resp.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(RETRY_AFTER, HeaderValue::from(self.retry_after));
+        self.set_headers(headers);
+        resp
+    }
+}
+
+/// Queued request, leaves the queue when admitted, timed out or dropped
+struct Queued<'a>(&'a Admission);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.queued.fetch_sub(1, Ordering::Relaxed);
+        METRICS.queued.dec();
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    admission: Admission,
+    priority: Arc<PriorityFn>,
+    service: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Middleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: From<String>,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let (parts, body) = req.into_parts();
+        let priority = (self.priority)(&parts);
+        let req = Request::from_parts(parts, body);
+        let admission = self.admission.clone();
+
+        Box::pin(async move {
+            if let Ok(_permit) = admission.permits.clone().try_acquire_owned() {
+                return inner.call(req).await;
+            }
+
+            let queued = admission.queued.fetch_add(1, Ordering::Relaxed);
+            METRICS.queued.inc();
+            let in_queue = Queued(&admission);
+            if queued >= priority.max_queued(admission.max_queued) {
+                drop(in_queue);
+                return Ok(admission.reject(priority, "queue_full"));
+            }
+
+            let acquire = admission.permits.clone().acquire_owned();
+            let _permit = match tokio::time::timeout(admission.queue_timeout, acquire).await {
+                Ok(Ok(permit)) => permit,
+                // the semaphore is never closed
+                Ok(Err(_)) | Err(_) => {
+                    drop(in_queue);
+                    return Ok(admission.reject(priority, "queue_timeout"));
+                }
+            };
+            drop(in_queue);
+
+            // the request had to wait, so clients are told to slow down before being rejected
+            let mut resp = inner.call(req).await?;
+            admission.set_headers(resp.headers_mut());
+            Ok(resp)
+        })
+    }
+}
+
+/// Limits the number of requests handled at once and signals the pressure to clients
+/// the same way in every service, so well-behaved clients back off consistently.
+///
+/// Requests over `max_in_flight` wait in a queue of up to `max_queued` requests for
+/// 1 second by default. Requests that had to wait are answered with `RateLimit-Limit`,
+/// `RateLimit-Remaining` (free in-flight slots) and `RateLimit-Reset` headers. When the
+/// queue is full or the wait times out, the request is answered 503 Service Unavailable
+/// with the same headers, `Retry-After` and an `over_capacity` JSON error body.
+///
+/// Requests have `Priority::Normal` by default, classes can be derived from the route
+/// or audience with `with_priority_fn`. `Low` requests are shed as soon as all in-flight
+/// slots are busy, `Normal` ones once the queue is half full, `High` ones once it's full.
+///
+/// Waiting requests are exported in `admission_queued_requests`, rejected ones in
+/// `admission_rejected_total{priority,reason}`. The response body must implement
+/// `From<String>`.
+///
+/// ```ignore
+/// let router = router.layer(BackpressureLayer::new(256, 1024).with_priority_fn(|parts| {
+///     match parts.extensions.get::<MatchedPath>().map(MatchedPath::as_str) {
+///         Some("/analytics/events") | Some("/prefetch") => Priority::Low,
+///         _ => Priority::High,
+///     }
+/// }));
+/// ```
+#[derive(Clone)]
+pub struct BackpressureLayer {
+    admission: Admission,
+    priority: Arc<PriorityFn>,
+}
+
+impl BackpressureLayer {
+    pub fn new(max_in_flight: usize, max_queued: usize) -> Self {
+        Self {
+            admission: Admission {
+                permits: Arc::new(Semaphore::new(max_in_flight)),
+                max_in_flight,
+                queued: Arc::new(AtomicUsize::new(0)),
+                max_queued,
+                queue_timeout: Duration::from_secs(1),
+                retry_after: 1,
+            },
+            priority: Arc::new(|_| Priority::default()),
+        }
+    }
+
+    /// How long requests wait for admission before being rejected
+    pub fn with_queue_timeout(mut self, queue_timeout: Duration) -> Self {
+        self.admission.queue_timeout = queue_timeout;
+        self
+    }
+
+    /// Seconds clients are told to wait in `Retry-After` and `RateLimit-Reset`, 1 by default
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.admission.retry_after = retry_after.as_secs().max(1);
+        self
+    }
+
+    /// Priority class of the request, e.g. by the route or the audience
+    pub fn with_priority_fn<F>(mut self, priority: F) -> Self
+    where
+        F: Fn(&Parts) -> Priority + Send + Sync + 'static,
+    {
+        self.priority = Arc::new(priority);
+        self
+    }
+}
+
+impl<S> Layer<S> for BackpressureLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            admission: self.admission.clone(),
+            priority: self.priority.clone(),
+            service,
+        }
+    }
+}
@@ -1,9 +1,8 @@
 use std::task::{Context, Poll};
 
-use axum::response::{IntoResponse, Response};
 use futures::future::BoxFuture;
-use http::Request;
-use hyper::{body::HttpBody, Body};
+use http::{Request, Response, StatusCode};
+use http_body::Body as HttpBody;
 use tower::{Layer, Service};
 
 #[derive(Clone)]
@@ -12,12 +11,14 @@ pub struct Middleware<S> {
     service: S,
 }
 
-impl<S> Service<Request<Body>> for Middleware<S>
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Middleware<S>
 where
-    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
     S::Future: Send,
+    ReqBody: HttpBody + Send + 'static,
+    ResBody: Default,
 {
-    type Response = Response;
+    type Response = Response<ResBody>;
     type Error = S::Error;
     type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
@@ -25,7 +26,7 @@ where
         self.service.poll_ready(cx)
     }
 
-    fn call(&mut self, req: Request<Body>) -> Self::Future {
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
         let limit = self.body_size_limit;
         // best practice is to clone the inner service like this
         // see https://github.com/tower-rs/tower/issues/547 for details
@@ -35,8 +36,9 @@ where
         Box::pin(async move {
             if let Some(len) = req.body().size_hint().exact() {
                 if len > limit {
-                    let resp_body: Response = Default::default();
-                    return Ok((http::StatusCode::PAYLOAD_TOO_LARGE, resp_body).into_response());
+                    let mut resp = Response::new(ResBody::default());
+                    *resp.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+                    return Ok(resp);
                 }
             }
 
@@ -45,6 +47,10 @@ where
     }
 }
 
+/// Rejects requests with declared body size over the limit with 413 Payload Too Large.
+///
+/// Works with any request body, the response body must implement `Default`.
+#[derive(Debug, Clone)]
 pub struct BodyLimitLayer {
     body_size_limit: u64,
 }
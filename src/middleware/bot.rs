@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use http::{
+    header::{ACCEPT, ACCEPT_LANGUAGE, CACHE_CONTROL, CONTENT_TYPE, COOKIE, USER_AGENT},
+    request::Parts,
+    HeaderValue, Method, Request, Response, StatusCode,
+};
+use once_cell::sync::Lazy;
+use prometheus::{register_histogram, register_int_counter, Histogram, HistogramOpts, IntCounter};
+use sha2::Sha256;
+use tower::{Layer, Service};
+use tracing::{field, Span};
+
+type HmacSha256 = Hmac<Sha256>;
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    score: Histogram,
+    challenged: IntCounter,
+    rejected: IntCounter,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            score: register_histogram!(HistogramOpts::new(
+                "bot_score",
+                "Bot likelihood score of requests from 0 to 100"
+            )
+            .buckets(vec![10.0, 25.0, 50.0, 75.0, 90.0, 100.0]))
+            .expect("Can't create stats metrics"),
+            challenged: register_int_counter!(
+                "bot_challenged_total",
+                "Requests answered with a challenge for the bot score over the threshold"
+            )
+            .expect("Can't create stats metrics"),
+            rejected: register_int_counter!(
+                "bot_rejected_total",
+                "Requests rejected for the bot score over the threshold"
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+// lowercase substrings of user agents of crawlers, scrapers and http libraries
+const BOT_PATTERNS: [&str; 14] = [
+    "bot",
+    "crawl",
+    "spider",
+    "slurp",
+    "scrapy",
+    "headless",
+    "phantomjs",
+    "curl/",
+    "wget/",
+    "python-requests",
+    "python-urllib",
+    "aiohttp",
+    "go-http-client",
+    "java/",
+];
+
+const MISSING_USER_AGENT: u8 = 40;
+const BOT_USER_AGENT: u8 = 60;
+const MISSING_ACCEPT: u8 = 15;
+const MISSING_ACCEPT_LANGUAGE: u8 = 15;
+const ABNORMAL_RATE: u8 = 40;
+
+// clients tracked for the request rate before the expired ones are dropped
+const MAX_TRACKED_CLIENTS: usize = 100_000;
+// share of the clients dropped oldest first when none of them expired
+const EVICTED_SHARE: usize = 10;
+
+/// Cookie a passed challenge is stored in
+pub const CHALLENGE_COOKIE: &str = "svc_bot_challenge";
+// a passed challenge is valid for the rest of the day and the next one
+const CHALLENGE_PERIOD: u64 = 24 * 3600;
+
+/// Likelihood of the request coming from a bot from 0 to 100, added to the request
+/// extensions by `BotDetectionLayer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BotScore(pub u8);
+
+impl BotScore {
+    /// Score of 50 and over, e.g. a crawler user agent or a missing one with
+    /// an abnormal rate
+    pub fn is_likely_bot(&self) -> bool {
+        self.0 >= 50
+    }
+}
+
+type KeyFn = dyn Fn(&Parts) -> Option<String> + Send + Sync;
+
+struct RateWindow {
+    window: Duration,
+    limit: u32,
+    clients: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateWindow {
+    /// Count the request and tell if the client went over the limit in the current window
+    fn hit(&self, key: String) -> bool {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+
+        if clients.len() >= MAX_TRACKED_CLIENTS && !clients.contains_key(&key) {
+            let window = self.window;
+            clients.retain(|_, (started, _)| now.duration_since(*started) < window);
+
+            // many clients within a window, e.g. a flood from spoofed keys
+            if clients.len() >= MAX_TRACKED_CLIENTS {
+                let mut starts = clients
+                    .values()
+                    .map(|(started, _)| *started)
+                    .collect::<Vec<_>>();
+                let (_, newest_evicted, _) =
+                    starts.select_nth_unstable(MAX_TRACKED_CLIENTS / EVICTED_SHARE);
+                let newest_evicted = *newest_evicted;
+                clients.retain(|_, (started, _)| *started > newest_evicted);
+            }
+        }
+
+        let (started, count) = clients.entry(key).or_insert((now, 0));
+        if now.duration_since(*started) >= self.window {
+            *started = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count > self.limit
+    }
+}
+
+fn score(parts: &Parts, rate: Option<(&RateWindow, &KeyFn)>) -> BotScore {
+    let mut score = 0u8;
+
+    match parts
+        .headers
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+    {
+        None => score += MISSING_USER_AGENT,
+        Some(user_agent) if user_agent.trim().is_empty() => score += MISSING_USER_AGENT,
+        Some(user_agent) => {
+            let user_agent = user_agent.to_ascii_lowercase();
+            if BOT_PATTERNS
+                .iter()
+                .any(|pattern| user_agent.contains(pattern))
+            {
+                score += BOT_USER_AGENT;
+            }
+        }
+    }
+    if !parts.headers.contains_key(ACCEPT) {
+        score += MISSING_ACCEPT;
+    }
+    if !parts.headers.contains_key(ACCEPT_LANGUAGE) {
+        score += MISSING_ACCEPT_LANGUAGE;
+    }
+
+    if let Some((window, key)) = rate {
+        if let Some(key) = key(parts) {
+            if window.hit(key) {
+                score += ABNORMAL_RATE;
+            }
+        }
+    }
+
+    BotScore(score.min(100))
+}
+
+/// Proof of running JavaScript for the user agent, checked before serving requests scoring
+/// over the threshold
+struct Challenge {
+    threshold: u8,
+    key: Vec<u8>,
+}
+
+impl Challenge {
+    fn token(&self, parts: &Parts, period: u64) -> String {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        mac.update(&period.to_be_bytes());
+        if let Some(user_agent) = parts.headers.get(USER_AGENT) {
+            mac.update(user_agent.as_bytes());
+        }
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    }
+
+    fn passed(&self, parts: &Parts, period: u64) -> bool {
+        let cookie = parts
+            .headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(name, _)| *name == CHALLENGE_COOKIE)
+            .map(|(_, value)| value);
+
+        match cookie {
+            Some(cookie) => [period, period.saturating_sub(1)]
+                .iter()
+                .any(|period| self.token(parts, *period) == cookie),
+            None => false,
+        }
+    }
+
+    /// Page storing the token in the cookie and reloading, so browsers pass
+    /// and clients without JavaScript stay at it
+    fn page(&self, parts: &Parts, period: u64) -> String {
+        format!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Checking your browser</title>\
+             <script>document.cookie=\"{}={}; path=/; max-age={}; SameSite=Lax\";\
+             location.reload();</script></head>\
+             <body><noscript>Enable JavaScript to continue.</noscript></body></html>",
+            CHALLENGE_COOKIE,
+            self.token(parts, period),
+            CHALLENGE_PERIOD
+        )
+    }
+}
+
+fn challenge_period() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / CHALLENGE_PERIOD
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    rate: Option<(Arc<RateWindow>, Arc<KeyFn>)>,
+    challenge: Option<Arc<Challenge>>,
+    reject_above: Option<u8>,
+    service: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Middleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: Default + From<String>,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let (mut parts, body) = req.into_parts();
+        let rate = self
+            .rate
+            .as_ref()
+            .map(|(window, key)| (window.as_ref(), key.as_ref()));
+        let score = score(&parts, rate);
+
+        METRICS.score.observe(score.0 as f64);
+        Span::current().record("bot_score", field::display(score.0));
+
+        if matches!(self.reject_above, Some(threshold) if score.0 > threshold) {
+            METRICS.rejected.inc();
+            return Box::pin(async move {
+                let mut resp = Response::new(ResBody::default());
+                *resp.status_mut() = StatusCode::FORBIDDEN;
+                Ok(resp)
+            });
+        }
+
+        if let Some(challenge) = &self.challenge {
+            let period = challenge_period();
+            let navigation = parts.method == Method::GET || parts.method == Method::HEAD;
+            if score.0 > challenge.threshold && !challenge.passed(&parts, period) {
+                METRICS.challenged.inc();
+                // other requests can't run the page, so they need to pass it before
+                let page = navigation.then(|| challenge.page(&parts, period));
+                return Box::pin(async move {
+                    let mut resp = Response::new(ResBody::default());
+                    *resp.status_mut() = StatusCode::FORBIDDEN;
+                    let headers = resp.headers_mut();
+                    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+                    if let Some(page) = page {
+                        headers.insert(
+                            CONTENT_TYPE,
+                            HeaderValue::from_static("text/html; charset=utf-8"),
+                        );
+                        *resp.body_mut() = ResBody::from(page);
+                    }
+                    Ok(resp)
+                });
+            }
+        }
+
+        parts.extensions.insert(score);
+        let fut = inner.call(Request::from_parts(parts, body));
+        Box::pin(fut)
+    }
+}
+
+/// Scores requests by the likelihood of coming from a bot and adds the score to the
+/// request extensions as `BotScore`, so handlers and metrics can tell scrapers apart.
+///
+/// The score sums the points of the heuristics, up to 100:
+///
+/// * 60 for a user agent of a crawler or an http library, 40 for a missing one
+/// * 15 for a missing `Accept`, 15 for a missing `Accept-Language`
+/// * 40 for more requests of the client within the window than the limit,
+///   only with `with_rate_limit`
+///
+/// Scores are observed in `bot_score`. Requests are only flagged unless a challenge
+/// threshold is set with `with_challenge_above` or a rejection threshold with
+/// `with_reject_above`. The response body must implement `From<String>`:
+///
+/// ```ignore
+/// let catalog = Router::new()
+///     .route("/api/v1/catalog", get(catalog))
+///     .route_layer(
+///         BotDetectionLayer::new()
+///             .with_rate_limit(Duration::from_secs(60), 300, |parts| {
+///                 let ip = parts.headers.get("x-real-ip")?.to_str().ok()?;
+///                 Some(ip.to_owned())
+///             })
+///             .with_challenge_above(50, &config.bot_challenge_key)
+///             .with_reject_above(90),
+///     );
+/// ```
+#[derive(Clone, Default)]
+pub struct BotDetectionLayer {
+    rate: Option<(Arc<RateWindow>, Arc<KeyFn>)>,
+    challenge: Option<Arc<Challenge>>,
+    reject_above: Option<u8>,
+}
+
+impl BotDetectionLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count requests of every client by the key, e.g. the client ip, in fixed windows.
+    /// Requests over `limit` in a window score as abnormal rate, requests without a key
+    /// are not counted.
+    pub fn with_rate_limit<F>(mut self, window: Duration, limit: u32, key: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        let window = RateWindow {
+            window,
+            limit,
+            clients: Mutex::new(HashMap::new()),
+        };
+        self.rate = Some((Arc::new(window), Arc::new(key)));
+        self
+    }
+
+    /// Answer requests scoring over the threshold with a page setting the
+    /// `svc_bot_challenge` cookie with JavaScript and reloading, counted in
+    /// `bot_challenged_total`. Requests with the cookie are served, so browsers pass
+    /// and plain http clients don't. The cookie is signed with `key` for the user agent
+    /// and is valid for a day or two. Only `GET` and `HEAD` get the page, other methods
+    /// are answered 403 Forbidden until the client passed it.
+    pub fn with_challenge_above(mut self, threshold: u8, key: &[u8]) -> Self {
+        self.challenge = Some(Arc::new(Challenge {
+            threshold,
+            key: key.to_vec(),
+        }));
+        self
+    }
+
+    /// Answer 403 Forbidden to requests scoring over the threshold, counted
+    /// in `bot_rejected_total`
+    pub fn with_reject_above(mut self, threshold: u8) -> Self {
+        self.reject_above = Some(threshold);
+        self
+    }
+}
+
+impl<S> Layer<S> for BotDetectionLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            rate: self.rate.clone(),
+            challenge: self.challenge.clone(),
+            reject_above: self.reject_above,
+            service,
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use http::{HeaderName, Request};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram, register_int_counter_vec, Histogram, HistogramOpts, IntCounterVec,
+};
+use tower::{Layer, Service};
+use tracing::{field, Span};
+
+/// Header with the client time in milliseconds since the unix epoch
+pub static CLIENT_TIME: HeaderName = HeaderName::from_static("x-client-time");
+
+// 1 s .. 1 day
+const SKEW_BUCKETS: [f64; 8] = [1.0, 5.0, 30.0, 60.0, 300.0, 900.0, 3600.0, 86400.0];
+
+// larger skews are broken or forged headers rather than clocks
+const MAX_SKEW_MS: i64 = 24 * 3600 * 1000;
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    skew: Histogram,
+    extreme_vec: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            skew: register_histogram!(HistogramOpts::new(
+                "client_clock_skew_seconds",
+                "Absolute difference between client and server clocks"
+            )
+            .buckets(SKEW_BUCKETS.to_vec()))
+            .expect("Can't create stats metrics"),
+            extreme_vec: register_int_counter_vec!(
+                "client_clock_skew_extreme_total",
+                "Requests with client clock skew over the threshold by direction",
+                &["direction"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Difference between the client and server clocks of the current request, positive
+/// when the client clock is ahead.
+///
+/// It includes the time the request took to reach the server, so skews under a second
+/// are noise. Handlers get it with `Option<Extension<ClockSkew>>`, it's missing when the
+/// client sent no valid `X-Client-Time` or it's more than a day off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSkew {
+    millis: i64,
+}
+
+impl ClockSkew {
+    fn from_header(value: &str, now: SystemTime) -> Option<Self> {
+        let client = value.trim().parse::<i64>().ok()?;
+        let server = now.duration_since(UNIX_EPOCH).ok()?.as_millis() as i64;
+        let millis = client.checked_sub(server)?;
+        if millis.abs() > MAX_SKEW_MS {
+            return None;
+        }
+        Some(Self { millis })
+    }
+
+    pub fn millis(&self) -> i64 {
+        self.millis
+    }
+
+    /// Absolute value of the skew
+    pub fn abs(&self) -> Duration {
+        Duration::from_millis(self.millis.unsigned_abs())
+    }
+
+    pub fn is_ahead(&self) -> bool {
+        self.millis > 0
+    }
+
+    /// Convert a time sent by the client, e.g. a scheduled start, to the server clock,
+    /// `None` when the result isn't representable
+    pub fn to_server_time(&self, client_time: SystemTime) -> Option<SystemTime> {
+        if self.is_ahead() {
+            client_time.checked_sub(self.abs())
+        } else {
+            client_time.checked_add(self.abs())
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    threshold: Duration,
+    service: S,
+}
+
+impl<S, B> Service<Request<B>> for Middleware<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        let skew = req
+            .headers()
+            .get(&CLIENT_TIME)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| ClockSkew::from_header(value, SystemTime::now()));
+
+        if let Some(skew) = skew {
+            METRICS.skew.observe(skew.abs().as_secs_f64());
+            if skew.abs() >= self.threshold {
+                let direction = if skew.is_ahead() { "ahead" } else { "behind" };
+                METRICS.extreme_vec.with_label_values(&[direction]).inc();
+                Span::current().record("clock_skew_ms", field::display(skew.millis()));
+            }
+            req.extensions_mut().insert(skew);
+        }
+
+        self.service.call(req)
+    }
+}
+
+/// Measures the skew of the client clock from `X-Client-Time` and adds it to the request
+/// extensions as `ClockSkew`.
+///
+/// Absolute skews are observed in `client_clock_skew_seconds`. Skews over the threshold,
+/// 5 minutes by default, are counted in `client_clock_skew_extreme_total{direction}`
+/// and recorded to the `clock_skew_ms` field of the request span.
+#[derive(Debug, Clone)]
+pub struct ClockSkewLayer {
+    threshold: Duration,
+}
+
+impl Default for ClockSkewLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClockSkewLayer {
+    pub fn new() -> Self {
+        Self {
+            threshold: Duration::from_secs(300),
+        }
+    }
+
+    pub fn with_threshold(mut self, threshold: Duration) -> Self {
+        self.threshold = threshold;
+        self
+    }
+}
+
+impl<S> Layer<S> for ClockSkewLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            threshold: self.threshold,
+            service,
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Buf;
+use futures::future::BoxFuture;
+use http::{header::CONTENT_ENCODING, Request, Response};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use once_cell::sync::Lazy;
+use pin_project_lite::pin_project;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use tower::{Layer, Service};
+use tower_http::compression::predicate::{And, NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::{Compression, CompressionLayer as TowerCompressionLayer};
+
+pub use tower_http::CompressionLevel;
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    input_vec: IntCounterVec,
+    saved_vec: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            input_vec: register_int_counter_vec!(
+                "response_compression_input_bytes_total",
+                "Size of compressed response bodies before compression",
+                &["encoding"]
+            )
+            .expect("Can't create stats metrics"),
+            saved_vec: register_int_counter_vec!(
+                "response_compression_saved_bytes_total",
+                "Bytes saved by response compression",
+                &["encoding"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+type CompressionPredicate =
+    And<And<And<SizeAbove, NotForContentType>, NotForContentType>, NotForContentType>;
+
+/// Uncompressed size of the current response body, counted under the compression
+#[derive(Clone, Default)]
+struct InputBytes(Arc<AtomicU64>);
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    service: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Middleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = Response<CountingBody<ResBody>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let input = InputBytes::default();
+        req.extensions_mut().insert(input.clone());
+        let fut = inner.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            let encoding = res
+                .headers()
+                .get(CONTENT_ENCODING)
+                .and_then(|encoding| encoding.to_str().ok())
+                .map(|encoding| encoding.to_owned());
+
+            Ok(res.map(|body| CountingBody {
+                inner: body,
+                bytes: 0,
+                report: encoding.map(|encoding| (encoding, input)),
+            }))
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct InputMiddleware<S> {
+    service: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for InputMiddleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = Response<InputBody<ResBody>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let input = req.extensions().get::<InputBytes>().cloned();
+        let fut = inner.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            Ok(res.map(|body| InputBody { inner: body, input }))
+        })
+    }
+}
+
+/// Compresses responses with brotli, zstd or gzip, whichever the client prefers.
+///
+/// Responses under the minimum size, images, gRPC and SSE aren't compressed. Bytes saved
+/// by compression are counted as `response_compression_saved_bytes_total{encoding}`.
+#[derive(Debug, Clone)]
+pub struct CompressionLayer {
+    level: CompressionLevel,
+    min_size: u16,
+    br: bool,
+    gzip: bool,
+    zstd: bool,
+}
+
+impl CompressionLayer {
+    /// Create new layer with every algorithm at the default level, compressing bodies
+    /// of at least 1 KiB
+    pub fn new() -> Self {
+        Self {
+            level: CompressionLevel::Default,
+            min_size: 1024,
+            br: true,
+            gzip: true,
+            zstd: true,
+        }
+    }
+
+    /// Compression level of every algorithm, lower levels save CPU
+    pub fn with_level(mut self, level: CompressionLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Don't compress bodies with known size under `min_size` bytes
+    pub fn with_min_size(mut self, min_size: u16) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    pub fn with_br(mut self, enabled: bool) -> Self {
+        self.br = enabled;
+        self
+    }
+
+    pub fn with_gzip(mut self, enabled: bool) -> Self {
+        self.gzip = enabled;
+        self
+    }
+
+    pub fn with_zstd(mut self, enabled: bool) -> Self {
+        self.zstd = enabled;
+        self
+    }
+}
+
+impl Default for CompressionLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for CompressionLayer {
+    type Service = Middleware<Compression<InputMiddleware<S>, CompressionPredicate>>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        let predicate = SizeAbove::new(self.min_size)
+            .and(NotForContentType::GRPC)
+            .and(NotForContentType::IMAGES)
+            .and(NotForContentType::SSE);
+
+        let compression = TowerCompressionLayer::new()
+            .quality(self.level)
+            .br(self.br)
+            .gzip(self.gzip)
+            .zstd(self.zstd)
+            .compress_when(predicate);
+
+        Middleware {
+            service: compression.layer(InputMiddleware { service }),
+        }
+    }
+}
+
+pin_project! {
+    /// Response body before compression
+    pub struct InputBody<B> {
+        #[pin]
+        inner: B,
+        input: Option<InputBytes>,
+    }
+}
+
+impl<B> HttpBody for InputBody<B>
+where
+    B: HttpBody,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let poll = this.inner.poll_frame(cx);
+        if let (Poll::Ready(Some(Ok(frame))), Some(input)) = (&poll, this.input) {
+            if let Some(data) = frame.data_ref() {
+                input
+                    .0
+                    .fetch_add(data.remaining() as u64, Ordering::Relaxed);
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+pin_project! {
+    /// Response body after compression, reports the saved bytes once finished
+    pub struct CountingBody<B> {
+        #[pin]
+        inner: B,
+        bytes: u64,
+        report: Option<(String, InputBytes)>,
+    }
+}
+
+impl<B> HttpBody for CountingBody<B>
+where
+    B: HttpBody,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let poll = this.inner.poll_frame(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    *this.bytes += data.remaining() as u64;
+                }
+            }
+            // only finished bodies are reported, the input of aborted ones is ahead of the output
+            Poll::Ready(None) => {
+                if let Some((encoding, input)) = this.report.take() {
+                    let input = input.0.load(Ordering::Relaxed);
+                    let saved = input.saturating_sub(*this.bytes);
+                    METRICS
+                        .input_vec
+                        .with_label_values(&[&encoding])
+                        .inc_by(input);
+                    METRICS
+                        .saved_vec
+                        .with_label_values(&[&encoding])
+                        .inc_by(saved);
+                }
+            }
+            _ => {}
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -1,12 +1,23 @@
 use std::time::Duration;
 
-use http::{
-    header::{HeaderName, AUTHORIZATION, CONTENT_TYPE},
-    Method,
-};
+use http::{header::HeaderName, Method};
 use tower::Layer;
 use tower_http::cors::{Any, Cors, CorsLayer as TowerCorsLayer};
 
+pub(crate) const ALLOW_METHODS: [&str; 5] = ["GET", "PUT", "POST", "PATCH", "DELETE"];
+
+pub(crate) const ALLOW_HEADERS: [&str; 7] = [
+    "authorization",
+    "content-type",
+    "ulms-app-audience",
+    "ulms-scope",
+    "ulms-app-version",
+    "ulms-app-label",
+    "x-agent-label",
+];
+
+pub(crate) const MAX_AGE: Duration = Duration::from_secs(3600);
+
 #[derive(Default, Clone)]
 pub struct CorsLayer;
 
@@ -20,25 +31,21 @@ impl<S> Layer<S> for CorsLayer {
     type Service = Cors<S>;
 
     fn layer(&self, inner: S) -> Self::Service {
+        let methods = ALLOW_METHODS
+            .iter()
+            .map(|m| Method::from_bytes(m.as_bytes()).expect("CORS method must be valid"))
+            .collect::<Vec<_>>();
+        let headers = ALLOW_HEADERS
+            .iter()
+            .copied()
+            .map(HeaderName::from_static)
+            .collect::<Vec<_>>();
+
         let cors = TowerCorsLayer::new()
-            .allow_methods([
-                Method::GET,
-                Method::PUT,
-                Method::POST,
-                Method::PATCH,
-                Method::DELETE,
-            ])
-            .allow_headers([
-                AUTHORIZATION,
-                CONTENT_TYPE,
-                HeaderName::from_static("ulms-app-audience"),
-                HeaderName::from_static("ulms-scope"),
-                HeaderName::from_static("ulms-app-version"),
-                HeaderName::from_static("ulms-app-label"),
-                HeaderName::from_static("x-agent-label"),
-            ])
+            .allow_methods(methods)
+            .allow_headers(headers)
             .allow_origin(Any)
-            .max_age(Duration::from_secs(3600));
+            .max_age(MAX_AGE);
 
         cors.layer(inner)
     }
@@ -0,0 +1,262 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::extract::FromRequestParts;
+use bytes::Buf;
+use futures::future::BoxFuture;
+use http::{request::Parts, Request, Response};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use once_cell::sync::Lazy;
+use pin_project_lite::pin_project;
+use prometheus::{
+    register_counter_vec, register_int_counter_vec, Counter, CounterVec, IntCounter, IntCounterVec,
+};
+use tower::{Layer, Service};
+
+use crate::extractors::AccountIdExtractor;
+
+// audience label of requests from audiences out of the list and without one
+const OTHER: &str = "other";
+const NONE: &str = "none";
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    requests_vec: IntCounterVec,
+    cpu_vec: CounterVec,
+    egress_vec: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            requests_vec: register_int_counter_vec!(
+                "audience_requests_total",
+                "Requests by audience",
+                &["audience"]
+            )
+            .expect("Can't create stats metrics"),
+            cpu_vec: register_counter_vec!(
+                "audience_cpu_seconds_total",
+                "Time handlers spent being polled on runtime threads by audience",
+                &["audience"]
+            )
+            .expect("Can't create stats metrics"),
+            egress_vec: register_int_counter_vec!(
+                "audience_egress_bytes_total",
+                "Response body bytes sent by audience",
+                &["audience"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+type AudienceFn = dyn Fn(&Parts) -> Option<String> + Send + Sync;
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    audiences: Arc<HashSet<String>>,
+    audience: Option<Arc<AudienceFn>>,
+    service: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Middleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = Response<EgressBody<ResBody>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let (mut parts, body) = req.into_parts();
+        let audiences = self.audiences.clone();
+        let audience_fn = self.audience.clone();
+
+        Box::pin(async move {
+            let audience = match audience_fn {
+                Some(audience_fn) => audience_fn(&parts),
+                None => AccountIdExtractor::from_request_parts(&mut parts, &())
+                    .await
+                    .ok()
+                    .map(|AccountIdExtractor(account_id)| account_id.audience().to_owned()),
+            };
+            let label = match audience {
+                Some(audience) if audiences.contains(&audience) => audience,
+                Some(_) => OTHER.to_owned(),
+                None => NONE.to_owned(),
+            };
+
+            METRICS.requests_vec.with_label_values(&[&label]).inc();
+
+            CostFuture {
+                inner: inner.call(Request::from_parts(parts, body)),
+                busy: BusyTime {
+                    counter: METRICS.cpu_vec.with_label_values(&[&label]),
+                    elapsed: Duration::ZERO,
+                },
+                egress: Some(METRICS.egress_vec.with_label_values(&[&label])),
+            }
+            .await
+        })
+    }
+}
+
+/// Exports per-audience totals for attributing infrastructure costs to tenants:
+/// `audience_requests_total`, `audience_cpu_seconds_total` and `audience_egress_bytes_total`.
+///
+/// CPU time is estimated as in `CpuTimeLayer`, by the time the handler future spends
+/// being polled. Egress is counted from the response body frames as they are sent,
+/// so it includes streamed bodies and excludes headers.
+///
+/// The audience is the one of the authenticated account by default, as in `QuotaLayer`,
+/// so the same `AccountIdExtractor` requirements apply. Unlike `QuotaLayer` nothing is
+/// limited. Only the listed audiences get their own label values, the rest are counted
+/// as `other` and requests failing authentication as `none`:
+///
+/// ```ignore
+/// let router = router.layer(CostAttributionLayer::new(&config.billed_audiences));
+/// ```
+#[derive(Clone)]
+pub struct CostAttributionLayer {
+    audiences: Arc<HashSet<String>>,
+    audience: Option<Arc<AudienceFn>>,
+}
+
+impl CostAttributionLayer {
+    /// Create new layer labeling the `audiences` of authenticated accounts
+    pub fn new<A: AsRef<str>>(audiences: &[A]) -> Self {
+        Self {
+            audiences: Arc::new(
+                audiences
+                    .iter()
+                    .map(|audience| audience.as_ref().to_owned())
+                    .collect(),
+            ),
+            audience: None,
+        }
+    }
+
+    /// Take the audience from the request another way, e.g. from the `ulms-app-audience`
+    /// header, which isn't authenticated, so only when clients are trusted:
+    ///
+    /// ```ignore
+    /// CostAttributionLayer::new(&audiences).with_audience_fn(|parts| {
+    ///     let audience = parts.headers.get("ulms-app-audience")?.to_str().ok()?;
+    ///     Some(audience.to_owned())
+    /// })
+    /// ```
+    pub fn with_audience_fn<F>(mut self, audience: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        self.audience = Some(Arc::new(audience));
+        self
+    }
+}
+
+impl<S> Layer<S> for CostAttributionLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            audiences: self.audiences.clone(),
+            audience: self.audience.clone(),
+            service,
+        }
+    }
+}
+
+/// Poll time accumulated so far, recorded once the future completes or is dropped
+struct BusyTime {
+    counter: Counter,
+    elapsed: Duration,
+}
+
+impl Drop for BusyTime {
+    fn drop(&mut self) {
+        self.counter.inc_by(self.elapsed.as_secs_f64());
+    }
+}
+
+pin_project! {
+    pub struct CostFuture<F> {
+        #[pin]
+        inner: F,
+        busy: BusyTime,
+        egress: Option<IntCounter>,
+    }
+}
+
+impl<F, B, E> Future for CostFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<EgressBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let started = Instant::now();
+        let poll = this.inner.poll(cx);
+        this.busy.elapsed += started.elapsed();
+
+        let res = ready!(poll)?;
+        let egress = this.egress.take().expect("polled after completion");
+        Poll::Ready(Ok(res.map(|inner| EgressBody { inner, egress })))
+    }
+}
+
+pin_project! {
+    /// Body counting the bytes of its frames to the audience egress
+    pub struct EgressBody<B> {
+        #[pin]
+        inner: B,
+        egress: IntCounter,
+    }
+}
+
+impl<B> HttpBody for EgressBody<B>
+where
+    B: HttpBody,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        if let Some(Ok(frame)) = &frame {
+            if let Some(data) = frame.data_ref() {
+                this.egress.inc_by(data.remaining() as u64);
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,126 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::extract::MatchedPath;
+use http::Request;
+use once_cell::sync::Lazy;
+use pin_project_lite::pin_project;
+use prometheus::{register_counter_vec, CounterVec};
+use tower::{Layer, Service};
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+// path label of requests without a matched route
+const UNMATCHED_PATH: &str = "unmatched";
+
+struct Metrics {
+    cpu_vec: CounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            cpu_vec: register_counter_vec!(
+                "handler_cpu_seconds_total",
+                "Time handlers spent being polled on runtime threads",
+                &["path"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<Request<B>> for Middleware<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BusyTimeFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let path = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str().trim_start_matches('/').replace('/', "_"))
+            .unwrap_or_else(|| UNMATCHED_PATH.to_owned());
+
+        BusyTimeFuture {
+            inner: self.service.call(req),
+            busy: BusyTime {
+                path,
+                elapsed: Duration::ZERO,
+            },
+        }
+    }
+}
+
+/// Experimental: attributes the time handlers spend being polled to their routes as the
+/// `handler_cpu_seconds_total{path}` counter, to find endpoints burning CPU rather than
+/// waiting on IO.
+///
+/// Poll time is wall time spent inside the handler future's `poll` on a runtime thread,
+/// so it also includes blocking calls made there, and excludes response body streaming
+/// and work moved to other tasks. Add it with `route_layer` so the route is known:
+///
+/// ```ignore
+/// Router::new()
+///     .route("/api/v1/reports", post(report))
+///     .route_layer(CpuTimeLayer);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuTimeLayer;
+
+impl<S> Layer<S> for CpuTimeLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware { service }
+    }
+}
+
+/// Poll time accumulated so far, recorded once the future completes or is dropped
+struct BusyTime {
+    path: String,
+    elapsed: Duration,
+}
+
+impl Drop for BusyTime {
+    fn drop(&mut self) {
+        METRICS
+            .cpu_vec
+            .with_label_values(&[&self.path])
+            .inc_by(self.elapsed.as_secs_f64());
+    }
+}
+
+pin_project! {
+    pub struct BusyTimeFuture<F> {
+        #[pin]
+        inner: F,
+        busy: BusyTime,
+    }
+}
+
+impl<F: Future> Future for BusyTimeFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let started = Instant::now();
+        let poll = this.inner.poll(cx);
+        this.busy.elapsed += started.elapsed();
+        poll
+    }
+}
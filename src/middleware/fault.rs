@@ -0,0 +1,206 @@
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::{extract::State, routing, Json, Router};
+use futures::future::BoxFuture;
+use http::{request::Parts, Request, Response, StatusCode};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use serde::{Deserialize, Serialize};
+use tower::{Layer, Service};
+use tracing::warn;
+
+static INJECTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "injected_faults_total",
+        "Faults injected into requests by kind",
+        &["kind"]
+    )
+    .expect("Can't create stats metrics")
+});
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Fault {
+    /// Delay the request before handling it
+    Latency { ms: u64 },
+    /// Answer with the status without handling the request
+    Error { status: u16 },
+    /// Never answer, the client gets its own timeout
+    Drop,
+}
+
+impl Fault {
+    fn kind(&self) -> &'static str {
+        match self {
+            Fault::Latency { .. } => "latency",
+            Fault::Error { .. } => "error",
+            Fault::Drop => "drop",
+        }
+    }
+}
+
+/// Fault injected into `percent` of the requests matching every set filter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FaultRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
+    /// Header name and value, e.g. to break only the requests of a test account
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<(String, String)>,
+    pub percent: f64,
+    pub fault: Fault,
+}
+
+impl FaultRule {
+    fn matches(&self, parts: &Parts) -> bool {
+        if let Some(prefix) = &self.path_prefix {
+            if !parts.uri.path().starts_with(prefix.as_str()) {
+                return false;
+            }
+        }
+        if let Some((name, value)) = &self.header {
+            if parts.headers.get(name.as_str()).map(|v| v.as_bytes()) != Some(value.as_bytes()) {
+                return false;
+            }
+        }
+        fastrand::f64() * 100.0 < self.percent
+    }
+}
+
+/// Rules of a `FaultInjectionLayer`, shared with the admin routes changing them
+#[derive(Clone, Default)]
+pub struct FaultInjection {
+    rules: Arc<RwLock<Vec<FaultRule>>>,
+}
+
+impl FaultInjection {
+    /// Create new rule set without rules, so no faults are injected
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rules(&self) -> Vec<FaultRule> {
+        self.rules
+            .read()
+            .expect("fault rules lock poisoned")
+            .clone()
+    }
+
+    pub fn set_rules(&self, rules: Vec<FaultRule>) {
+        warn!(?rules, "Fault injection rules changed");
+        *self.rules.write().expect("fault rules lock poisoned") = rules;
+    }
+
+    /// Routes reading and replacing the rules on `GET` and `PUT /admin/faults` and removing
+    /// them on `DELETE /admin/faults`, meant to be passed to `admin_router`
+    pub fn admin_routes(&self) -> Router {
+        Router::new()
+            .route(
+                "/admin/faults",
+                routing::get(|State(faults): State<FaultInjection>| async move {
+                    Json(faults.rules())
+                })
+                .put(
+                    |State(faults): State<FaultInjection>, Json(rules): Json<Vec<FaultRule>>| async move {
+                        faults.set_rules(rules);
+                        StatusCode::NO_CONTENT
+                    },
+                )
+                .delete(|State(faults): State<FaultInjection>| async move {
+                    faults.set_rules(vec![]);
+                    StatusCode::NO_CONTENT
+                }),
+            )
+            .with_state(self.clone())
+    }
+
+    fn pick(&self, parts: &Parts) -> Option<Fault> {
+        let rules = self.rules.read().expect("fault rules lock poisoned");
+        rules
+            .iter()
+            .find(|rule| rule.matches(parts))
+            .map(|rule| rule.fault.clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    faults: FaultInjection,
+    service: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Middleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let (parts, body) = req.into_parts();
+        let fault = self.faults.pick(&parts);
+        let req = Request::from_parts(parts, body);
+
+        Box::pin(async move {
+            if let Some(fault) = &fault {
+                INJECTED.with_label_values(&[fault.kind()]).inc();
+            }
+
+            match fault {
+                None => {}
+                Some(Fault::Latency { ms }) => tokio::time::sleep(Duration::from_millis(ms)).await,
+                Some(Fault::Error { status }) => {
+                    let mut resp = Response::new(ResBody::default());
+                    *resp.status_mut() =
+                        StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                    return Ok(resp);
+                }
+                Some(Fault::Drop) => futures::future::pending::<()>().await,
+            }
+
+            inner.call(req).await
+        })
+    }
+}
+
+/// Injects latency, errors or hanging responses into a share of requests for game days.
+///
+/// Nothing is injected until rules are set, usually through `FaultInjection::admin_routes`.
+/// The first matching rule applies, injected faults are counted in
+/// `injected_faults_total{kind}`. Keep it off production with a config flag.
+#[derive(Clone)]
+pub struct FaultInjectionLayer {
+    faults: FaultInjection,
+}
+
+impl FaultInjectionLayer {
+    pub fn new(faults: FaultInjection) -> Self {
+        Self { faults }
+    }
+}
+
+impl<S> Layer<S> for FaultInjectionLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            faults: self.faults.clone(),
+            service,
+        }
+    }
+}
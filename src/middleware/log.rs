@@ -1,7 +1,8 @@
+use std::borrow::Cow;
 use std::time::Duration;
 
 use http::{Method, Request, Response};
-use hyper::{body::HttpBody, Body};
+use http_body::Body as HttpBody;
 use tower::Layer;
 use tower_http::classify::{ServerErrorsAsFailures, SharedClassifier};
 use tower_http::trace::TraceLayer;
@@ -13,11 +14,20 @@ use tracing::{
 };
 
 #[derive(Default, Clone)]
-pub struct LogLayer;
+pub struct LogLayer {
+    scrub_pii: bool,
+}
 
 impl LogLayer {
     pub fn new() -> Self {
-        Self
+        Self::default()
+    }
+
+    /// Mask emails, phone numbers and tokens in the logged path and query
+    #[cfg(feature = "pii-scrubbing")]
+    pub fn with_pii_scrubbing(mut self) -> Self {
+        self.scrub_pii = true;
+        self
     }
 }
 
@@ -31,7 +41,9 @@ impl<S> Layer<S> for LogLayer {
 
     fn layer(&self, service: S) -> Self::Service {
         let layer = TraceLayer::new_for_http()
-            .make_span_with(SpanMaker)
+            .make_span_with(SpanMaker {
+                scrub_pii: self.scrub_pii,
+            })
             .on_response(OnResp);
 
         layer.layer(service)
@@ -39,17 +51,33 @@ impl<S> Layer<S> for LogLayer {
 }
 
 #[derive(Debug, Clone)]
-pub struct SpanMaker;
+pub struct SpanMaker {
+    scrub_pii: bool,
+}
+
+impl<B> MakeSpan<B> for SpanMaker
+where
+    B: HttpBody,
+{
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let mut path = Cow::Borrowed(request.uri().path());
+        let mut query = request.uri().query().map(Cow::Borrowed);
+        if self.scrub_pii {
+            path = scrub(path);
+            query = query.map(scrub);
+        }
 
-impl MakeSpan<Body> for SpanMaker {
-    fn make_span(&mut self, request: &Request<Body>) -> Span {
         let span = tracing::error_span!(
             "http-api-request",
             status_code = Empty,
-            path = request.uri().path(),
-            query = request.uri().query(),
+            path = &*path,
+            query = query.as_deref(),
             method = %request.method(),
             account_id = Empty,
+            locale = Empty,
+            client = Empty,
+            bot_score = Empty,
+            clock_skew_ms = Empty,
             body_size = Empty,
             kind = Empty,
             detail = Empty,
@@ -58,7 +86,7 @@ impl MakeSpan<Body> for SpanMaker {
         if request.method() != Method::GET && request.method() != Method::OPTIONS {
             span.record(
                 "body_size",
-                &field::debug(request.body().size_hint().upper()),
+                field::debug(request.body().size_hint().upper()),
             );
         }
 
@@ -66,12 +94,25 @@ impl MakeSpan<Body> for SpanMaker {
     }
 }
 
+#[cfg(feature = "pii-scrubbing")]
+fn scrub(value: Cow<'_, str>) -> Cow<'_, str> {
+    match crate::pii::scrub(&value) {
+        Cow::Borrowed(_) => value,
+        Cow::Owned(scrubbed) => Cow::Owned(scrubbed),
+    }
+}
+
+#[cfg(not(feature = "pii-scrubbing"))]
+fn scrub(value: Cow<'_, str>) -> Cow<'_, str> {
+    value
+}
+
 #[derive(Debug, Clone)]
 pub struct OnResp;
 
 impl<B> OnResponse<B> for OnResp {
     fn on_response(self, response: &Response<B>, latency: Duration, span: &Span) {
-        span.record("status_code", &field::debug(response.status()));
+        span.record("status_code", field::debug(response.status()));
         if response.status().is_client_error() || response.status().is_server_error() {
             error!("response generated in {:?}", latency)
         } else {
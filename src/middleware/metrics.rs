@@ -1,44 +1,105 @@
 use std::collections::HashMap;
 use std::convert::{Infallible, TryFrom};
 use std::iter::FromIterator;
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-use axum::body::Body;
+use axum::body::{Body, Bytes};
 use axum::routing::Router;
+use axum::BoxError;
+use bytes::Buf;
 use futures::future::BoxFuture;
-use hyper::Request;
-use hyper::Response;
-use hyper::{Method, StatusCode};
+use http::{Method, Request, Response, StatusCode};
+use http_body::{Body as HttpBody, Frame, SizeHint};
 use once_cell::sync::{Lazy, OnceCell};
+use pin_project_lite::pin_project;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
 use prometheus::{
-    register_histogram_vec, register_int_counter_vec, Histogram, HistogramTimer, HistogramVec,
+    register_histogram_vec, register_int_counter_vec, Histogram, HistogramOpts, HistogramVec,
     IntCounter, IntCounterVec,
 };
 use tower::{Layer, Service};
 use tracing::error;
 
+use super::summary;
+
 static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
 
+static DURATIONS: Lazy<Durations> = Lazy::new(|| {
+    let durations = Durations::new();
+    prometheus::register(Box::new(durations.clone())).expect("Can't create stats metrics");
+    durations
+});
+
+// 64 B .. 64 MiB
+const SIZE_BUCKETS: [f64; 11] = [
+    64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0,
+    67108864.0,
+];
+
+// 64 KiB/s .. 1 GiB/s
+const RATE_BUCKETS: [f64; 8] = [
+    65536.0,
+    262144.0,
+    1048576.0,
+    4194304.0,
+    16777216.0,
+    67108864.0,
+    268435456.0,
+    1073741824.0,
+];
+
+const DURATION_NAME: &str = "request_duration";
+const DURATION_HELP: &str = "Request duration";
+const DURATION_LABELS: [&str; 3] = ["path", "method", "success"];
+
+// path label of requests handled by the metered fallback
+const UNMATCHED_PATH: &str = "unmatched";
+
+// transfer rate is only meaningful for bodies of at least this size
+const LARGE_BODY_SIZE: u64 = 1024 * 1024;
+
 struct Metrics {
-    duration_vec: HistogramVec,
     body_size_vec: HistogramVec,
+    body_rate_vec: HistogramVec,
+    response_size_vec: HistogramVec,
+    response_rate_vec: HistogramVec,
     status_vec: IntCounterVec,
+    status_class_vec: IntCounterVec,
 }
 
 impl Metrics {
     fn new() -> Self {
         Metrics {
-            duration_vec: register_histogram_vec!(
-                "request_duration",
-                "Request duration",
-                &["path", "method"]
-            )
-            .expect("Can't create stats metrics"),
             body_size_vec: register_histogram_vec!(
                 "request_body_size",
                 "Request body size",
-                &["path", "method"]
+                &["path", "method"],
+                SIZE_BUCKETS.to_vec()
+            )
+            .expect("Can't create stats metrics"),
+            body_rate_vec: register_histogram_vec!(
+                "request_body_transfer_rate",
+                "Request body transfer rate of large bodies, bytes per second",
+                &["path", "method"],
+                RATE_BUCKETS.to_vec()
+            )
+            .expect("Can't create stats metrics"),
+            response_size_vec: register_histogram_vec!(
+                "response_body_size",
+                "Response body size",
+                &["path", "method"],
+                SIZE_BUCKETS.to_vec()
+            )
+            .expect("Can't create stats metrics"),
+            response_rate_vec: register_histogram_vec!(
+                "response_body_transfer_rate",
+                "Response body transfer rate of large bodies, bytes per second",
+                &["path", "method"],
+                RATE_BUCKETS.to_vec()
             )
             .expect("Can't create stats metrics"),
             status_vec: register_int_counter_vec!(
@@ -47,7 +108,102 @@ impl Metrics {
                 &["path", "method", "status_code"]
             )
             .expect("Can't create stats metrics"),
+            status_class_vec: register_int_counter_vec!(
+                "request_status_class_total",
+                "Request stats by status class",
+                &["path", "method", "class"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct DurationVecs {
+    by_buckets: Vec<(Vec<f64>, HistogramVec)>,
+    // a path in several vectors would be collected as duplicate series
+    buckets_by_path: HashMap<String, Vec<f64>>,
+}
+
+/// `request_duration` histograms of all routes, one vector per set of buckets, collected
+/// as a single metric. Clones share the vectors.
+#[derive(Clone)]
+struct Durations {
+    desc: Desc,
+    vecs: Arc<Mutex<DurationVecs>>,
+}
+
+impl Durations {
+    fn new() -> Self {
+        let desc = Desc::new(
+            DURATION_NAME.to_owned(),
+            DURATION_HELP.to_owned(),
+            DURATION_LABELS.iter().map(|l| l.to_string()).collect(),
+            HashMap::new(),
+        )
+        .expect("Can't create stats metrics");
+
+        Self {
+            desc,
+            vecs: Arc::new(Mutex::new(DurationVecs::default())),
+        }
+    }
+
+    /// Histogram vector of the path with the buckets, created on first use. A path keeps
+    /// the buckets it was first seen with.
+    fn vec(&self, path: &str, buckets: &[f64]) -> HistogramVec {
+        let mut vecs = self.lock();
+        let buckets = match vecs.buckets_by_path.get(path) {
+            Some(registered) if registered.as_slice() != buckets => {
+                error!(
+                    path,
+                    ?buckets,
+                    ?registered,
+                    "Routes with the same path have different duration buckets, keeping the first ones"
+                );
+                registered.clone()
+            }
+            Some(registered) => registered.clone(),
+            None => {
+                vecs.buckets_by_path
+                    .insert(path.to_owned(), buckets.to_vec());
+                buckets.to_vec()
+            }
+        };
+
+        if let Some((_, vec)) = vecs.by_buckets.iter().find(|(b, _)| *b == buckets) {
+            return vec.clone();
+        }
+
+        let opts = HistogramOpts::new(DURATION_NAME, DURATION_HELP).buckets(buckets.clone());
+        let vec = HistogramVec::new(opts, &DURATION_LABELS).expect("Can't create stats metrics");
+        vecs.by_buckets.push((buckets, vec.clone()));
+        vec
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, DurationVecs> {
+        self.vecs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Collector for Durations {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.desc]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let vecs = self.lock();
+        let mut families = vecs.by_buckets.iter().flat_map(|(_, vec)| vec.collect());
+        let mut family = match families.next() {
+            Some(family) => family,
+            None => return vec![],
+        };
+        for mut other in families {
+            family.mut_metric().extend(other.take_metric());
         }
+        vec![family]
     }
 }
 
@@ -88,16 +244,99 @@ impl MethodStatusCounters {
     }
 }
 
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+/// Counters by method and status class, i.e. the first digit of the status code
+#[derive(Clone)]
+struct MethodClassCounters(Arc<HashMap<(Method, u16), OnceCell<IntCounter>>>);
+
+impl MethodClassCounters {
+    fn new(methods: &[Method]) -> Self {
+        let map = methods
+            .iter()
+            .flat_map(|m| (1..=5).map(move |class| ((m.to_owned(), class), OnceCell::new())))
+            .collect();
+        MethodClassCounters(Arc::new(map))
+    }
+
+    fn inc_counter(&self, method: Method, status: StatusCode, path: &str) {
+        let class = status.as_u16() / 100;
+        let counter = self.0.get(&(method.clone(), class)).and_then(|c| {
+            c.get_or_try_init(|| {
+                METRICS
+                    .status_class_vec
+                    .get_metric_with_label_values(&[
+                        path,
+                        method.as_ref(),
+                        STATUS_CLASSES[usize::from(class) - 1],
+                    ])
+                    .map_err(|err| {
+                        error!(
+                            path,
+                            %method,
+                            ?status,
+                            "Creating counter for metrics errored: {:?}", err
+                        );
+                    })
+            })
+            .ok()
+        });
+        if let Some(counter) = counter {
+            counter.inc()
+        }
+    }
+}
+
+/// Configuration of a metered route
+#[derive(Debug, Clone, Default)]
+pub struct MetricsConfig {
+    summary_window: Option<Duration>,
+    buckets: Option<Vec<f64>>,
+}
+
+impl MetricsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record request duration as `request_duration_summary` with p50/p95/p99 over the sliding
+    /// `window` instead of the `request_duration` histogram. The quantiles count every
+    /// request of the window and are accurate within 1%.
+    pub fn with_summary(mut self, window: Duration) -> Self {
+        self.summary_window = Some(window);
+        self
+    }
+
+    /// Bucket upper bounds of the `request_duration` histogram in seconds instead of
+    /// the prometheus default ones, e.g. `prometheus::exponential_buckets(0.0005, 2.0, 12)`
+    /// for sub-10ms endpoints.
+    ///
+    /// Pass the config to `MetricsLayer::with_config` to change the buckets of the whole
+    /// service or to `metered_route_with` for a single route. Routes with the same path
+    /// label must have the same buckets, otherwise the error is logged and the buckets
+    /// of the first route are kept.
+    pub fn with_buckets(mut self, mut buckets: Vec<f64>) -> Self {
+        buckets.retain(|bound| bound.is_finite());
+        buckets.sort_by(f64::total_cmp);
+        buckets.dedup();
+        self.buckets = Some(buckets).filter(|buckets| !buckets.is_empty());
+        self
+    }
+}
+
+/// Counters and timers of a metered route, shared by the axum and actix middlewares
 #[derive(Clone)]
-struct MetricsMiddleware<S> {
-    durations: HashMap<Method, OnceCell<Histogram>>,
+pub(crate) struct RouteMetrics {
+    durations: Arc<HashMap<(Method, bool), OnceCell<Histogram>>>,
+    duration_vec: HistogramVec,
+    summary_window: Option<Duration>,
     stats: MethodStatusCounters,
+    classes: MethodClassCounters,
     path: String,
-    service: S,
 }
 
-impl<S> MetricsMiddleware<S> {
-    fn new(service: S, path: &str) -> Self {
+impl RouteMetrics {
+    pub(crate) fn new(path: &str, config: &MetricsConfig) -> Self {
         let path = path.trim_start_matches('/').replace('/', "_");
         let methods = [
             Method::PUT,
@@ -110,7 +349,7 @@ impl<S> MetricsMiddleware<S> {
         let status_codes = (100..600).filter_map(|x| StatusCode::try_from(x).ok());
         let durations = methods
             .iter()
-            .map(|method| (method.to_owned(), OnceCell::new()))
+            .flat_map(|m| [true, false].map(|success| ((m.to_owned(), success), OnceCell::new())))
             .collect();
         let stats = status_codes
             .flat_map(|s| {
@@ -119,44 +358,105 @@ impl<S> MetricsMiddleware<S> {
                     .map(move |m| ((m.to_owned(), s), OnceCell::new()))
             })
             .collect();
+        let classes = MethodClassCounters::new(&methods);
         Self {
-            durations,
+            durations: Arc::new(durations),
+            duration_vec: DURATIONS.vec(
+                &path,
+                config
+                    .buckets
+                    .as_deref()
+                    .unwrap_or(prometheus::DEFAULT_BUCKETS),
+            ),
+            summary_window: config.summary_window,
             stats,
+            classes,
             path,
-            service,
         }
     }
 
-    fn start_timer(&self, method: Method) -> Option<HistogramTimer> {
-        self.durations
-            .get(&method)
+    pub(crate) fn request_body_observer(&self, method: &Method) -> BodyObserver {
+        BodyObserver::new(
+            &METRICS.body_size_vec,
+            &METRICS.body_rate_vec,
+            &self.path,
+            method,
+        )
+    }
+
+    pub(crate) fn response_body_observer(&self, method: &Method) -> BodyObserver {
+        BodyObserver::new(
+            &METRICS.response_size_vec,
+            &METRICS.response_rate_vec,
+            &self.path,
+            method,
+        )
+    }
+
+    /// Count the response status and observe the request duration
+    pub(crate) fn record(&self, method: Method, status: StatusCode, started: Instant) {
+        self.stats.inc_counter(method.clone(), status, &self.path);
+        self.classes.inc_counter(method.clone(), status, &self.path);
+        self.observe_duration(method, status, started);
+    }
+
+    /// Success means 1xx-3xx status, error responses (e.g. fast 401s) are observed separately
+    fn observe_duration(&self, method: Method, status: StatusCode, started: Instant) {
+        let path = &self.path;
+        let success = !status.is_client_error() && !status.is_server_error();
+        if let Some(window) = self.summary_window {
+            let labels = [
+                path.as_str(),
+                method.as_ref(),
+                if success { "true" } else { "false" },
+            ];
+            summary::observe(labels, window, started.elapsed().as_secs_f64());
+            return;
+        }
+
+        let histogram = self
+            .durations
+            .get(&(method.clone(), success))
             .and_then(|h| {
                 h.get_or_try_init(|| {
-                    METRICS
-                        .duration_vec
-                        .get_metric_with_label_values(&[&self.path, method.as_ref()])
+                    self.duration_vec
+                        .get_metric_with_label_values(&[
+                            path,
+                            method.as_ref(),
+                            if success { "true" } else { "false" },
+                        ])
                         .map_err(|err| {
                             error!(
-                                path = %self.path,
+                                %path,
                                 %method,
                                 "Creating timer for metrics errored: {:?}", err
                             )
                         })
                 })
                 .ok()
-            })
-            .map(|x| x.start_timer())
+            });
+        if let Some(histogram) = histogram {
+            histogram.observe(started.elapsed().as_secs_f64());
+        }
     }
 }
 
+#[derive(Clone)]
+pub struct MetricsMiddleware<S> {
+    metrics: RouteMetrics,
+    service: S,
+}
+
 impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for MetricsMiddleware<S>
 where
-    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S: Service<Request<CountingBody<ReqBody>>, Response = Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
     S::Future: Send + 'static,
-    ReqBody: hyper::body::HttpBody + Send + 'static,
-    ResBody: Send + 'static,
+    ResBody: HttpBody + Send + 'static,
 {
-    type Response = S::Response;
+    type Response = Response<CountingBody<ResBody>>;
     type Error = S::Error;
     type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
@@ -171,51 +471,199 @@ where
         let mut inner = std::mem::replace(&mut self.service, clone);
         let method = req.method().to_owned();
 
-        let path = self.path.clone();
-        let counters = self.stats.clone();
-
-        if let Some(body_size) = req.body().size_hint().upper() {
-            match METRICS
-                .body_size_vec
-                .get_metric_with_label_values(&[&path, method.as_ref()])
-            {
-                Ok(m) => m.observe(body_size as f64),
-                Err(err) => error!(
-                    %path,
-                    %method,
-                    "Failed to record body size: {:?}", err
-                ),
-            }
-        }
+        let metrics = self.metrics.clone();
+        let observer = metrics.request_body_observer(&method);
+        let req = req.map(|body| CountingBody::new(body, observer));
 
-        let timer = self.start_timer(method.clone());
+        let started = Instant::now();
+        let fut = inner.call(req);
 
         Box::pin(async move {
-            let res: Response<ResBody> = inner.call(req).await?;
-            counters.inc_counter(method, res.status(), &path);
-            drop(timer);
-            Ok(res)
+            let res: Response<ResBody> = fut.await?;
+            metrics.record(method.clone(), res.status(), started);
+            let observer = metrics.response_body_observer(&method);
+            Ok(res.map(|body| CountingBody::new(body, observer)))
         })
     }
 }
 
+/// Records the size and, for large bodies, the transfer rate of a body once it's finished or dropped
+pub(crate) struct BodyObserver {
+    size: Option<Histogram>,
+    rate: Option<Histogram>,
+    started: Instant,
+    bytes: u64,
+    finished: bool,
+}
+
+impl BodyObserver {
+    fn new(size_vec: &HistogramVec, rate_vec: &HistogramVec, path: &str, method: &Method) -> Self {
+        let get = |vec: &HistogramVec| {
+            vec.get_metric_with_label_values(&[path, method.as_ref()])
+                .map_err(|err| {
+                    error!(
+                        %path,
+                        %method,
+                        "Failed to record body size: {:?}", err
+                    )
+                })
+                .ok()
+        };
+
+        Self {
+            size: get(size_vec),
+            rate: get(rate_vec),
+            started: Instant::now(),
+            bytes: 0,
+            finished: false,
+        }
+    }
+
+    pub(crate) fn add(&mut self, bytes: u64) {
+        self.bytes += bytes;
+    }
+
+    pub(crate) fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+
+        if let Some(size) = &self.size {
+            size.observe(self.bytes as f64);
+        }
+
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if self.bytes >= LARGE_BODY_SIZE && elapsed > 0.0 {
+            if let Some(rate) = &self.rate {
+                rate.observe(self.bytes as f64 / elapsed);
+            }
+        }
+    }
+}
+
+impl Drop for BodyObserver {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+pin_project! {
+    /// Body counting bytes actually transferred through it
+    pub struct CountingBody<B> {
+        #[pin]
+        inner: B,
+        observer: BodyObserver,
+    }
+}
+
+impl<B> CountingBody<B> {
+    fn new(inner: B, observer: BodyObserver) -> Self {
+        Self { inner, observer }
+    }
+}
+
+impl<B> HttpBody for CountingBody<B>
+where
+    B: HttpBody,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let poll = this.inner.poll_frame(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    this.observer.add(data.remaining() as u64);
+                }
+            }
+            Poll::Ready(None) => this.observer.finish(),
+            _ => {}
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Records request metrics of a service under the `path` label.
+///
+/// Works with any request and response bodies, the inner service receives the request
+/// body wrapped into a byte counting body. Use `MeteredRoute` for axum routers.
 #[derive(Debug, Clone)]
-struct MetricsMiddlewareLayer {
+pub struct MetricsLayer {
     path: String,
+    config: MetricsConfig,
 }
 
-impl MetricsMiddlewareLayer {
-    fn new(path: String) -> Self {
-        Self { path }
+impl MetricsLayer {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            config: MetricsConfig::default(),
+        }
+    }
+
+    pub fn with_config(mut self, config: MetricsConfig) -> Self {
+        self.config = config;
+        self
     }
 }
 
-impl<S> Layer<S> for MetricsMiddlewareLayer {
+impl<S> Layer<S> for MetricsLayer {
     type Service = MetricsMiddleware<S>;
 
     fn layer(&self, service: S) -> Self::Service {
-        MetricsMiddleware::new(service, &self.path)
+        MetricsMiddleware {
+            metrics: RouteMetrics::new(&self.path, &self.config),
+            service,
+        }
+    }
+}
+
+/// Converts the counting request body back into axum body for routes
+#[derive(Clone)]
+pub(crate) struct AxumBody<S> {
+    service: S,
+}
+
+impl<S> Service<Request<CountingBody<Body>>> for AxumBody<S>
+where
+    S: Service<Request<Body>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
     }
+
+    fn call(&mut self, req: Request<CountingBody<Body>>) -> Self::Future {
+        self.service.call(req.map(Body::new))
+    }
+}
+
+/// Route service metered under the `path` label
+pub(crate) fn metered<S>(
+    path: &str,
+    config: MetricsConfig,
+    service: S,
+) -> MetricsMiddleware<AxumBody<S>> {
+    MetricsLayer::new(path)
+        .with_config(config)
+        .layer(AxumBody { service })
 }
 
 pub trait MeteredRoute<H>
@@ -225,20 +673,36 @@ where
     type Output;
 
     fn metered_route(self, path: &str, svc: H) -> Self::Output;
+
+    /// Same as `metered_route` with non-default metrics configuration
+    fn metered_route_with(self, path: &str, svc: H, config: MetricsConfig) -> Self::Output;
+
+    /// Set the fallback service, metered with the fixed `unmatched` path label,
+    /// so requests not matching any route (scanners, broken clients) are visible in metrics
+    fn metered_fallback(self, svc: H) -> Self::Output;
 }
 
-impl<H> MeteredRoute<H> for Router
+impl<H, ResBody> MeteredRoute<H> for Router
 where
-    H: Service<Request<Body>, Response = Response<axum::body::BoxBody>, Error = Infallible>
+    H: Service<Request<Body>, Response = Response<ResBody>, Error = Infallible>
         + Clone
         + Send
         + 'static,
     H::Future: Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Unpin + Send + 'static,
+    ResBody::Error: Into<BoxError>,
 {
     type Output = Router;
 
     fn metered_route(self, path: &str, svc: H) -> Self::Output {
-        let handler = MetricsMiddlewareLayer::new(path.to_owned()).layer(svc);
-        self.route_service(path, handler)
+        self.metered_route_with(path, svc, MetricsConfig::default())
+    }
+
+    fn metered_route_with(self, path: &str, svc: H, config: MetricsConfig) -> Self::Output {
+        self.route_service(path, metered(path, config, svc))
+    }
+
+    fn metered_fallback(self, svc: H) -> Self::Output {
+        self.fallback_service(metered(UNMATCHED_PATH, MetricsConfig::default(), svc))
     }
 }
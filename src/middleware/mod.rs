@@ -1,23 +1,113 @@
+#[cfg(feature = "backpressure-middleware")]
+pub use backpressure::{BackpressureLayer, Priority};
+
 #[cfg(feature = "body-limit-middleware")]
 pub use body_limit::BodyLimitLayer;
 
+#[cfg(feature = "bot-detection-middleware")]
+pub use bot::{BotDetectionLayer, BotScore, CHALLENGE_COOKIE};
+
+#[cfg(feature = "clock-skew-middleware")]
+pub use clock_skew::{ClockSkew, ClockSkewLayer, CLIENT_TIME};
+
+#[cfg(feature = "compression-middleware")]
+pub use compression::{CompressionLayer, CompressionLevel};
+
 #[cfg(feature = "cors-middleware")]
 pub use cors::CorsLayer;
 
+#[cfg(feature = "cost-attribution-middleware")]
+pub use cost::CostAttributionLayer;
+
+#[cfg(feature = "cpu-time-middleware")]
+pub use cpu::CpuTimeLayer;
+
+#[cfg(feature = "fault-injection-middleware")]
+pub use fault::{Fault, FaultInjection, FaultInjectionLayer, FaultRule};
+
 #[cfg(feature = "log-middleware")]
 pub use log::LogLayer;
 
 #[cfg(feature = "metrics-middleware")]
-pub use metrics::MeteredRoute;
+pub use metrics::{CountingBody, MeteredRoute, MetricsConfig, MetricsLayer};
+
+#[cfg(feature = "overhead-middleware")]
+pub use overhead::{OverheadLayer, OverheadMarkLayer};
+
+#[cfg(feature = "preset-middleware")]
+pub use preset::{preset, Preset};
+
+#[cfg(feature = "shard-middleware")]
+pub use shard::{ShardRedirectLayer, SHARD_REDIRECTED};
+
+#[cfg(feature = "slow-poll-middleware")]
+pub use slow_poll::SlowPollLayer;
+
+#[cfg(feature = "stack-builder")]
+pub use stack::{stage, StackBuilder};
+
+#[cfg(feature = "throttle-middleware")]
+pub use throttle::ThrottleLayer;
+
+#[cfg(feature = "timeout-middleware")]
+pub use timeout::{Deadline, TimedRoute, TimeoutLayer};
+
+#[cfg(feature = "actix-middleware")]
+pub mod actix;
+
+#[cfg(feature = "backpressure-middleware")]
+mod backpressure;
 
 #[cfg(feature = "body-limit-middleware")]
 mod body_limit;
 
+#[cfg(feature = "bot-detection-middleware")]
+mod bot;
+
+#[cfg(feature = "clock-skew-middleware")]
+mod clock_skew;
+
+#[cfg(feature = "compression-middleware")]
+mod compression;
+
 #[cfg(feature = "cors-middleware")]
 mod cors;
 
+#[cfg(feature = "cost-attribution-middleware")]
+mod cost;
+
+#[cfg(feature = "cpu-time-middleware")]
+mod cpu;
+
+#[cfg(feature = "fault-injection-middleware")]
+mod fault;
+
 #[cfg(feature = "log-middleware")]
 mod log;
 
 #[cfg(feature = "metrics-middleware")]
-mod metrics;
+pub(crate) mod metrics;
+
+#[cfg(feature = "overhead-middleware")]
+mod overhead;
+
+#[cfg(feature = "preset-middleware")]
+mod preset;
+
+#[cfg(feature = "shard-middleware")]
+mod shard;
+
+#[cfg(feature = "slow-poll-middleware")]
+mod slow_poll;
+
+#[cfg(feature = "stack-builder")]
+mod stack;
+
+#[cfg(feature = "metrics-middleware")]
+mod summary;
+
+#[cfg(feature = "throttle-middleware")]
+mod throttle;
+
+#[cfg(feature = "timeout-middleware")]
+pub(crate) mod timeout;
@@ -0,0 +1,180 @@
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use http::Request;
+use once_cell::sync::Lazy;
+use prometheus::{register_histogram_vec, HistogramVec};
+use tower::{Layer, Service};
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+// 10 µs .. 100 ms
+const OVERHEAD_BUCKETS: [f64; 9] = [
+    0.00001, 0.000025, 0.0001, 0.00025, 0.001, 0.0025, 0.01, 0.025, 0.1,
+];
+
+struct Metrics {
+    overhead_vec: HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            overhead_vec: register_histogram_vec!(
+                "request_middleware_overhead",
+                "Time spent in middleware stack before (ingress) and after (egress) the handler",
+                &["phase"],
+                OVERHEAD_BUCKETS.to_vec()
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Marks {
+    handler_entered: Option<Instant>,
+    handler_exited: Option<Instant>,
+}
+
+/// Handler entry and exit times of the current request, set by `OverheadMarkLayer`
+#[derive(Clone, Default)]
+struct HandlerMarks(Arc<Mutex<Marks>>);
+
+impl HandlerMarks {
+    fn update(&self, f: impl FnOnce(&mut Marks)) {
+        f(&mut self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<Request<B>> for Middleware<S>
+where
+    S: Service<Request<B>> + Clone + Send + 'static,
+    S::Future: Send,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        let start = Instant::now();
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let marks = HandlerMarks::default();
+        req.extensions_mut().insert(marks.clone());
+
+        Box::pin(async move {
+            let res = inner.call(req).await;
+            let end = Instant::now();
+
+            marks.update(|marks| {
+                // requests rejected by a middleware never reach the handler
+                if let (Some(entered), Some(exited)) = (marks.handler_entered, marks.handler_exited)
+                {
+                    observe("ingress", entered.saturating_duration_since(start));
+                    observe("egress", end.saturating_duration_since(exited));
+                }
+            });
+
+            res
+        })
+    }
+}
+
+/// Measures how much latency the middleware stack adds, as the `request_middleware_overhead{phase}`
+/// histogram.
+///
+/// Should be the outermost layer, `OverheadMarkLayer` marks where the handler begins:
+///
+/// ```ignore
+/// Router::new()
+///     .route("/", get(handler))
+///     .route_layer(OverheadMarkLayer)
+///     .layer(LogLayer::new())
+///     .layer(OverheadLayer);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OverheadLayer;
+
+impl<S> Layer<S> for OverheadLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware { service }
+    }
+}
+
+#[derive(Clone)]
+pub struct MarkMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<Request<B>> for MarkMiddleware<S>
+where
+    S: Service<Request<B>> + Clone + Send + 'static,
+    S::Future: Send,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let marks = req.extensions().get::<HandlerMarks>().cloned();
+        if let Some(marks) = &marks {
+            marks.update(|marks| marks.handler_entered = Some(Instant::now()));
+        }
+
+        Box::pin(async move {
+            let res = inner.call(req).await;
+            if let Some(marks) = marks {
+                marks.update(|marks| marks.handler_exited = Some(Instant::now()));
+            }
+            res
+        })
+    }
+}
+
+/// Marks handler entry and exit for `OverheadLayer`, should be the innermost layer
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OverheadMarkLayer;
+
+impl<S> Layer<S> for OverheadMarkLayer {
+    type Service = MarkMiddleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        MarkMiddleware { service }
+    }
+}
+
+fn observe(phase: &str, duration: Duration) {
+    METRICS
+        .overhead_vec
+        .with_label_values(&[phase])
+        .observe(duration.as_secs_f64());
+}
@@ -0,0 +1,27 @@
+use tower::layer::util::{Identity, Stack};
+use tower::ServiceBuilder;
+
+use super::{BodyLimitLayer, CorsLayer, LogLayer, MetricsLayer};
+
+/// `ServiceBuilder` with the logging, metrics, CORS and body limit layers
+pub type Preset = ServiceBuilder<
+    Stack<BodyLimitLayer, Stack<CorsLayer, Stack<MetricsLayer, Stack<LogLayer, Identity>>>>,
+>;
+
+/// The middleware stack as a plain `tower::ServiceBuilder`, for services not built on
+/// axum `Router` (hyper, warp, tonic). Requests of the whole service are metered under
+/// the `path` label.
+///
+/// ```ignore
+/// let svc = svc_utils::middleware::preset("/api", 1024 * 1024).service(svc);
+/// ```
+///
+/// Compose the layers manually for a different order or configuration.
+pub fn preset(path: impl Into<String>, body_size_limit: u64) -> Preset {
+    ServiceBuilder::new()
+        .layer(LogLayer::new())
+        // outside of CORS and body limit, so rejected requests are metered too
+        .layer(MetricsLayer::new(path))
+        .layer(CorsLayer::new())
+        .layer(BodyLimitLayer::new(body_size_limit))
+}
@@ -0,0 +1,128 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use http::{header, request::Parts, HeaderName, HeaderValue, Request, Response, StatusCode};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter, IntCounter};
+use tower::{Layer, Service};
+
+use crate::sharding::{normalize_member, ShardRing};
+
+/// Header of requests already redirected by a replica, served where they land
+pub static SHARD_REDIRECTED: HeaderName = HeaderName::from_static("x-shard-redirected");
+
+static REDIRECTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "shard_redirects_total",
+        "Requests redirected to the replica owning their shard"
+    )
+    .expect("Can't create stats metrics")
+});
+
+type KeyFn = Arc<dyn Fn(&Parts) -> Option<String> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    ring: ShardRing,
+    local: Arc<str>,
+    key: KeyFn,
+    service: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Middleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let (parts, body) = req.into_parts();
+
+        // replicas briefly disagreeing on the membership would bounce the request otherwise
+        let redirected = parts.headers.contains_key(&SHARD_REDIRECTED);
+
+        let location = (self.key)(&parts)
+            .filter(|_| !redirected)
+            .and_then(|key| self.ring.owner(&key))
+            .filter(|owner| **owner != *self.local)
+            .and_then(|owner| {
+                let path = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+                let location = format!("{}{}", owner.trim_end_matches('/'), path);
+                HeaderValue::from_str(&location).ok()
+            });
+
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let req = Request::from_parts(parts, body);
+        Box::pin(async move {
+            if let Some(location) = location {
+                REDIRECTS.inc();
+                let mut resp = Response::new(ResBody::default());
+                *resp.status_mut() = StatusCode::TEMPORARY_REDIRECT;
+                resp.headers_mut().insert(header::LOCATION, location);
+                resp.headers_mut()
+                    .insert(SHARD_REDIRECTED.clone(), HeaderValue::from_static("1"));
+                return Ok(resp);
+            }
+
+            inner.call(req).await
+        })
+    }
+}
+
+/// Redirects requests of shards owned by another replica there with 307 Temporary Redirect,
+/// which keeps the method and the body. Redirects are counted in `shard_redirects_total`.
+///
+/// `local` is the member of the ring this replica is, members must be base URLs
+/// (`http://10.0.3.12:8080`) the request path is appended to. Requests without a shard key
+/// and requests arriving while the ring is empty are served locally.
+///
+/// Redirect responses carry `X-Shard-Redirected: 1`. Clients and gateways following
+/// the redirect should send it back with the request, which is then served by the replica
+/// it lands on even if that replica sees another owner, so requests don't bounce between
+/// replicas with different views of the membership.
+#[derive(Clone)]
+pub struct ShardRedirectLayer {
+    ring: ShardRing,
+    local: Arc<str>,
+    key: KeyFn,
+}
+
+impl ShardRedirectLayer {
+    pub fn new<F>(ring: ShardRing, local: &str, key: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        Self {
+            ring,
+            local: normalize_member(local).into(),
+            key: Arc::new(key),
+        }
+    }
+}
+
+impl<S> Layer<S> for ShardRedirectLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            ring: self.ring.clone(),
+            local: self.local.clone(),
+            key: self.key.clone(),
+            service,
+        }
+    }
+}
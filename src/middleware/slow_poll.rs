@@ -0,0 +1,116 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use http::{Method, Request, Uri};
+use pin_project_lite::pin_project;
+use tower::{Layer, Service};
+use tracing::warn;
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    threshold: Duration,
+    service: S,
+}
+
+impl<S, B> Service<Request<B>> for Middleware<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = SlowPollFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let method = req.method().clone();
+        let uri = req.uri().clone();
+
+        SlowPollFuture {
+            inner: self.service.call(req),
+            threshold: self.threshold,
+            method,
+            uri,
+            started: Instant::now(),
+            polls: 0,
+        }
+    }
+}
+
+/// Warns when a single poll of a handler future takes longer than the threshold, which
+/// means the handler blocked its runtime thread (sync IO, heavy computation, std locks).
+///
+/// The warning is logged within the request span with the request method and uri, the
+/// poll number and the time since the request started, so it's easy to tell at which
+/// point of the handler it blocked. Add it under `LogLayer`:
+///
+/// ```ignore
+/// Router::new()
+///     .route("/", get(handler))
+///     .layer(SlowPollLayer::new(Duration::from_millis(10)))
+///     .layer(LogLayer::new());
+/// ```
+#[derive(Debug, Clone)]
+pub struct SlowPollLayer {
+    threshold: Duration,
+}
+
+impl SlowPollLayer {
+    pub fn new(threshold: Duration) -> Self {
+        Self { threshold }
+    }
+}
+
+impl<S> Layer<S> for SlowPollLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            threshold: self.threshold,
+            service,
+        }
+    }
+}
+
+pin_project! {
+    pub struct SlowPollFuture<F> {
+        #[pin]
+        inner: F,
+        threshold: Duration,
+        method: Method,
+        uri: Uri,
+        started: Instant,
+        polls: u64,
+    }
+}
+
+impl<F: Future> Future for SlowPollFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        *this.polls += 1;
+
+        let started = Instant::now();
+        let poll = this.inner.poll(cx);
+        let elapsed = started.elapsed();
+
+        if elapsed > *this.threshold {
+            warn!(
+                method = %this.method,
+                uri = %this.uri,
+                poll = *this.polls,
+                since_request_start = ?started.saturating_duration_since(*this.started),
+                ready = poll.is_ready(),
+                "Handler blocked the runtime for {:?} in a single poll",
+                elapsed
+            );
+        }
+
+        poll
+    }
+}
@@ -0,0 +1,214 @@
+use std::marker::PhantomData;
+
+use tower::layer::util::{Identity, Stack};
+use tower::{Layer, ServiceBuilder};
+
+#[cfg(feature = "body-limit-middleware")]
+use super::BodyLimitLayer;
+#[cfg(feature = "compression-middleware")]
+use super::CompressionLayer;
+#[cfg(feature = "cors-middleware")]
+use super::CorsLayer;
+#[cfg(feature = "log-middleware")]
+use super::LogLayer;
+#[cfg(feature = "metrics-middleware")]
+use super::MetricsLayer;
+#[cfg(feature = "timeout-middleware")]
+use super::TimeoutLayer;
+
+/// Stages of a `StackBuilder`, named after the last added group of layers
+pub mod stage {
+    pub struct Start;
+    pub struct Limited;
+    pub struct Logged;
+    pub struct Metered;
+    pub struct Timed;
+    pub struct Shaped;
+    pub struct Custom;
+
+    /// Stages where a body limit may still be added
+    pub trait BeforeBodyLimit: sealed::Sealed {}
+    /// Stages where logging may still be added
+    pub trait BeforeLog: sealed::Sealed {}
+    /// Stages where metrics may still be added
+    pub trait BeforeMetrics: sealed::Sealed {}
+    /// Stages where a timeout may still be added
+    pub trait BeforeTimeout: sealed::Sealed {}
+    /// Stages where CORS or compression may still be added
+    pub trait BeforeShaping: sealed::Sealed {}
+
+    impl BeforeBodyLimit for Start {}
+
+    impl BeforeLog for Start {}
+    impl BeforeLog for Limited {}
+
+    impl BeforeMetrics for Start {}
+    impl BeforeMetrics for Limited {}
+    impl BeforeMetrics for Logged {}
+
+    impl BeforeTimeout for Start {}
+    impl BeforeTimeout for Limited {}
+    impl BeforeTimeout for Logged {}
+    impl BeforeTimeout for Metered {}
+
+    impl BeforeShaping for Start {}
+    impl BeforeShaping for Limited {}
+    impl BeforeShaping for Logged {}
+    impl BeforeShaping for Metered {}
+    impl BeforeShaping for Timed {}
+    impl BeforeShaping for Shaped {}
+
+    mod sealed {
+        pub trait Sealed {}
+
+        impl Sealed for super::Start {}
+        impl Sealed for super::Limited {}
+        impl Sealed for super::Logged {}
+        impl Sealed for super::Metered {}
+        impl Sealed for super::Timed {}
+        impl Sealed for super::Shaped {}
+        impl Sealed for super::Custom {}
+    }
+}
+
+/// `ServiceBuilder` accepting the layers of this crate only in the recommended order,
+/// from the outermost:
+///
+/// 1. body limit, so oversized bodies are cut before anything reads them
+/// 2. logging, so every request gets a span
+/// 3. metrics, so requests rejected by the layers below are metered too
+/// 4. timeout, so timed out requests are metered as such
+/// 5. CORS and compression in any order
+/// 6. custom layers like authn, so metrics wrap them
+///
+/// Every group is optional. Adding a layer after a group which must wrap it fails to
+/// compile, e.g. `with_metrics` after `with_compression` fails with
+/// "the trait `BeforeMetrics` is not implemented for `Shaped`", and `with_body_limit`
+/// anywhere but first fails with "the trait `BeforeBodyLimit` is not implemented".
+///
+/// ```ignore
+/// let stack = StackBuilder::new()
+///     .with_body_limit(BodyLimitLayer::new(1024 * 1024))
+///     .with_log(LogLayer::new())
+///     .with_metrics(MetricsLayer::new("/api"))
+///     .with_compression(CompressionLayer::new())
+///     .layer(from_extractor::<AccountIdExtractor>());
+/// let router = router.layer(stack);
+/// ```
+pub struct StackBuilder<L, S> {
+    builder: ServiceBuilder<L>,
+    _stage: PhantomData<fn() -> S>,
+}
+
+impl Default for StackBuilder<Identity, stage::Start> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StackBuilder<Identity, stage::Start> {
+    pub fn new() -> Self {
+        Self {
+            builder: ServiceBuilder::new(),
+            _stage: PhantomData,
+        }
+    }
+}
+
+impl<L, S> StackBuilder<L, S> {
+    fn push<T, N>(self, layer: T) -> StackBuilder<Stack<T, L>, N> {
+        StackBuilder {
+            builder: self.builder.layer(layer),
+            _stage: PhantomData,
+        }
+    }
+
+    #[cfg(feature = "body-limit-middleware")]
+    pub fn with_body_limit(
+        self,
+        layer: BodyLimitLayer,
+    ) -> StackBuilder<Stack<BodyLimitLayer, L>, stage::Limited>
+    where
+        S: stage::BeforeBodyLimit,
+    {
+        self.push(layer)
+    }
+
+    #[cfg(feature = "log-middleware")]
+    pub fn with_log(self, layer: LogLayer) -> StackBuilder<Stack<LogLayer, L>, stage::Logged>
+    where
+        S: stage::BeforeLog,
+    {
+        self.push(layer)
+    }
+
+    #[cfg(feature = "metrics-middleware")]
+    pub fn with_metrics(
+        self,
+        layer: MetricsLayer,
+    ) -> StackBuilder<Stack<MetricsLayer, L>, stage::Metered>
+    where
+        S: stage::BeforeMetrics,
+    {
+        self.push(layer)
+    }
+
+    #[cfg(feature = "timeout-middleware")]
+    pub fn with_timeout(
+        self,
+        layer: TimeoutLayer,
+    ) -> StackBuilder<Stack<TimeoutLayer, L>, stage::Timed>
+    where
+        S: stage::BeforeTimeout,
+    {
+        self.push(layer)
+    }
+
+    #[cfg(feature = "cors-middleware")]
+    pub fn with_cors(self, layer: CorsLayer) -> StackBuilder<Stack<CorsLayer, L>, stage::Shaped>
+    where
+        S: stage::BeforeShaping,
+    {
+        self.push(layer)
+    }
+
+    #[cfg(feature = "compression-middleware")]
+    pub fn with_compression(
+        self,
+        layer: CompressionLayer,
+    ) -> StackBuilder<Stack<CompressionLayer, L>, stage::Shaped>
+    where
+        S: stage::BeforeShaping,
+    {
+        self.push(layer)
+    }
+
+    /// Add a layer of the service inside all of the layers of this crate
+    pub fn layer<T>(self, layer: T) -> StackBuilder<Stack<T, L>, stage::Custom> {
+        self.push(layer)
+    }
+
+    pub fn into_builder(self) -> ServiceBuilder<L> {
+        self.builder
+    }
+}
+
+impl<L: Clone, S> Clone for StackBuilder<L, S> {
+    fn clone(&self) -> Self {
+        Self {
+            builder: self.builder.clone(),
+            _stage: PhantomData,
+        }
+    }
+}
+
+impl<L, S, Svc> Layer<Svc> for StackBuilder<L, S>
+where
+    L: Layer<Svc>,
+{
+    type Service = L::Service;
+
+    fn layer(&self, service: Svc) -> Self::Service {
+        self.builder.service(service)
+    }
+}
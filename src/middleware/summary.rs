@@ -0,0 +1,251 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType, Quantile, Summary};
+use tracing::error;
+
+const NAME: &str = "request_duration_summary";
+const HELP: &str = "Request duration, sliding window quantiles";
+const LABELS: [&str; 3] = ["path", "method", "success"];
+const QUANTILES: [f64; 3] = [0.5, 0.95, 0.99];
+
+// the window slides by one of these parts
+const WINDOW_PARTS: u32 = 6;
+// quantiles are off by at most this share of the value, whatever the number of samples
+const RELATIVE_ERROR: f64 = 0.01;
+// values under it, e.g. zero durations, share one bucket
+const MIN_VALUE: f64 = 1e-9;
+
+static SUMMARIES: Lazy<Summaries> = Lazy::new(|| {
+    let summaries = Summaries::new();
+    if let Err(err) = prometheus::register(Box::new(summaries.clone())) {
+        error!("Can't register {}: {:?}", NAME, err);
+    }
+    summaries
+});
+
+/// Record request duration as the `request_duration_summary` series of the route
+pub(crate) fn observe(labels: [&str; 3], window: Duration, value: f64) {
+    SUMMARIES.observe(labels, window, value)
+}
+
+/// Summaries of all routes, clones share the series
+#[derive(Clone)]
+struct Summaries {
+    desc: Desc,
+    series: Arc<Mutex<HashMap<[String; 3], Window>>>,
+}
+
+impl Summaries {
+    fn new() -> Self {
+        let desc = Desc::new(
+            NAME.to_owned(),
+            HELP.to_owned(),
+            LABELS.iter().map(|l| l.to_string()).collect(),
+            HashMap::new(),
+        )
+        .expect("Can't create stats metrics");
+
+        Self {
+            desc,
+            series: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn observe(&self, labels: [&str; 3], window: Duration, value: f64) {
+        let mut series = self.lock();
+        let key = labels.map(|l| l.to_owned());
+        series
+            .entry(key)
+            .or_insert_with(|| Window::new(window))
+            .observe(value);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<[String; 3], Window>> {
+        self.series
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Collector for Summaries {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.desc]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mut series = self.lock();
+        if series.is_empty() {
+            return vec![];
+        }
+
+        let metrics = series
+            .iter_mut()
+            .map(|(labels, window)| {
+                let pairs = LABELS
+                    .iter()
+                    .zip(labels.iter())
+                    .map(|(name, value)| {
+                        let mut pair = LabelPair::default();
+                        pair.set_name(name.to_string());
+                        pair.set_value(value.to_owned());
+                        pair
+                    })
+                    .collect::<Vec<_>>();
+
+                let mut metric = Metric::default();
+                // protobuf models use RepeatedField when `prometheus/protobuf` is enabled
+                #[allow(clippy::useless_conversion)]
+                metric.set_label(pairs.into());
+                metric.set_summary(window.summary());
+                metric
+            })
+            .collect::<Vec<_>>();
+
+        let mut family = MetricFamily::default();
+        family.set_name(NAME.to_owned());
+        family.set_help(HELP.to_owned());
+        family.set_field_type(MetricType::SUMMARY);
+        #[allow(clippy::useless_conversion)]
+        family.set_metric(metrics.into());
+        vec![family]
+    }
+}
+
+/// Counts of samples in buckets growing exponentially, so every sample of the window
+/// counts in the quantiles in bounded memory, about a thousand buckets from 1 µs to 1000 s
+#[derive(Default)]
+struct Sketch {
+    zeros: u64,
+    buckets: BTreeMap<i32, u64>,
+}
+
+impl Sketch {
+    fn gamma() -> f64 {
+        (1.0 + RELATIVE_ERROR) / (1.0 - RELATIVE_ERROR)
+    }
+
+    fn observe(&mut self, value: f64) {
+        if value.is_nan() || value <= MIN_VALUE {
+            self.zeros += 1;
+            return;
+        }
+
+        let idx = (value.ln() / Self::gamma().ln()).ceil() as i32;
+        *self.buckets.entry(idx).or_default() += 1;
+    }
+
+    fn merge(&mut self, other: &Sketch) {
+        self.zeros += other.zeros;
+        for (idx, count) in &other.buckets {
+            *self.buckets.entry(*idx).or_default() += count;
+        }
+    }
+
+    fn count(&self) -> u64 {
+        self.zeros + self.buckets.values().sum::<u64>()
+    }
+
+    /// Value of the quantile, NaN without samples
+    fn quantile(&self, q: f64, count: u64) -> f64 {
+        if count == 0 {
+            return f64::NAN;
+        }
+
+        let rank = ((count as f64 * q).ceil() as u64).clamp(1, count);
+        if rank <= self.zeros {
+            return 0.0;
+        }
+
+        let gamma = Self::gamma();
+        let mut seen = self.zeros;
+        for (idx, bucket_count) in &self.buckets {
+            seen += bucket_count;
+            if seen >= rank {
+                // the bucket holds values in (gamma^(idx-1), gamma^idx]
+                return 2.0 * gamma.powi(*idx) / (gamma + 1.0);
+            }
+        }
+        f64::NAN
+    }
+}
+
+/// Samples of the last `window`, split into parts so old samples expire gradually
+struct Window {
+    part_duration: Duration,
+    parts: Vec<Part>,
+    count: u64,
+    sum: f64,
+}
+
+struct Part {
+    started: Instant,
+    sketch: Sketch,
+}
+
+impl Window {
+    fn new(window: Duration) -> Self {
+        Self {
+            part_duration: window / WINDOW_PARTS,
+            parts: vec![],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+
+        let now = Instant::now();
+        let current = match self.parts.last_mut() {
+            Some(part) if now.duration_since(part.started) < self.part_duration => part,
+            _ => {
+                self.expire(now);
+                self.parts.push(Part {
+                    started: now,
+                    sketch: Sketch::default(),
+                });
+                self.parts.last_mut().expect("part was just pushed")
+            }
+        };
+
+        current.sketch.observe(value);
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let window = self.part_duration * WINDOW_PARTS;
+        self.parts
+            .retain(|part| now.duration_since(part.started) < window);
+    }
+
+    fn summary(&mut self) -> Summary {
+        self.expire(Instant::now());
+
+        let mut sketch = Sketch::default();
+        for part in &self.parts {
+            sketch.merge(&part.sketch);
+        }
+        let count = sketch.count();
+
+        let quantiles = QUANTILES
+            .iter()
+            .map(|&q| {
+                let mut quantile = Quantile::default();
+                quantile.set_quantile(q);
+                quantile.set_value(sketch.quantile(q, count));
+                quantile
+            })
+            .collect::<Vec<_>>();
+
+        let mut summary = Summary::default();
+        summary.set_sample_count(self.count);
+        summary.set_sample_sum(self.sum);
+        #[allow(clippy::useless_conversion)]
+        summary.set_quantile(quantiles.into());
+        summary
+    }
+}
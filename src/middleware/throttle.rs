@@ -0,0 +1,173 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use bytes::Buf;
+use futures::future::BoxFuture;
+use http::{request::Parts, Request, Response};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use tokio::time::{Instant, Sleep};
+use tower::{Layer, Service};
+
+type RateFn = dyn Fn(&Parts) -> Option<u64> + Send + Sync;
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    rate: Arc<RateFn>,
+    service: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Middleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = Response<ThrottledBody<ResBody>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let (parts, body) = req.into_parts();
+        let rate = (self.rate)(&parts).filter(|rate| *rate > 0);
+        let fut = inner.call(Request::from_parts(parts, body));
+
+        Box::pin(async move {
+            let res = fut.await?;
+            Ok(res.map(|body| ThrottledBody::new(body, rate)))
+        })
+    }
+}
+
+/// Limits the rate response bodies are sent at, in bytes per second per response,
+/// so a few clients of download endpoints can't saturate the pod network.
+///
+/// Apply it to the routes to throttle with `route_layer` or per route. The rate can also
+/// depend on the request, e.g. on the audience:
+///
+/// ```ignore
+/// ThrottleLayer::with_rate_fn(|parts| {
+///     match parts.headers.get("ulms-app-audience")?.to_str().ok()? {
+///         "partner.example.org" => Some(512 * 1024),
+///         _ => Some(4 * 1024 * 1024),
+///     }
+/// })
+/// ```
+///
+/// The body is delayed between frames, so a single large frame is still sent at once.
+#[derive(Clone)]
+pub struct ThrottleLayer {
+    rate: Arc<RateFn>,
+}
+
+impl ThrottleLayer {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self::with_rate_fn(move |_| Some(bytes_per_sec))
+    }
+
+    /// Rate of the request response in bytes per second, `None` disables throttling
+    pub fn with_rate_fn<F>(rate: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<u64> + Send + Sync + 'static,
+    {
+        Self {
+            rate: Arc::new(rate),
+        }
+    }
+}
+
+impl<S> Layer<S> for ThrottleLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            rate: self.rate.clone(),
+            service,
+        }
+    }
+}
+
+pin_project! {
+    /// Body delaying its frames to keep under the rate
+    pub struct ThrottledBody<B> {
+        #[pin]
+        inner: B,
+        rate: Option<u64>,
+        started: Option<Instant>,
+        sent: u64,
+        sleep: Option<Pin<Box<Sleep>>>,
+    }
+}
+
+impl<B> ThrottledBody<B> {
+    fn new(inner: B, rate: Option<u64>) -> Self {
+        Self {
+            inner,
+            rate,
+            started: None,
+            sent: 0,
+            sleep: None,
+        }
+    }
+}
+
+impl<B> HttpBody for ThrottledBody<B>
+where
+    B: HttpBody,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+
+        let rate = match this.rate {
+            Some(rate) => *rate,
+            None => return this.inner.poll_frame(cx),
+        };
+
+        if let Some(sleep) = this.sleep {
+            ready!(sleep.as_mut().poll(cx));
+            *this.sleep = None;
+        }
+
+        let frame = ready!(this.inner.poll_frame(cx));
+        if let Some(Ok(frame)) = &frame {
+            if let Some(data) = frame.data_ref() {
+                let started = *this.started.get_or_insert_with(Instant::now);
+                *this.sent += data.remaining() as u64;
+
+                // the next frame is due once the bytes sent so far fit into the rate
+                let due = started + Duration::from_secs_f64(*this.sent as f64 / rate as f64);
+                if due > Instant::now() {
+                    *this.sleep = Some(Box::pin(tokio::time::sleep_until(due)));
+                }
+            }
+        }
+
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,290 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::body::{Body, Bytes};
+use axum::response::{IntoResponse, Response};
+use axum::routing::Router;
+use futures::future::BoxFuture;
+use http::{Request, StatusCode};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use tokio::time::{Instant, Sleep};
+use tower::{Layer, Service};
+use tracing::warn;
+
+// deadline of timeouts too long to add to the current time, like `Duration::MAX`,
+// about 30 years as tokio uses for timers that never fire
+const NO_DEADLINE: Duration = Duration::from_secs(86400 * 365 * 30);
+
+/// Deadline of the current request, shared between `TimeoutLayer` and timed routes.
+///
+/// Handlers can get it with `Extension<Deadline>` to bound their own work, e.g. with
+/// `BlockingPool::run_until`.
+#[derive(Clone)]
+pub struct Deadline(Arc<Mutex<Instant>>);
+
+impl Deadline {
+    pub fn get(&self) -> Instant {
+        *self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn set(&self, deadline: Instant) {
+        *self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = deadline;
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    timeout: Duration,
+    idle_timeout: Option<Duration>,
+    service: S,
+}
+
+impl<S, B> Service<Request<B>> for Middleware<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let deadline = Deadline(Arc::new(Mutex::new(deadline_after(self.timeout))));
+        req.extensions_mut().insert(deadline.clone());
+        let idle_timeout = self.idle_timeout;
+
+        Box::pin(async move {
+            let fut = inner.call(req);
+            tokio::pin!(fut);
+
+            let sleep = tokio::time::sleep_until(deadline.get());
+            tokio::pin!(sleep);
+
+            loop {
+                tokio::select! {
+                    res = &mut fut => {
+                        return match idle_timeout {
+                            Some(idle_timeout) => res.map(|res| {
+                                res.map(|inner| Body::new(IdleTimeoutBody::new(inner, idle_timeout)))
+                            }),
+                            None => res,
+                        };
+                    }
+                    _ = &mut sleep => {
+                        // a timed route may have moved the deadline
+                        let current = deadline.get();
+                        if current <= Instant::now() {
+                            return Ok(timeout_response());
+                        }
+                        sleep.as_mut().reset(current);
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Fails requests that take longer than the timeout to produce a response with 504 Gateway Timeout.
+///
+/// Routes added with `TimedRoute::timed_route` override this timeout. Streaming of
+/// the response body (SSE, chunked responses) is bounded by the optional idle timeout instead.
+#[derive(Debug, Clone)]
+pub struct TimeoutLayer {
+    timeout: Duration,
+    idle_timeout: Option<Duration>,
+}
+
+impl TimeoutLayer {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            idle_timeout: None,
+        }
+    }
+
+    /// Abort response bodies that produced no data for `idle_timeout`
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            service,
+            timeout: self.timeout,
+            idle_timeout: self.idle_timeout,
+        }
+    }
+}
+
+/// Per-route timeout, also moves the `TimeoutLayer` deadline when there is one
+#[derive(Debug, Clone)]
+pub(crate) struct RouteTimeoutLayer {
+    timeout: Duration,
+}
+
+impl RouteTimeoutLayer {
+    pub(crate) fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+impl<S> Layer<S> for RouteTimeoutLayer {
+    type Service = RouteTimeout<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        RouteTimeout {
+            timeout: self.timeout,
+            service,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct RouteTimeout<S> {
+    timeout: Duration,
+    service: S,
+}
+
+impl<S, B> Service<Request<B>> for RouteTimeout<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+        let timeout = self.timeout;
+
+        // moving the TimeoutLayer deadline lets longer routes outlive it and handlers see
+        // the route deadline, a shorter one is enforced here since the layer only re-reads
+        // the deadline once its own sleep fires
+        if let Some(deadline) = req.extensions().get::<Deadline>() {
+            deadline.set(deadline_after(timeout));
+        }
+
+        Box::pin(async move {
+            match tokio::time::timeout(timeout, inner.call(req)).await {
+                Ok(res) => res,
+                Err(_) => Ok(timeout_response()),
+            }
+        })
+    }
+}
+
+pub trait TimedRoute<H>
+where
+    H: Service<Request<Body>, Error = Infallible> + Send,
+{
+    type Output;
+
+    fn timed_route(self, path: &str, timeout: Duration, svc: H) -> Self::Output;
+}
+
+impl<H> TimedRoute<H> for Router
+where
+    H: Service<Request<Body>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    H::Future: Send + 'static,
+{
+    type Output = Router;
+
+    fn timed_route(self, path: &str, timeout: Duration, svc: H) -> Self::Output {
+        self.route_service(path, RouteTimeoutLayer::new(timeout).layer(svc))
+    }
+}
+
+/// Response body failing when the inner body produced no data for `idle_timeout`
+struct IdleTimeoutBody {
+    inner: Body,
+    idle_timeout: Duration,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl IdleTimeoutBody {
+    fn new(inner: Body, idle_timeout: Duration) -> Self {
+        Self {
+            inner,
+            idle_timeout,
+            sleep: Box::pin(tokio::time::sleep(idle_timeout)),
+        }
+    }
+}
+
+impl HttpBody for IdleTimeoutBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match Pin::new(&mut self.inner).poll_frame(cx) {
+            Poll::Ready(frame) => {
+                let deadline = deadline_after(self.idle_timeout);
+                self.sleep.as_mut().reset(deadline);
+                Poll::Ready(frame)
+            }
+            Poll::Pending => match self.sleep.as_mut().poll(cx) {
+                Poll::Ready(()) => {
+                    warn!("Response body idle for {:?}, aborting", self.idle_timeout);
+                    Poll::Ready(Some(Err(axum::Error::new("response body idle timeout"))))
+                }
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Deadline `timeout` from now, one that never comes for timeouts like `Duration::MAX`
+fn deadline_after(timeout: Duration) -> Instant {
+    let now = Instant::now();
+    now.checked_add(timeout)
+        .unwrap_or_else(|| now + NO_DEADLINE)
+}
+
+fn timeout_response() -> Response {
+    warn!("Request timed out");
+    let resp_body: Response = Default::default();
+    (StatusCode::GATEWAY_TIMEOUT, resp_body).into_response()
+}
@@ -0,0 +1,94 @@
+use bytes::Bytes;
+use http::{header, Request, StatusCode, Uri};
+use http_body_util::{BodyExt, Full};
+use hyper_util::client::legacy::connect::{Connect, HttpConnector};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+
+use super::{Message, SendError, Sender};
+
+/// Posts messages as JSON `{"to", "subject", "text", "html"}` to a mailing provider
+/// or an internal mailer service.
+///
+/// Connection failures, 429 and 5xx responses are temporary failures, other 4xx are
+/// permanent ones.
+pub struct HttpSender<C = HttpConnector> {
+    client: Client<C, Full<Bytes>>,
+    endpoint: Uri,
+    headers: Vec<(header::HeaderName, header::HeaderValue)>,
+}
+
+impl HttpSender {
+    /// Create new sender over http, use `with_client` for https endpoints
+    pub fn new(endpoint: Uri) -> Self {
+        Self::with_client(Client::builder(TokioExecutor::new()).build_http(), endpoint)
+    }
+}
+
+impl<C> HttpSender<C>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    pub fn with_client(client: Client<C, Full<Bytes>>, endpoint: Uri) -> Self {
+        Self {
+            client,
+            endpoint,
+            headers: vec![],
+        }
+    }
+
+    /// Add a header to every request, e.g. `Authorization`
+    pub fn with_header(mut self, name: header::HeaderName, value: header::HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+}
+
+#[axum::async_trait]
+impl<C> Sender for HttpSender<C>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    async fn send(&self, message: &Message) -> Result<(), SendError> {
+        let payload =
+            serde_json::to_vec(message).map_err(|err| SendError::Permanent(Box::new(err)))?;
+
+        let mut builder =
+            Request::post(self.endpoint.clone()).header(header::CONTENT_TYPE, "application/json");
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        let req = builder
+            .body(Full::from(payload))
+            .expect("notification request must be valid");
+
+        let res = self
+            .client
+            .request(req)
+            .await
+            .map_err(|err| SendError::Temporary(Box::new(err)))?;
+        let status = res.status();
+        // drain the body so the connection can be reused
+        let body = res.into_body().collect().await.map(|body| body.to_bytes());
+
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let body = body.unwrap_or_default();
+        let err = format!(
+            "provider answered {}: {}",
+            status,
+            String::from_utf8_lossy(&body)
+        );
+        if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS {
+            Err(SendError::Permanent(err.into()))
+        } else {
+            Err(SendError::Temporary(err.into()))
+        }
+    }
+}
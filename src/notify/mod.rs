@@ -0,0 +1,370 @@
+//! Transactional notifications sent through a retry queue.
+//!
+//! ```ignore
+//! let sender = HttpSender::new("http://mailer/api/v1/messages".parse()?);
+//! let notifier = Notifier::new(sender).with_templates(templates).spawn();
+//! let queue = notifier.queue();
+//!
+//! queue.notify("user@example.org", "room_invite", &json!({ "room": room.title }))?;
+//! // on shutdown
+//! notifier.shutdown(Duration::from_secs(10)).await;
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use once_cell::sync::Lazy;
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+pub use self::http::HttpSender;
+
+mod http;
+
+// longest delay before a retry, however many attempts there were
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    notifications_vec: IntCounterVec,
+    attempts_vec: IntCounterVec,
+    delivery_vec: HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            notifications_vec: register_int_counter_vec!(
+                "notifications_total",
+                "Notifications by final result: delivered, failed or dropped",
+                &["sender", "result"]
+            )
+            .expect("Can't create stats metrics"),
+            attempts_vec: register_int_counter_vec!(
+                "notification_attempts_total",
+                "Notification send attempts by result",
+                &["sender", "result"]
+            )
+            .expect("Can't create stats metrics"),
+            delivery_vec: register_histogram_vec!(
+                "notification_delivery_duration",
+                "Time from queueing a notification to its delivery",
+                &["sender"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Rendered notification
+#[derive(Debug, Clone, Serialize)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub html: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    /// The provider is unavailable or throttles, the message is retried
+    Temporary(Box<dyn StdError + Send + Sync>),
+    /// The provider rejected the message, e.g. for an invalid address
+    Permanent(Box<dyn StdError + Send + Sync>),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Temporary(err) => write!(f, "temporary send failure: {}", err),
+            SendError::Permanent(err) => write!(f, "message rejected: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+/// Backend delivering messages, e.g. an SMTP relay or an HTTP mailing provider
+#[async_trait]
+pub trait Sender: Send + Sync + 'static {
+    /// `sender` label of the metrics
+    fn name(&self) -> &'static str;
+
+    async fn send(&self, message: &Message) -> Result<(), SendError>;
+}
+
+/// Renders templated notifications into messages
+pub trait Templates: Send + Sync + 'static {
+    fn render(
+        &self,
+        to: &str,
+        template: &str,
+        data: &serde_json::Value,
+    ) -> Result<Message, NotifyError>;
+}
+
+#[derive(Debug)]
+pub enum NotifyError {
+    /// The queue is full or the notifier is shut down, the message is dropped
+    QueueFull,
+    /// Templated notification without templates configured
+    NoTemplates,
+    /// Template is unknown or failed to render
+    Template(Box<dyn StdError + Send + Sync>),
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotifyError::QueueFull => write!(f, "notification queue is full"),
+            NotifyError::NoTemplates => write!(f, "no notification templates configured"),
+            NotifyError::Template(err) => write!(f, "failed to render notification: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for NotifyError {}
+
+/// Queue of notifications delivered in the background with retries.
+///
+/// Temporary failures are retried with exponential backoff, permanent ones are not.
+/// Attempts are counted in `notification_attempts_total{sender,result}`, final results
+/// in `notifications_total{sender,result}` and the time to deliver in
+/// `notification_delivery_duration{sender}`.
+pub struct Notifier<S> {
+    sender: S,
+    templates: Option<Arc<dyn Templates>>,
+    queue_size: usize,
+    concurrency: usize,
+    max_attempts: u32,
+    backoff: Duration,
+}
+
+impl<S: Sender> Notifier<S> {
+    /// Create new notifier queueing up to 1000 messages and sending up to 10 at once,
+    /// each with up to 5 attempts
+    pub fn new(sender: S) -> Self {
+        Self {
+            sender,
+            templates: None,
+            queue_size: 1000,
+            concurrency: 10,
+            max_attempts: 5,
+            backoff: Duration::from_secs(1),
+        }
+    }
+
+    pub fn with_templates<T: Templates>(mut self, templates: T) -> Self {
+        self.templates = Some(Arc::new(templates));
+        self
+    }
+
+    /// Messages queued before new ones are rejected, at least 1
+    pub fn with_queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size.max(1);
+        self
+    }
+
+    /// Messages sent at once, at least 1
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        // all permits are taken back on shutdown with `acquire_many(u32)`
+        self.concurrency = concurrency.clamp(1, u32::MAX as usize);
+        self
+    }
+
+    /// Attempts of a message, at least 1, and the delay before the first retry, doubled
+    /// for every next one up to an hour
+    pub fn with_retries(mut self, max_attempts: u32, backoff: Duration) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self.backoff = backoff.min(MAX_BACKOFF);
+        self
+    }
+
+    /// Start delivering queued messages in a separate tokio task
+    pub fn spawn(self) -> NotifierHandle {
+        let (tx, mut rx) = mpsc::channel::<(Message, Instant)>(self.queue_size);
+        let name = self.sender.name();
+        let sender = Arc::new(self.sender);
+        let in_flight = Arc::new(Semaphore::new(self.concurrency));
+        let concurrency = self.concurrency as u32;
+        let max_attempts = self.max_attempts;
+        let backoff = self.backoff;
+
+        let (closer, mut closed) = oneshot::channel::<()>();
+
+        let join_handle = tokio::task::spawn(async move {
+            let mut closing = false;
+            loop {
+                let (message, queued) = tokio::select! {
+                    item = rx.recv() => match item {
+                        Some(item) => item,
+                        None => break,
+                    },
+                    // keep receiving the queued messages until the queue is empty
+                    _ = &mut closed, if !closing => {
+                        closing = true;
+                        rx.close();
+                        continue;
+                    }
+                };
+
+                let permit = in_flight
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("in flight semaphore is never closed");
+                let sender = sender.clone();
+
+                tokio::task::spawn(async move {
+                    deliver(sender.as_ref(), &message, queued, max_attempts, backoff).await;
+                    drop(permit);
+                });
+            }
+
+            // wait for the messages in flight
+            let _ = in_flight.acquire_many(concurrency).await;
+        });
+
+        NotifierHandle {
+            queue: NotificationQueue {
+                tx,
+                templates: self.templates,
+                name,
+            },
+            join_handle,
+            closer,
+        }
+    }
+}
+
+async fn deliver<S: Sender>(
+    sender: &S,
+    message: &Message,
+    queued: Instant,
+    max_attempts: u32,
+    mut backoff: Duration,
+) {
+    let name = sender.name();
+
+    for attempt in 1..=max_attempts {
+        let result = match sender.send(message).await {
+            Ok(()) => {
+                METRICS
+                    .attempts_vec
+                    .with_label_values(&[name, "success"])
+                    .inc();
+                METRICS
+                    .notifications_vec
+                    .with_label_values(&[name, "delivered"])
+                    .inc();
+                METRICS
+                    .delivery_vec
+                    .with_label_values(&[name])
+                    .observe(queued.elapsed().as_secs_f64());
+                return;
+            }
+            Err(err) => err,
+        };
+
+        METRICS
+            .attempts_vec
+            .with_label_values(&[name, "failure"])
+            .inc();
+
+        match result {
+            SendError::Permanent(err) => {
+                error!(sender = name, "Notification rejected: {}", err);
+                break;
+            }
+            SendError::Temporary(err) if attempt < max_attempts => {
+                warn!(sender = name, attempt, "Notification send failed: {}", err);
+                tokio::time::sleep(backoff).await;
+                backoff = backoff.saturating_mul(2).min(MAX_BACKOFF);
+            }
+            SendError::Temporary(err) => {
+                error!(
+                    sender = name,
+                    attempts = max_attempts,
+                    "Notification send failed after retries: {}",
+                    err
+                );
+            }
+        }
+    }
+
+    METRICS
+        .notifications_vec
+        .with_label_values(&[name, "failed"])
+        .inc();
+}
+
+/// Queues notifications to a running `Notifier`
+#[derive(Clone)]
+pub struct NotificationQueue {
+    tx: mpsc::Sender<(Message, Instant)>,
+    templates: Option<Arc<dyn Templates>>,
+    name: &'static str,
+}
+
+impl NotificationQueue {
+    /// Queue the message, fails without waiting when the queue is full
+    pub fn send(&self, message: Message) -> Result<(), NotifyError> {
+        self.tx.try_send((message, Instant::now())).map_err(|_| {
+            METRICS
+                .notifications_vec
+                .with_label_values(&[self.name, "dropped"])
+                .inc();
+            NotifyError::QueueFull
+        })
+    }
+
+    /// Render the template with `data` and queue the message
+    pub fn notify(
+        &self,
+        to: &str,
+        template: &str,
+        data: &serde_json::Value,
+    ) -> Result<(), NotifyError> {
+        let templates = self.templates.as_ref().ok_or(NotifyError::NoTemplates)?;
+        let message = templates.render(to, template, data)?;
+        self.send(message)
+    }
+}
+
+/// Handle of a running `Notifier`
+pub struct NotifierHandle {
+    queue: NotificationQueue,
+    join_handle: JoinHandle<()>,
+    closer: oneshot::Sender<()>,
+}
+
+impl NotifierHandle {
+    pub fn queue(&self) -> NotificationQueue {
+        self.queue.clone()
+    }
+
+    /// Stop accepting notifications and deliver the queued ones, waiting up to `timeout`
+    pub async fn shutdown(self, timeout: Duration) {
+        let _ = self.closer.send(());
+
+        match tokio::time::timeout(timeout, self.join_handle).await {
+            Err(e) => {
+                error!("Notifier timed out during shutdown, error = {:?}", e);
+            }
+            Ok(Err(e)) => {
+                error!("Notifier failed during shutdown, error = {:?}", e);
+            }
+            Ok(Ok(())) => {
+                info!("Notifier successfully exited");
+            }
+        }
+    }
+}
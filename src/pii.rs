@@ -0,0 +1,98 @@
+//! Masking of personal data and credentials in strings before they're logged
+//! or answered in error payloads.
+//!
+//! ```ignore
+//! warn!("Invite failed for {}", pii::Scrubbed(&invite.email));
+//! let detail = pii::scrub(&err.to_string());
+//! ```
+
+use std::borrow::Cow;
+use std::fmt;
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+// `%40` for query strings, which are logged percent-encoded
+static EMAIL: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"[A-Za-z0-9._%+-]+(?:@|%40)[A-Za-z0-9.-]+\.[A-Za-z]{2,}")
+        .expect("valid email regex")
+});
+
+// numbers with a country code (`+`, `%2B` in query strings), an area code in parentheses
+// or the russian `7`/`8` prefix followed by the 3-3-2-2 grouping, so dates, timestamps
+// and ids aren't masked; candidates are also checked to have 10-15 digits
+static PHONE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(concat!(
+        r"(?:\+|%2[Bb])\d[\d \-()]{8,20}\d",
+        r"|\(\d{3,5}\)[ \-]?\d{1,3}[ \-]?\d{2}[ \-]?\d{2}\b",
+        r"|\b[78][ \-]?\(?\d{3}\)?[ \-]?\d{3}[ \-]?\d{2}[ \-]?\d{2}\b",
+    ))
+    .expect("valid phone regex")
+});
+
+static JWT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"eyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+\.[A-Za-z0-9_-]*").expect("valid jwt regex")
+});
+
+static BEARER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\bbearer\s+[^\s,;]+").expect("valid bearer regex"));
+
+static SECRET_PARAM: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(\w*(?:token|password|secret|api_key))=[^&\s]+")
+        .expect("valid secret param regex")
+});
+
+/// Mask emails, phone numbers, JWTs, bearer tokens and secret query parameters.
+///
+/// Returns the input as is when there's nothing to mask.
+pub fn scrub(input: &str) -> Cow<'_, str> {
+    let mut output = Cow::Borrowed(input);
+
+    replace(&mut output, &SECRET_PARAM, |caps| {
+        format!("{}=[token]", &caps[1])
+    });
+    replace(&mut output, &BEARER, |_| "Bearer [token]".to_owned());
+    replace(&mut output, &JWT, |_| "[token]".to_owned());
+    replace(&mut output, &EMAIL, |_| "[email]".to_owned());
+    replace(&mut output, &PHONE, |caps| {
+        let candidate = &caps[0];
+        let number = candidate
+            .strip_prefix("%2B")
+            .or_else(|| candidate.strip_prefix("%2b"))
+            .unwrap_or(candidate);
+        let digits = number.chars().filter(char::is_ascii_digit).count();
+        if (10..=15).contains(&digits) {
+            "[phone]".to_owned()
+        } else {
+            candidate.to_owned()
+        }
+    });
+
+    output
+}
+
+fn replace<F>(output: &mut Cow<'_, str>, regex: &Regex, replacement: F)
+where
+    F: FnMut(&Captures) -> String,
+{
+    let replaced = match regex.replace_all(output, replacement) {
+        Cow::Owned(replaced) => replaced,
+        Cow::Borrowed(_) => return,
+    };
+    *output = Cow::Owned(replaced);
+}
+
+/// Formats the value with personal data masked
+pub struct Scrubbed<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for Scrubbed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", scrub(&self.0.to_string()))
+    }
+}
+
+impl<T: fmt::Display> fmt::Debug for Scrubbed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", scrub(&self.0.to_string()))
+    }
+}
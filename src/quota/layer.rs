@@ -0,0 +1,210 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::extract::FromRequestParts;
+use futures::future::BoxFuture;
+use http::{
+    header::{CONTENT_LENGTH, RETRY_AFTER},
+    request::Parts,
+    HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode,
+};
+use http_body::Body as HttpBody;
+use tower::{Layer, Service};
+
+use super::{QuotaStatus, Quotas, Usage, METRICS};
+use crate::extractors::AccountIdExtractor;
+
+/// Header with the audience of the client app, not authenticated, so it's only fit for
+/// `QuotaLayer::with_audience_fn` when clients are trusted
+pub static AUDIENCE: HeaderName = HeaderName::from_static("ulms-app-audience");
+
+static LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+static REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+static REMAINING_BYTES: HeaderName = HeaderName::from_static("x-ratelimit-remaining-bytes");
+static RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+type AudienceFn = dyn Fn(&Parts) -> Option<String> + Send + Sync;
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    quotas: Arc<Quotas>,
+    audience: Option<Arc<AudienceFn>>,
+    reject: bool,
+    service: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Middleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: HttpBody + Default + Send,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let (mut parts, body) = req.into_parts();
+        let audience_fn = self.audience.clone();
+        let quotas = self.quotas.clone();
+        let reject = self.reject;
+
+        Box::pin(async move {
+            let audience = match audience_fn {
+                Some(audience_fn) => audience_fn(&parts),
+                None => AccountIdExtractor::from_request_parts(&mut parts, &())
+                    .await
+                    .ok()
+                    .map(|AccountIdExtractor(account_id)| account_id.audience().to_owned()),
+            };
+            let req = Request::from_parts(parts, body);
+
+            // not authenticated requests are rejected by the handlers
+            let audience = match audience {
+                Some(audience) => audience,
+                None => return inner.call(req).await,
+            };
+
+            let usage = Usage {
+                requests: 1,
+                bytes: content_length(req.headers()),
+            };
+
+            // the store failing must not take the service down, requests pass unaccounted
+            let status = quotas.consume(&audience, usage).await.ok();
+
+            if let Some(status) = &status {
+                if reject && status.is_exceeded() {
+                    METRICS
+                        .rejected
+                        .with_label_values(&[quotas.label(&audience)])
+                        .inc();
+
+                    let mut resp = Response::new(ResBody::default());
+                    *resp.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+                    resp.headers_mut()
+                        .insert(RETRY_AFTER, HeaderValue::from(status.reset));
+                    set_headers(resp.headers_mut(), status);
+                    return Ok(resp);
+                }
+            }
+
+            let mut resp = inner.call(req).await?;
+
+            if let Some(status) = &status {
+                set_headers(resp.headers_mut(), status);
+            }
+
+            let bytes = resp
+                .body()
+                .size_hint()
+                .exact()
+                .unwrap_or_else(|| content_length(resp.headers()));
+            if bytes > 0 {
+                let usage = Usage { requests: 0, bytes };
+                tokio::spawn(async move {
+                    // errors are already logged
+                    let _ = quotas.consume(&audience, usage).await;
+                });
+            }
+
+            Ok(resp)
+        })
+    }
+}
+
+fn content_length(headers: &HeaderMap) -> u64 {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0)
+}
+
+fn set_headers(headers: &mut HeaderMap, status: &QuotaStatus) {
+    if let (Some(limit), Some(remaining)) = (status.limit.requests, status.remaining_requests()) {
+        headers.insert(LIMIT.clone(), HeaderValue::from(limit));
+        headers.insert(REMAINING.clone(), HeaderValue::from(remaining));
+    }
+    if let Some(remaining) = status.remaining_bytes() {
+        headers.insert(REMAINING_BYTES.clone(), HeaderValue::from(remaining));
+    }
+    if status.limit.requests.is_some() || status.limit.bytes.is_some() {
+        headers.insert(RESET.clone(), HeaderValue::from(status.reset));
+    }
+}
+
+/// Counts requests and bytes of every audience in `Quotas` and tells the clients their
+/// remaining quota in `X-RateLimit-Limit`, `X-RateLimit-Remaining` (requests),
+/// `X-RateLimit-Remaining-Bytes` and `X-RateLimit-Reset` (seconds to the window end).
+///
+/// The audience is the one of the authenticated account by default, the same
+/// `AccountIdExtractor` requirements apply. Requests failing authentication aren't
+/// counted, handlers requiring authentication reject them anyway. Bytes are the `Content-Length` of the request plus the size of
+/// the response body when it's known upfront, streamed bodies aren't counted.
+///
+/// Requests are only counted unless `with_reject` is set. The store failing lets
+/// requests through without the headers.
+#[derive(Clone)]
+pub struct QuotaLayer {
+    quotas: Arc<Quotas>,
+    audience: Option<Arc<AudienceFn>>,
+    reject: bool,
+}
+
+impl QuotaLayer {
+    pub fn new(quotas: Quotas) -> Self {
+        Self {
+            quotas: Arc::new(quotas),
+            audience: None,
+            reject: false,
+        }
+    }
+
+    /// Take the audience from the request another way, e.g. from the `AUDIENCE` header
+    /// of trusted internal clients:
+    ///
+    /// ```ignore
+    /// QuotaLayer::new(quotas).with_audience_fn(|parts| {
+    ///     let audience = parts.headers.get(&AUDIENCE)?.to_str().ok()?;
+    ///     Some(audience.to_owned())
+    /// })
+    /// ```
+    pub fn with_audience_fn<F>(mut self, audience: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        self.audience = Some(Arc::new(audience));
+        self
+    }
+
+    /// Answer 429 Too Many Requests with `Retry-After` over the quota, counted
+    /// in `quota_rejected_total{audience}`. Rejected requests count towards the quota too.
+    pub fn with_reject(mut self) -> Self {
+        self.reject = true;
+        self
+    }
+}
+
+impl<S> Layer<S> for QuotaLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            quotas: self.quotas.clone(),
+            audience: self.audience.clone(),
+            reject: self.reject,
+            service,
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+
+use super::{QuotaStore, StoreError, Usage};
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Inner {
+    counters: HashMap<String, (Usage, Instant)>,
+    last_purge: Instant,
+}
+
+/// Process-local quota store, suitable for a single replica
+pub struct InMemoryQuotaStore {
+    inner: Mutex<Inner>,
+}
+
+impl InMemoryQuotaStore {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                counters: HashMap::new(),
+                last_purge: Instant::now(),
+            }),
+        }
+    }
+}
+
+impl Default for InMemoryQuotaStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl QuotaStore for InMemoryQuotaStore {
+    async fn add(&self, key: &str, usage: Usage, ttl: Duration) -> Result<Usage, StoreError> {
+        let now = Instant::now();
+        let mut inner = self
+            .inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if now.duration_since(inner.last_purge) >= PURGE_INTERVAL {
+            inner.counters.retain(|_, (_, expires)| *expires > now);
+            inner.last_purge = now;
+        }
+
+        let (counter, expires) = inner
+            .counters
+            .entry(key.to_owned())
+            .or_insert((Usage::default(), now + ttl));
+        if *expires <= now {
+            *counter = Usage::default();
+            *expires = now + ttl;
+        }
+        counter.requests += usage.requests;
+        counter.bytes += usage.bytes;
+        Ok(*counter)
+    }
+
+    async fn get(&self, key: &str) -> Result<Usage, StoreError> {
+        let now = Instant::now();
+        let inner = self
+            .inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        match inner.counters.get(key) {
+            Some((usage, expires)) if *expires > now => Ok(*usage),
+            _ => Ok(Usage::default()),
+        }
+    }
+}
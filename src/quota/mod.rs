@@ -0,0 +1,265 @@
+//! Usage quotas of audiences (tenants) over rolling windows.
+//!
+//! ```ignore
+//! let quotas = Quotas::new(RedisQuotaStore::new(redis, "myservice:quota:"), Duration::from_secs(3600))
+//!     .with_default_limit(QuotaLimit::new().with_requests(10_000))
+//!     .with_limit("partner.example.org", QuotaLimit::new().with_requests(100_000).with_bytes(1 << 30));
+//!
+//! let admin = admin_router(admin_config, quotas.admin_routes());
+//! let router = router.layer(QuotaLayer::new(quotas).with_reject());
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    async_trait,
+    extract::{Path, State},
+    routing, Json, Router,
+};
+use http::StatusCode;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter, register_int_counter_vec, IntCounter, IntCounterVec};
+use serde::Serialize;
+use tracing::error;
+
+pub use layer::{QuotaLayer, AUDIENCE};
+pub use memory::InMemoryQuotaStore;
+
+#[cfg(feature = "quota-redis")]
+pub use self::redis::RedisQuotaStore;
+
+mod layer;
+mod memory;
+
+#[cfg(feature = "quota-redis")]
+mod redis;
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    rejected: IntCounterVec,
+    store_errors: IntCounter,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            rejected: register_int_counter_vec!(
+                "quota_rejected_total",
+                "Requests rejected over the quota by audience",
+                &["audience"]
+            )
+            .expect("Can't create stats metrics"),
+            store_errors: register_int_counter!(
+                "quota_store_errors_total",
+                "Failed reads and updates of the quota store"
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Error of the underlying usage storage
+pub type StoreError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Storage of usage counters, one per audience and window
+#[async_trait]
+pub trait QuotaStore: Send + Sync {
+    /// Atomically add to the counters of the key, created with `ttl`.
+    /// Returns the counters after the update.
+    async fn add(&self, key: &str, usage: Usage, ttl: Duration) -> Result<Usage, StoreError>;
+
+    /// Counters of the key, zero if missing
+    async fn get(&self, key: &str) -> Result<Usage, StoreError>;
+}
+
+/// Requests and bytes (request plus response bodies) used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub requests: u64,
+    pub bytes: u64,
+}
+
+/// Limits of an audience within the window, unset ones are unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct QuotaLimit {
+    pub requests: Option<u64>,
+    pub bytes: Option<u64>,
+}
+
+impl QuotaLimit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_requests(mut self, requests: u64) -> Self {
+        self.requests = Some(requests);
+        self
+    }
+
+    pub fn with_bytes(mut self, bytes: u64) -> Self {
+        self.bytes = Some(bytes);
+        self
+    }
+}
+
+/// Usage of an audience in the rolling window ending now
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaStatus {
+    pub audience: String,
+    pub usage: Usage,
+    pub limit: QuotaLimit,
+    /// Seconds until the current window ends
+    pub reset: u64,
+}
+
+impl QuotaStatus {
+    pub fn remaining_requests(&self) -> Option<u64> {
+        self.limit
+            .requests
+            .map(|limit| limit.saturating_sub(self.usage.requests))
+    }
+
+    pub fn remaining_bytes(&self) -> Option<u64> {
+        self.limit
+            .bytes
+            .map(|limit| limit.saturating_sub(self.usage.bytes))
+    }
+
+    /// Whether the usage is over any of the limits
+    pub fn is_exceeded(&self) -> bool {
+        matches!(self.limit.requests, Some(limit) if self.usage.requests > limit)
+            || matches!(self.limit.bytes, Some(limit) if self.usage.bytes > limit)
+    }
+}
+
+/// Quota limits of the audiences and the store accounting their usage, shared between
+/// `QuotaLayer` and the admin routes.
+///
+/// Usage is counted in fixed windows aligned to the unix epoch, so replicas sharing
+/// the store agree on them. The rolling usage is the current window plus the part
+/// of the previous one the rolling window still covers, assuming the previous window
+/// usage was even.
+#[derive(Clone)]
+pub struct Quotas {
+    store: Arc<dyn QuotaStore>,
+    window: Duration,
+    default: QuotaLimit,
+    limits: HashMap<String, QuotaLimit>,
+}
+
+impl Quotas {
+    /// # Arguments
+    ///
+    /// * `store` - where usage is counted, `RedisQuotaStore` to share it between replicas
+    /// * `window` - length of the rolling window, whole seconds
+    pub fn new<S: QuotaStore + 'static>(store: S, window: Duration) -> Self {
+        Self {
+            store: Arc::new(store),
+            window: Duration::from_secs(window.as_secs().max(1)),
+            default: QuotaLimit::default(),
+            limits: HashMap::new(),
+        }
+    }
+
+    /// Limit of the audiences without their own limit, unlimited by default
+    pub fn with_default_limit(mut self, limit: QuotaLimit) -> Self {
+        self.default = limit;
+        self
+    }
+
+    pub fn with_limit(mut self, audience: &str, limit: QuotaLimit) -> Self {
+        self.limits.insert(audience.to_owned(), limit);
+        self
+    }
+
+    pub fn limit(&self, audience: &str) -> QuotaLimit {
+        self.limits.get(audience).copied().unwrap_or(self.default)
+    }
+
+    /// Count the usage and return the status including it
+    pub async fn consume(&self, audience: &str, usage: Usage) -> Result<QuotaStatus, StoreError> {
+        let (current, previous) = self.keys(audience);
+        let (current, previous) = futures::try_join!(
+            self.store.add(&current, usage, self.window * 2),
+            self.store.get(&previous)
+        )
+        .map_err(|err| self.store_error(audience, err))?;
+        Ok(self.status(audience, current, previous))
+    }
+
+    /// Current status without counting anything
+    pub async fn status_of(&self, audience: &str) -> Result<QuotaStatus, StoreError> {
+        let (current, previous) = self.keys(audience);
+        let (current, previous) =
+            futures::try_join!(self.store.get(&current), self.store.get(&previous))
+                .map_err(|err| self.store_error(audience, err))?;
+        Ok(self.status(audience, current, previous))
+    }
+
+    /// Route reading the status of an audience on `GET /admin/quotas/:audience`,
+    /// meant to be passed to `admin_router`
+    pub fn admin_routes(&self) -> Router {
+        Router::new()
+            .route(
+                "/admin/quotas/:audience",
+                routing::get(
+                    |State(quotas): State<Quotas>, Path(audience): Path<String>| async move {
+                        quotas
+                            .status_of(&audience)
+                            .await
+                            .map(Json)
+                            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)
+                    },
+                ),
+            )
+            .with_state(self.clone())
+    }
+
+    // label of the audience in metrics, only the configured ones to bound the cardinality
+    fn label<'a>(&self, audience: &'a str) -> &'a str {
+        if self.limits.contains_key(audience) {
+            audience
+        } else {
+            "other"
+        }
+    }
+
+    fn keys(&self, audience: &str) -> (String, String) {
+        let idx = self.now().as_secs() / self.window.as_secs();
+        (
+            format!("{}:{}", audience, idx),
+            format!("{}:{}", audience, idx.saturating_sub(1)),
+        )
+    }
+
+    fn now(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    fn status(&self, audience: &str, current: Usage, previous: Usage) -> QuotaStatus {
+        let window = self.window.as_secs_f64();
+        let elapsed = self.now().as_secs_f64() % window;
+        let weight = 1.0 - elapsed / window;
+
+        QuotaStatus {
+            audience: audience.to_owned(),
+            usage: Usage {
+                requests: current.requests + (previous.requests as f64 * weight) as u64,
+                bytes: current.bytes + (previous.bytes as f64 * weight) as u64,
+            },
+            limit: self.limit(audience),
+            reset: (window - elapsed).ceil() as u64,
+        }
+    }
+
+    fn store_error(&self, audience: &str, err: StoreError) -> StoreError {
+        error!(audience, "Quota store failed: {:?}", err);
+        METRICS.store_errors.inc();
+        err
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use axum::async_trait;
+use redis::aio::ConnectionManager;
+
+use super::{QuotaStore, StoreError, Usage};
+
+/// Quota store shared between replicas, backed by Redis hashes with `HINCRBY`
+#[derive(Clone)]
+pub struct RedisQuotaStore {
+    connection: ConnectionManager,
+    prefix: String,
+}
+
+impl RedisQuotaStore {
+    /// # Arguments
+    ///
+    /// * `connection` - redis connection
+    /// * `prefix` - prefix of the keys, e.g. "myservice:quota:"
+    pub fn new(connection: ConnectionManager, prefix: &str) -> Self {
+        Self {
+            connection,
+            prefix: prefix.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl QuotaStore for RedisQuotaStore {
+    async fn add(&self, key: &str, usage: Usage, ttl: Duration) -> Result<Usage, StoreError> {
+        let mut connection = self.connection.clone();
+        let key = format!("{}{}", self.prefix, key);
+        let (requests, bytes): (u64, u64) = redis::pipe()
+            .atomic()
+            .hincr(&key, "requests", usage.requests)
+            .hincr(&key, "bytes", usage.bytes)
+            .pexpire(&key, ttl.as_millis() as usize)
+            .ignore()
+            .query_async(&mut connection)
+            .await?;
+
+        Ok(Usage { requests, bytes })
+    }
+
+    async fn get(&self, key: &str) -> Result<Usage, StoreError> {
+        let mut connection = self.connection.clone();
+        let (requests, bytes): (Option<u64>, Option<u64>) = redis::cmd("HMGET")
+            .arg(format!("{}{}", self.prefix, key))
+            .arg("requests")
+            .arg("bytes")
+            .query_async(&mut connection)
+            .await?;
+
+        Ok(Usage {
+            requests: requests.unwrap_or(0),
+            bytes: bytes.unwrap_or(0),
+        })
+    }
+}
@@ -0,0 +1,368 @@
+//! Recording of live requests and their replay against a `Router` in tests, to check
+//! refactored handlers with real traffic shapes.
+//!
+//! Record on staging:
+//!
+//! ```ignore
+//! let recorder = Recorder::create("/var/lib/service/requests.jsonl")?;
+//! let router = router.layer(RecordLayer::new(recorder));
+//! ```
+//!
+//! Replay in a test:
+//!
+//! ```ignore
+//! let recording = Recording::load("tests/data/requests.jsonl")?;
+//! let results = Replay::new(recording).run(build_router(state)).await;
+//! assert!(results.iter().all(|r| !r.status.is_server_error()));
+//! ```
+
+use std::error::Error as StdError;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::body::{Body, Bytes};
+use axum::Router;
+use futures::future::BoxFuture;
+use http::{header, HeaderName, HeaderValue, Method, Request, Response, StatusCode};
+use http_body::Body as HttpBody;
+use http_body_util::LengthLimitError;
+use serde::{Deserialize, Serialize};
+use tower::{Layer, Service, ServiceExt};
+use tracing::warn;
+
+// requests waiting to be written before new ones are dropped
+const QUEUE_SIZE: usize = 1024;
+// longest pauses between replayed requests, 100 times the recorded ones
+const MAX_TIME_SCALE: f64 = 100.0;
+
+/// Request as stored in a recording, one JSON object per line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    /// Time since the recording started
+    pub offset_ms: u64,
+    pub method: String,
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    fn to_request(&self) -> Result<Request<Body>, http::Error> {
+        let mut builder = Request::builder()
+            .method(Method::from_bytes(self.method.as_bytes())?)
+            .uri(self.uri.as_str());
+        for (name, value) in &self.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        builder.body(Body::from(self.body.clone()))
+    }
+}
+
+/// Appends recorded requests to a file from a separate thread, so requests don't wait
+/// for the disk. When the disk can't keep up, requests over 1024 queued ones are dropped
+/// from the recording.
+#[derive(Clone)]
+pub struct Recorder {
+    tx: SyncSender<RecordedRequest>,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Create the recording file, truncating an existing one
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let (tx, rx) = mpsc::sync_channel::<RecordedRequest>(QUEUE_SIZE);
+
+        // stops once every recorder clone is dropped
+        std::thread::Builder::new()
+            .name("request-recorder".to_owned())
+            .spawn(move || {
+                for request in rx {
+                    if let Err(err) = write_line(&mut writer, &request) {
+                        warn!("Failed to record request, error = {}", err);
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            tx,
+            started: Instant::now(),
+        })
+    }
+
+    fn write(&self, request: RecordedRequest) {
+        match self.tx.try_send(request) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("Recording queue is full, request dropped"),
+            Err(TrySendError::Disconnected(_)) => warn!("Recording writer stopped"),
+        }
+    }
+}
+
+fn write_line(writer: &mut BufWriter<File>, request: &RecordedRequest) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, request)?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    recorder: Recorder,
+    skip_headers: Arc<[HeaderName]>,
+    max_body_size: u64,
+    service: S,
+}
+
+impl<S, ResBody> Service<Request<Body>> for Middleware<S>
+where
+    S: Service<Request<Body>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let recorder = self.recorder.clone();
+        let skip_headers = self.skip_headers.clone();
+        let max_body_size = self.max_body_size;
+
+        Box::pin(async move {
+            // bodies of unknown size can't be buffered without risking to lose them
+            let size = req.body().size_hint().upper();
+            if !matches!(size, Some(size) if size <= max_body_size) {
+                return inner.call(req).await;
+            }
+
+            let (parts, body) = req.into_parts();
+            let bytes = match axum::body::to_bytes(body, max_body_size as usize).await {
+                Ok(bytes) => bytes,
+                // the body is gone, so the request can't be handled either
+                Err(err) => {
+                    warn!("Failed to read recorded request body, error = {}", err);
+                    let mut resp = Response::new(ResBody::default());
+                    *resp.status_mut() = if is_length_limit(&err) {
+                        StatusCode::PAYLOAD_TOO_LARGE
+                    } else {
+                        StatusCode::BAD_REQUEST
+                    };
+                    return Ok(resp);
+                }
+            };
+
+            if let Ok(body) = std::str::from_utf8(&bytes) {
+                let headers = parts
+                    .headers
+                    .iter()
+                    .filter(|(name, _)| !skip_headers.contains(name))
+                    .filter_map(|(name, value)| {
+                        Some((name.to_string(), value.to_str().ok()?.to_owned()))
+                    })
+                    .collect();
+                let recorded = RecordedRequest {
+                    offset_ms: recorder.started.elapsed().as_millis() as u64,
+                    method: parts.method.to_string(),
+                    uri: parts.uri.to_string(),
+                    headers,
+                    body: body.to_owned(),
+                };
+                recorder.write(recorded);
+            }
+
+            inner
+                .call(Request::from_parts(parts, Body::from(bytes)))
+                .await
+        })
+    }
+}
+
+fn is_length_limit(err: &axum::Error) -> bool {
+    let mut source = StdError::source(err);
+    while let Some(err) = source {
+        if err.is::<LengthLimitError>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Records requests to a `Recorder`.
+///
+/// `Authorization` and `Cookie` headers aren't recorded, add test credentials on replay.
+/// Requests with bodies of unknown size, over the size limit (64 KiB by default)
+/// or not in UTF-8 are passed through without being recorded. Requests whose bodies
+/// fail to be read are answered 400 Bad Request without calling the service, or 413
+/// Payload Too Large when the body turns out to be over the size limit. The response
+/// body must implement `Default`.
+#[derive(Clone)]
+pub struct RecordLayer {
+    recorder: Recorder,
+    skip_headers: Arc<[HeaderName]>,
+    max_body_size: u64,
+}
+
+impl RecordLayer {
+    pub fn new(recorder: Recorder) -> Self {
+        Self {
+            recorder,
+            skip_headers: Arc::new([header::AUTHORIZATION, header::COOKIE]),
+            max_body_size: 64 * 1024,
+        }
+    }
+
+    /// Replace the headers which aren't recorded
+    pub fn with_skip_headers(mut self, headers: Vec<HeaderName>) -> Self {
+        self.skip_headers = headers.into();
+        self
+    }
+
+    pub fn with_max_body_size(mut self, max_body_size: u64) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+}
+
+impl<S> Layer<S> for RecordLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            recorder: self.recorder.clone(),
+            skip_headers: self.skip_headers.clone(),
+            max_body_size: self.max_body_size,
+            service,
+        }
+    }
+}
+
+/// Requests loaded from a recording file
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    pub requests: Vec<RecordedRequest>,
+}
+
+impl Recording {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let mut requests = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            requests.push(serde_json::from_str(&line)?);
+        }
+        Ok(Self { requests })
+    }
+}
+
+/// Response to a replayed request
+#[derive(Debug)]
+pub struct ReplayResult {
+    pub request: RecordedRequest,
+    pub status: StatusCode,
+    pub headers: http::HeaderMap,
+    pub body: Bytes,
+    pub latency: Duration,
+}
+
+/// Sends recorded requests to a router one by one in the recorded order, so the results
+/// are deterministic as long as the handlers are.
+pub struct Replay {
+    recording: Recording,
+    time_scale: Option<f64>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl Replay {
+    /// Create new replay sending every request right after the previous one is answered
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            recording,
+            time_scale: None,
+            headers: vec![],
+        }
+    }
+
+    /// Keep the recorded pauses between requests multiplied by `time_scale`, e.g. `0.5`
+    /// replays twice as fast. The scale is clamped to `0.0..=100.0`. With
+    /// `tokio::time::pause` the pauses take no real time.
+    pub fn with_time_scale(mut self, time_scale: f64) -> Self {
+        let time_scale = if time_scale.is_nan() { 0.0 } else { time_scale };
+        self.time_scale = Some(time_scale.clamp(0.0, MAX_TIME_SCALE));
+        self
+    }
+
+    /// Add a header to every request, e.g. `Authorization` of a test account
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    /// Requests which can't be built from the recording, e.g. edited by hand,
+    /// are answered with 400 Bad Request without reaching the router
+    pub async fn run(self, router: Router) -> Vec<ReplayResult> {
+        let mut results = Vec::with_capacity(self.recording.requests.len());
+        let started = tokio::time::Instant::now();
+
+        for recorded in self.recording.requests {
+            if let Some(time_scale) = self.time_scale {
+                let offset = Duration::from_millis(recorded.offset_ms).mul_f64(time_scale);
+                tokio::time::sleep_until(started + offset).await;
+            }
+
+            let mut req = match recorded.to_request() {
+                Ok(req) => req,
+                Err(_) => {
+                    results.push(ReplayResult {
+                        request: recorded,
+                        status: StatusCode::BAD_REQUEST,
+                        headers: http::HeaderMap::new(),
+                        body: Bytes::new(),
+                        latency: Duration::ZERO,
+                    });
+                    continue;
+                }
+            };
+            for (name, value) in &self.headers {
+                req.headers_mut().insert(name.clone(), value.clone());
+            }
+
+            let sent = Instant::now();
+            let resp = match router.clone().oneshot(req).await {
+                Ok(resp) => resp,
+                Err(infallible) => match infallible {},
+            };
+            let (parts, body) = resp.into_parts();
+            let body = axum::body::to_bytes(body, usize::MAX)
+                .await
+                .unwrap_or_default();
+
+            results.push(ReplayResult {
+                request: recorded,
+                status: parts.status,
+                headers: parts.headers,
+                body,
+                latency: sent.elapsed(),
+            });
+        }
+
+        results
+    }
+}
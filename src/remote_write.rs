@@ -0,0 +1,313 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use http::{header, Request, StatusCode, Uri};
+use http_body_util::{BodyExt, Full};
+use hyper_util::client::legacy::connect::{Connect, HttpConnector};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+use prometheus::Registry;
+use tokio::time::MissedTickBehavior;
+use tokio::{sync::oneshot, task::JoinHandle};
+use tracing::{error, info, warn};
+
+// pushing more often overloads the endpoint rather than adding resolution
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+// longest delay between retries of a push
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Pushes registry snapshots to a Prometheus remote-write endpoint, for deployments
+/// without a scraper.
+///
+/// Failed pushes are retried with exponential backoff of up to a minute unless the
+/// endpoint rejected the request with a 4xx (except 429). When a push with retries takes
+/// longer than the interval, missed snapshots are skipped rather than queued.
+pub struct RemoteWriter<C = HttpConnector> {
+    client: Client<C, Full<Bytes>>,
+    endpoint: Uri,
+    registry: Option<Registry>,
+    interval: Duration,
+    max_retries: u32,
+    headers: Vec<(header::HeaderName, header::HeaderValue)>,
+}
+
+impl RemoteWriter {
+    /// Create new writer of the prometheus default registry pushing every 15 seconds over http.
+    /// Use `with_client` for https endpoints.
+    pub fn new(endpoint: Uri) -> Self {
+        Self::with_client(Client::builder(TokioExecutor::new()).build_http(), endpoint)
+    }
+}
+
+impl<C> RemoteWriter<C>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    pub fn with_client(client: Client<C, Full<Bytes>>, endpoint: Uri) -> Self {
+        Self {
+            client,
+            endpoint,
+            registry: None,
+            interval: Duration::from_secs(15),
+            max_retries: 3,
+            headers: vec![],
+        }
+    }
+
+    pub fn with_registry(mut self, registry: Registry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Interval between pushes, at least a second
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(MIN_INTERVAL);
+        self
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Add a header to every push, e.g. `Authorization`
+    pub fn with_header(mut self, name: header::HeaderName, value: header::HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    /// Start pushing in a separate tokio task
+    pub fn spawn(self) -> RemoteWriterHandle {
+        let (closer, mut rx) = oneshot::channel::<()>();
+
+        let join_handle = tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        self.push().await;
+                    }
+                    _ = &mut rx => {
+                        // send the final state before exiting
+                        self.push().await;
+                        break;
+                    }
+                }
+            }
+        });
+
+        RemoteWriterHandle {
+            join_handle,
+            closer,
+        }
+    }
+
+    async fn push(&self) {
+        let families = match &self.registry {
+            Some(registry) => registry.gather(),
+            None => prometheus::gather(),
+        };
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+
+        let payload = encode_write_request(&families, timestamp);
+        let payload = match snap::raw::Encoder::new().compress_vec(&payload) {
+            Ok(payload) => payload,
+            Err(err) => {
+                error!("Remote write payload not compressed: {}", err);
+                return;
+            }
+        };
+
+        let mut backoff = Duration::from_millis(500);
+        for attempt in 0..=self.max_retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff = backoff.saturating_mul(2).min(MAX_BACKOFF);
+            }
+
+            match self.send(payload.clone()).await {
+                Ok(status) if status.is_success() => return,
+                Ok(status)
+                    if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS =>
+                {
+                    error!(%status, "Remote write rejected, dropping samples");
+                    return;
+                }
+                Ok(status) => {
+                    warn!(%status, attempt, "Remote write failed");
+                }
+                Err(err) => {
+                    warn!(attempt, "Remote write failed: {}", err);
+                }
+            }
+        }
+
+        error!(
+            retries = self.max_retries,
+            "Remote write failed after retries, dropping samples"
+        );
+    }
+
+    async fn send(
+        &self,
+        payload: Vec<u8>,
+    ) -> Result<StatusCode, hyper_util::client::legacy::Error> {
+        let mut builder = Request::post(self.endpoint.clone())
+            .header(header::CONTENT_TYPE, "application/x-protobuf")
+            .header(header::CONTENT_ENCODING, "snappy")
+            .header("X-Prometheus-Remote-Write-Version", "0.1.0");
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        let req = builder
+            .body(Full::from(payload))
+            .expect("remote write request must be valid");
+
+        let res = self.client.request(req).await?;
+        let status = res.status();
+        // drain the body so the connection can be reused
+        let _ = res.into_body().collect().await;
+        Ok(status)
+    }
+}
+
+/// Handle of a running `RemoteWriter`
+pub struct RemoteWriterHandle {
+    join_handle: JoinHandle<()>,
+    closer: oneshot::Sender<()>,
+}
+
+impl RemoteWriterHandle {
+    /// Push metrics one last time and stop the writer
+    pub async fn shutdown(self) {
+        let _ = self.closer.send(());
+
+        match tokio::time::timeout(Duration::from_secs(10), self.join_handle).await {
+            Err(e) => {
+                error!("Remote writer timed out during shutdown, error = {:?}", e);
+            }
+            Ok(Err(e)) => {
+                error!("Remote writer failed during shutdown, error = {:?}", e);
+            }
+            Ok(Ok(())) => {
+                info!("Remote writer successfully exited");
+            }
+        }
+    }
+}
+
+/// Encodes `prometheus.WriteRequest` protobuf message
+fn encode_write_request(families: &[MetricFamily], timestamp: i64) -> Vec<u8> {
+    let mut buf = vec![];
+
+    for family in families {
+        let name = family.get_name();
+        for metric in family.get_metric() {
+            let labels = metric.get_label();
+            let mut series = |suffix: &str, extra: Option<(&str, String)>, value: f64| {
+                let series = encode_time_series(
+                    &format!("{}{}", name, suffix),
+                    labels,
+                    extra,
+                    value,
+                    timestamp,
+                );
+                // WriteRequest.timeseries = 1
+                encode_bytes(&mut buf, 1, &series);
+            };
+
+            match family.get_field_type() {
+                MetricType::COUNTER => series("", None, metric.get_counter().get_value()),
+                MetricType::GAUGE => series("", None, metric.get_gauge().get_value()),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    for bucket in histogram.get_bucket() {
+                        let le = bucket.get_upper_bound().to_string();
+                        series(
+                            "_bucket",
+                            Some(("le", le)),
+                            bucket.get_cumulative_count() as f64,
+                        );
+                    }
+                    let count = histogram.get_sample_count() as f64;
+                    series("_bucket", Some(("le", "+Inf".to_owned())), count);
+                    series("_sum", None, histogram.get_sample_sum());
+                    series("_count", None, count);
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        series(
+                            "",
+                            Some(("quantile", quantile.get_quantile().to_string())),
+                            quantile.get_value(),
+                        );
+                    }
+                    series("_sum", None, summary.get_sample_sum());
+                    series("_count", None, summary.get_sample_count() as f64);
+                }
+                MetricType::UNTYPED => {}
+            }
+        }
+    }
+
+    buf
+}
+
+fn encode_time_series(
+    name: &str,
+    labels: &[LabelPair],
+    extra: Option<(&str, String)>,
+    value: f64,
+    timestamp: i64,
+) -> Vec<u8> {
+    let mut pairs = vec![("__name__", name)];
+    pairs.extend(labels.iter().map(|l| (l.get_name(), l.get_value())));
+    if let Some((label, value)) = &extra {
+        pairs.push((label, value.as_str()));
+    }
+    // remote write requires labels sorted by name
+    pairs.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut buf = vec![];
+    for (label, value) in pairs {
+        let mut pair = vec![];
+        encode_bytes(&mut pair, 1, label.as_bytes());
+        encode_bytes(&mut pair, 2, value.as_bytes());
+        // TimeSeries.labels = 1
+        encode_bytes(&mut buf, 1, &pair);
+    }
+
+    let mut sample = vec![];
+    // Sample.value = 1 (double)
+    encode_varint(&mut sample, (1 << 3) | 1);
+    sample.extend_from_slice(&value.to_le_bytes());
+    // Sample.timestamp = 2 (int64)
+    encode_varint(&mut sample, 2 << 3);
+    encode_varint(&mut sample, timestamp as u64);
+    // TimeSeries.samples = 2
+    encode_bytes(&mut buf, 2, &sample);
+
+    buf
+}
+
+fn encode_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    encode_varint(buf, (field << 3) | 2);
+    encode_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
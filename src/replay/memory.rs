@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+
+use super::{NonceStore, StoreError};
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Inner {
+    expires: HashMap<String, Instant>,
+    last_purge: Instant,
+}
+
+/// Process-local nonce store, suitable for a single replica
+pub struct InMemoryNonceStore {
+    inner: Mutex<Inner>,
+}
+
+impl InMemoryNonceStore {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                expires: HashMap::new(),
+                last_purge: Instant::now(),
+            }),
+        }
+    }
+}
+
+impl Default for InMemoryNonceStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl NonceStore for InMemoryNonceStore {
+    async fn insert(&self, nonce: &str, ttl: Duration) -> Result<bool, StoreError> {
+        let now = Instant::now();
+        let mut inner = self
+            .inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if now.duration_since(inner.last_purge) >= PURGE_INTERVAL {
+            inner.expires.retain(|_, expires| *expires > now);
+            inner.last_purge = now;
+        }
+
+        match inner.expires.get(nonce) {
+            Some(expires) if *expires > now => Ok(false),
+            _ => {
+                inner.expires.insert(nonce.to_owned(), now + ttl);
+                Ok(true)
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use axum::async_trait;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use tracing::{error, warn};
+
+pub use memory::InMemoryNonceStore;
+
+#[cfg(feature = "replay-protection-redis")]
+pub use self::redis::RedisNonceStore;
+
+mod memory;
+
+#[cfg(feature = "replay-protection-redis")]
+mod redis;
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    rejected: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            rejected: register_int_counter_vec!(
+                "replay_rejected_total",
+                "Requests rejected by replay protection",
+                &["reason"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Error of the underlying nonce storage
+pub type StoreError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Storage of nonces seen within the replay window
+#[async_trait]
+pub trait NonceStore: Send + Sync {
+    /// Atomically remember the nonce for `ttl`.
+    /// Returns `false` if the nonce is already remembered.
+    async fn insert(&self, nonce: &str, ttl: Duration) -> Result<bool, StoreError>;
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    /// Timestamp is outside of the window around current time
+    Stale,
+    /// Nonce was already used within the window
+    Replayed,
+    /// Nonce store failed
+    Store(StoreError),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Stale => write!(f, "timestamp is outside of the allowed window"),
+            ReplayError::Replayed => write!(f, "nonce was already used"),
+            ReplayError::Store(err) => write!(f, "nonce store failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Rejects messages with stale timestamps or reused nonces.
+///
+/// Used by signed request receivers (webhooks etc.) where the sender supplies
+/// a unique nonce and the signing time. Rejections are counted in `replay_rejected_total{reason}`.
+pub struct ReplayGuard<S> {
+    store: S,
+    window: Duration,
+}
+
+impl<S: NonceStore> ReplayGuard<S> {
+    /// # Arguments
+    ///
+    /// * `store` - where seen nonces are kept
+    /// * `window` - maximum allowed difference between the timestamp and current time
+    pub fn new(store: S, window: Duration) -> Self {
+        Self { store, window }
+    }
+
+    /// Check the message and remember its nonce
+    pub async fn check(&self, nonce: &str, timestamp: SystemTime) -> Result<(), ReplayError> {
+        let now = SystemTime::now();
+        let skew = now
+            .duration_since(timestamp)
+            .or_else(|_| timestamp.duration_since(now))
+            .unwrap_or_default();
+
+        if skew > self.window {
+            warn!(nonce, ?skew, "Stale message rejected");
+            METRICS.rejected.with_label_values(&["stale"]).inc();
+            return Err(ReplayError::Stale);
+        }
+
+        // timestamps are accepted from `window` in the past up to `window` in the future,
+        // the nonce must be remembered for as long as its timestamp stays acceptable
+        match self.store.insert(nonce, self.window * 2).await {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                warn!(nonce, "Replayed message rejected");
+                METRICS.rejected.with_label_values(&["replayed"]).inc();
+                Err(ReplayError::Replayed)
+            }
+            Err(err) => {
+                error!(nonce, "Nonce store failed: {:?}", err);
+                METRICS.rejected.with_label_values(&["store_error"]).inc();
+                Err(ReplayError::Store(err))
+            }
+        }
+    }
+}
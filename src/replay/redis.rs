@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use axum::async_trait;
+use redis::aio::ConnectionManager;
+
+use super::{NonceStore, StoreError};
+
+/// Nonce store shared between replicas, backed by Redis `SET NX PX`
+#[derive(Clone)]
+pub struct RedisNonceStore {
+    connection: ConnectionManager,
+    prefix: String,
+}
+
+impl RedisNonceStore {
+    /// # Arguments
+    ///
+    /// * `connection` - redis connection
+    /// * `prefix` - prefix of the keys, e.g. "myservice:nonce:"
+    pub fn new(connection: ConnectionManager, prefix: &str) -> Self {
+        Self {
+            connection,
+            prefix: prefix.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl NonceStore for RedisNonceStore {
+    async fn insert(&self, nonce: &str, ttl: Duration) -> Result<bool, StoreError> {
+        let mut connection = self.connection.clone();
+        let reply: Option<String> = redis::cmd("SET")
+            .arg(format!("{}{}", self.prefix, nonce))
+            .arg(1)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl.as_millis() as u64)
+            .query_async(&mut connection)
+            .await?;
+
+        Ok(reply.is_some())
+    }
+}
@@ -0,0 +1,192 @@
+//! Per-request values (context, deadline, locale) readable anywhere in the call stack of
+//! the request without passing them through every function.
+//!
+//! ```ignore
+//! let router = router
+//!     .layer(RequestScopeLayer::new().with_extension::<Deadline>())
+//!     .layer(TimeoutLayer::new(Duration::from_secs(10)));
+//!
+//! // in a handler
+//! request_scope::set(Locale::from_headers(&headers));
+//!
+//! // deep in the domain code
+//! let locale = request_scope::get::<Locale>().unwrap_or_default();
+//! ```
+//!
+//! Every request gets an empty scope dropped when its response is produced, so values
+//! never leak to other requests served by the same connection or worker thread.
+//! Tasks spawned by a handler don't inherit the scope, run them with `fork` to pass
+//! a copy explicitly.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use http::{Extensions, Request};
+use tower::{Layer, Service};
+use tracing::warn;
+
+tokio::task_local! {
+    static SCOPE: RefCell<Extensions>;
+}
+
+/// Store the value in the current request scope, replacing a value of the same type.
+///
+/// Returns `false` and drops the value outside of a scope.
+pub fn set<T>(value: T) -> bool
+where
+    T: Clone + Send + Sync + 'static,
+{
+    let stored = SCOPE
+        .try_with(|scope| {
+            scope.borrow_mut().insert(value);
+        })
+        .is_ok();
+    if !stored {
+        warn!(
+            "Request scope value {} set outside of a request scope",
+            std::any::type_name::<T>()
+        );
+    }
+    stored
+}
+
+/// Get a copy of the value from the current request scope
+pub fn get<T>() -> Option<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    with(|value: Option<&T>| value.cloned())
+}
+
+/// Borrow the value from the current request scope, `None` outside of a scope.
+///
+/// Setting values from within `f` panics.
+pub fn with<T, R, F>(f: F) -> R
+where
+    T: Send + Sync + 'static,
+    F: FnOnce(Option<&T>) -> R,
+{
+    let mut f = Some(f);
+    let res = SCOPE.try_with(|scope| {
+        let f = f.take().expect("called once");
+        f(scope.borrow().get::<T>())
+    });
+    match res {
+        Ok(res) => res,
+        Err(_) => (f.take().expect("called once"))(None),
+    }
+}
+
+/// Whether the code runs in a request scope
+pub fn is_set() -> bool {
+    SCOPE.try_with(|_| ()).is_ok()
+}
+
+/// Run the future in a new scope with the given values
+pub fn scope<F: Future>(values: Extensions, fut: F) -> impl Future<Output = F::Output> {
+    SCOPE.scope(RefCell::new(values), fut)
+}
+
+/// Run the future in a copy of the current scope, e.g. a task spawned by a handler.
+/// The copy is made right away, changes made by the future aren't visible
+/// in the current scope.
+pub fn fork<F: Future>(fut: F) -> impl Future<Output = F::Output> {
+    let values = SCOPE
+        .try_with(|scope| scope.borrow().clone())
+        .unwrap_or_default();
+    scope(values, fut)
+}
+
+type CopyFn = fn(&Extensions, &mut Extensions);
+
+fn copy_extension<T>(from: &Extensions, to: &mut Extensions)
+where
+    T: Clone + Send + Sync + 'static,
+{
+    if let Some(value) = from.get::<T>() {
+        to.insert(value.clone());
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    extensions: Arc<[CopyFn]>,
+    service: S,
+}
+
+impl<S, B> Service<Request<B>> for Middleware<S>
+where
+    S: Service<Request<B>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+
+        let mut values = Extensions::new();
+        for copy in self.extensions.iter() {
+            copy(req.extensions(), &mut values);
+        }
+
+        // the scope covers the handler only, not streaming of the response body
+        Box::pin(scope(values, inner.call(req)))
+    }
+}
+
+/// Runs every request in a new request scope.
+///
+/// Request extensions of the types added with `with_extension` are copied to the scope,
+/// so the layer has to be inside of the layers adding them, e.g. `TimeoutLayer`.
+#[derive(Clone)]
+pub struct RequestScopeLayer {
+    extensions: Arc<[CopyFn]>,
+}
+
+impl Default for RequestScopeLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestScopeLayer {
+    pub fn new() -> Self {
+        Self {
+            extensions: Arc::new([]),
+        }
+    }
+
+    /// Copy the request extension of type `T` to the scope
+    pub fn with_extension<T>(mut self) -> Self
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        let mut extensions = self.extensions.to_vec();
+        extensions.push(copy_extension::<T>);
+        self.extensions = extensions.into();
+        self
+    }
+}
+
+impl<S> Layer<S> for RequestScopeLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Middleware {
+            extensions: self.extensions.clone(),
+            service,
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use axum::http::{header::CACHE_CONTROL, HeaderValue};
+use axum::response::Response;
+use axum::routing::{MethodRouter, Router};
+use tower::layer::layer_fn;
+
+use crate::middleware::metrics::metered;
+use crate::middleware::timeout::RouteTimeoutLayer;
+use crate::middleware::{BodyLimitLayer, MetricsConfig};
+
+/// Policies of a route, applied by `RouteTable`
+#[derive(Debug, Clone, Default)]
+pub struct RoutePolicy {
+    metrics_label: Option<String>,
+    metrics: MetricsConfig,
+    body_limit: Option<u64>,
+    timeout: Option<Duration>,
+    cache_ttl: Option<Duration>,
+    authn: bool,
+}
+
+impl RoutePolicy {
+    /// `path` label of the route metrics, the route path by default
+    pub fn metrics_label(&self) -> Option<&str> {
+        self.metrics_label.as_deref()
+    }
+
+    pub fn body_limit(&self) -> Option<u64> {
+        self.body_limit
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn cache_ttl(&self) -> Option<Duration> {
+        self.cache_ttl
+    }
+
+    /// Whether requests without a valid access token are rejected
+    pub fn authn(&self) -> bool {
+        self.authn
+    }
+}
+
+/// Route declared together with its policies
+///
+/// ```ignore
+/// let router = RouteTable::new()
+///     .route(
+///         RouteSpec::new("/api/v1/rooms", post(create_room))
+///             .with_authn()
+///             .with_body_limit(64 * 1024)
+///             .with_timeout(Duration::from_secs(5)),
+///     )
+///     .route(RouteSpec::new("/api/v1/config", get(config)).with_cache_ttl(Duration::from_secs(60)))
+///     .into_router();
+/// ```
+pub struct RouteSpec {
+    path: String,
+    handler: MethodRouter,
+    policy: RoutePolicy,
+}
+
+impl RouteSpec {
+    pub fn new(path: &str, handler: MethodRouter) -> Self {
+        Self {
+            path: path.to_owned(),
+            handler,
+            policy: RoutePolicy::default(),
+        }
+    }
+
+    /// Record metrics under `label` instead of the route path, e.g. to group routes
+    pub fn with_metrics_label(mut self, label: &str) -> Self {
+        self.policy.metrics_label = Some(label.to_owned());
+        self
+    }
+
+    pub fn with_metrics_config(mut self, config: MetricsConfig) -> Self {
+        self.policy.metrics = config;
+        self
+    }
+
+    /// Reject requests with declared body size over the limit with 413
+    pub fn with_body_limit(mut self, body_size_limit: u64) -> Self {
+        self.policy.body_limit = Some(body_size_limit);
+        self
+    }
+
+    /// Answer 504 after the timeout, overrides the `TimeoutLayer` timeout
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.policy.timeout = Some(timeout);
+        self
+    }
+
+    /// Let clients and proxies cache responses for `ttl` unless the handler set
+    /// `Cache-Control` itself, `max-age` is rounded up to whole seconds
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.policy.cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Reject requests without a valid access token with 401, handlers still extract
+    /// the account with `AccountIdExtractor`
+    #[cfg(feature = "authn-extractor")]
+    pub fn with_authn(mut self) -> Self {
+        self.policy.authn = true;
+        self
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn policy(&self) -> &RoutePolicy {
+        &self.policy
+    }
+
+    /// Add the route to `router`, the layers are applied from the innermost:
+    /// cache TTL, authn, body limit, timeout, metrics. Routes with the same path are
+    /// merged into one method router, so each method keeps its own policies.
+    fn apply(self, router: Router) -> Router {
+        let path = self.path;
+        let policy = self.policy;
+        let mut handler = self.handler;
+
+        if let Some(ttl) = policy.cache_ttl {
+            // a sub-second TTL would become `max-age=0`, disabling the cache
+            let secs = ttl.as_secs() + u64::from(ttl.subsec_nanos() > 0);
+            let value = HeaderValue::from_str(&format!("max-age={}", secs))
+                .expect("cache control must be a valid header value");
+            handler = handler.layer(axum::middleware::map_response(move |mut res: Response| {
+                let value = value.clone();
+                async move {
+                    if res.status().is_success() {
+                        res.headers_mut().entry(CACHE_CONTROL).or_insert(value);
+                    }
+                    res
+                }
+            }));
+        }
+
+        #[cfg(feature = "authn-extractor")]
+        if policy.authn {
+            handler = handler.layer(axum::middleware::from_extractor::<
+                crate::extractors::AccountIdExtractor,
+            >());
+        }
+
+        if let Some(limit) = policy.body_limit {
+            handler = handler.layer(BodyLimitLayer::new(limit));
+        }
+
+        if let Some(timeout) = policy.timeout {
+            handler = handler.layer(RouteTimeoutLayer::new(timeout));
+        }
+
+        let label = policy.metrics_label.unwrap_or_else(|| path.clone());
+        let metrics = policy.metrics;
+        handler = handler.layer(layer_fn(move |route| {
+            metered(&label, metrics.clone(), route)
+        }));
+
+        router.route(&path, handler)
+    }
+}
+
+/// Assembles an axum `Router` from declared routes, so every route gets its policies
+/// applied the same way and the policies can be listed for audit
+#[derive(Default)]
+pub struct RouteTable {
+    routes: Vec<RouteSpec>,
+}
+
+impl RouteTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(mut self, spec: RouteSpec) -> Self {
+        self.routes.push(spec);
+        self
+    }
+
+    /// Declared routes and their policies
+    pub fn routes(&self) -> impl Iterator<Item = (&str, &RoutePolicy)> {
+        self.routes.iter().map(|spec| (spec.path(), spec.policy()))
+    }
+
+    pub fn into_router(self) -> Router {
+        self.routes
+            .into_iter()
+            .fold(Router::new(), |router, spec| spec.apply(router))
+    }
+}
@@ -0,0 +1,174 @@
+//! Secrets loaded from mounted files or env, reloaded when rotated.
+//!
+//! ```ignore
+//! let db_password = SecretFile::new("/var/run/secrets/db/password").spawn()?;
+//!
+//! let mut changes = db_password.clone();
+//! tokio::spawn(async move {
+//!     while changes.changed().await.is_ok() {
+//!         pool.set_password(changes.get().expose());
+//!     }
+//! });
+//! ```
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+// rotations take minutes to propagate to mounted volumes anyway
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Secret value, formatted as `[REDACTED]` so it can't leak into logs by accident
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret([REDACTED])")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[REDACTED]")
+    }
+}
+
+#[derive(Debug)]
+pub enum SecretError {
+    /// Env variable is not set or isn't unicode
+    Env(String),
+    /// Secret file can't be read
+    File(PathBuf, std::io::Error),
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::Env(name) => write!(f, "secret env variable {} is not set", name),
+            SecretError::File(path, err) => {
+                write!(f, "failed to read secret {}: {}", path.display(), err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SecretError {}
+
+/// Read the secret from a file, trailing newlines are trimmed
+pub fn read_file(path: &Path) -> Result<Secret, SecretError> {
+    let value =
+        std::fs::read_to_string(path).map_err(|err| SecretError::File(path.to_owned(), err))?;
+    Ok(Secret(value.trim_end_matches(&['\r', '\n'][..]).to_owned()))
+}
+
+pub fn read_env(name: &str) -> Result<Secret, SecretError> {
+    std::env::var(name)
+        .map(Secret)
+        .map_err(|_| SecretError::Env(name.to_owned()))
+}
+
+/// Secret mounted as a file, e.g. from a Kubernetes secret volume, re-read periodically
+/// so rotated values are picked up without a restart
+pub struct SecretFile {
+    path: PathBuf,
+    interval: Duration,
+}
+
+impl SecretFile {
+    /// Create new secret file re-read every 30 seconds
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            interval: Duration::from_secs(30),
+        }
+    }
+
+    /// Interval between re-reads of the file, at least a second
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(MIN_INTERVAL);
+        self
+    }
+
+    /// Read the secret and start re-reading it in a separate tokio task,
+    /// which stops once every handle is dropped
+    pub fn spawn(self) -> Result<SecretHandle, SecretError> {
+        let (tx, rx) = watch::channel(read_file(&self.path)?);
+
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.tick().await;
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = tx.closed() => break,
+                }
+
+                match read_file(&self.path) {
+                    Ok(secret) => {
+                        let changed = tx.send_if_modified(|current| {
+                            if *current == secret {
+                                false
+                            } else {
+                                *current = secret;
+                                true
+                            }
+                        });
+                        if changed {
+                            info!(path = %self.path.display(), "Secret reloaded");
+                        }
+                    }
+                    // keep the current value, the file is likely being replaced
+                    Err(err) => warn!("{}", err),
+                }
+            }
+        });
+
+        Ok(SecretHandle { rx })
+    }
+}
+
+/// Current value of a secret
+#[derive(Clone)]
+pub struct SecretHandle {
+    rx: watch::Receiver<Secret>,
+}
+
+impl SecretHandle {
+    /// Secret from env, it never changes
+    pub fn from_env(name: &str) -> Result<Self, SecretError> {
+        let (_tx, rx) = watch::channel(read_env(name)?);
+        Ok(Self { rx })
+    }
+
+    pub fn get(&self) -> Secret {
+        self.rx.borrow().clone()
+    }
+
+    /// Wait until the secret is rotated, fails once the secret can't change anymore
+    pub async fn changed(&mut self) -> Result<(), watch::error::RecvError> {
+        self.rx.changed().await
+    }
+}
+
+impl fmt::Debug for SecretHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SecretHandle")
+            .field(&*self.rx.borrow())
+            .finish()
+    }
+}
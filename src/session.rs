@@ -0,0 +1,227 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequestParts},
+    http::{header, request::Parts, HeaderValue, StatusCode},
+    response::{IntoResponseParts, ResponseParts},
+};
+use cookie::{Cookie, CookieJar};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::warn;
+
+pub use cookie::{Key, SameSite};
+
+const MAX_TTL: Duration = Duration::from_secs(400 * 24 * 3600);
+
+#[derive(Serialize, Deserialize)]
+struct Payload<T> {
+    exp: u64,
+    data: T,
+}
+
+/// Cookie name or path which can't be sent in a `Set-Cookie` header
+#[derive(Debug)]
+pub struct InvalidSessionConfig {
+    field: &'static str,
+    value: String,
+}
+
+impl fmt::Display for InvalidSessionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid session cookie {}: {:?}", self.field, self.value)
+    }
+}
+
+impl std::error::Error for InvalidSessionConfig {}
+
+/// Settings of encrypted cookie sessions.
+///
+/// Cookie values are encrypted and authenticated with AES-256-GCM.
+/// Should be added to the router as `Extension(Arc<SessionConfig>)`.
+pub struct SessionConfig {
+    name: String,
+    keys: Vec<Key>,
+    ttl: Duration,
+    same_site: SameSite,
+    secure: bool,
+    path: String,
+}
+
+impl SessionConfig {
+    /// Create new config with 1 day TTL, `SameSite=Lax`, `Secure` and `Path=/`.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - cookie name, a token without separators like `=` or `;`
+    /// * `key` - key used to encrypt new sessions
+    pub fn new(name: &str, key: Key) -> Result<Self, InvalidSessionConfig> {
+        let is_token = |c: char| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c);
+        if name.is_empty() || !name.chars().all(is_token) {
+            return Err(InvalidSessionConfig {
+                field: "name",
+                value: name.to_owned(),
+            });
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            keys: vec![key],
+            ttl: Duration::from_secs(24 * 3600),
+            same_site: SameSite::Lax,
+            secure: true,
+            path: "/".to_owned(),
+        })
+    }
+
+    /// Also accept sessions encrypted with a previous key, for key rotation.
+    /// Such sessions are re-encrypted with the current key on the next write.
+    pub fn with_previous_key(mut self, key: Key) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    /// Session lifetime, capped at 400 days like browsers cap cookie lifetimes
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl.min(MAX_TTL);
+        self
+    }
+
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Cookie path, `/` followed by visible ASCII characters except `;`
+    pub fn with_path(mut self, path: &str) -> Result<Self, InvalidSessionConfig> {
+        let valid = path.starts_with('/') && path.chars().all(|c| c.is_ascii_graphic() && c != ';');
+        if !valid {
+            return Err(InvalidSessionConfig {
+                field: "path",
+                value: path.to_owned(),
+            });
+        }
+
+        self.path = path.to_owned();
+        Ok(self)
+    }
+
+    /// Build `Set-Cookie` storing the session data
+    pub fn set<T: Serialize>(&self, data: &T) -> Result<SetSession, serde_json::Error> {
+        let payload = Payload {
+            exp: unix_now() + self.ttl.as_secs(),
+            data,
+        };
+        let value = serde_json::to_string(&payload)?;
+
+        let mut jar = CookieJar::new();
+        jar.private_mut(&self.keys[0])
+            .add(self.cookie(value).finish());
+        let cookie = jar
+            .get(&self.name)
+            .expect("Cookie was just added to the jar");
+
+        Ok(SetSession(header_value(cookie)))
+    }
+
+    /// Build `Set-Cookie` removing the session
+    pub fn remove(&self) -> SetSession {
+        let mut cookie = self.cookie(String::new()).finish();
+        cookie.make_removal();
+
+        SetSession(header_value(&cookie))
+    }
+
+    fn cookie(&self, value: String) -> cookie::CookieBuilder<'static> {
+        Cookie::build(self.name.clone(), value)
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site)
+            .path(self.path.clone())
+            .max_age(cookie::time::Duration::seconds(self.ttl.as_secs() as i64))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, cookie: Cookie<'static>) -> Option<T> {
+        let mut jar = CookieJar::new();
+        let cookie = self
+            .keys
+            .iter()
+            .find_map(|key| jar.private_mut(key).decrypt(cookie.clone()))?;
+
+        match serde_json::from_str::<Payload<T>>(cookie.value()) {
+            Ok(payload) if payload.exp > unix_now() => Some(payload.data),
+            Ok(_) => None,
+            Err(err) => {
+                warn!("Failed to parse session: {:?}", err);
+                None
+            }
+        }
+    }
+}
+
+/// `Set-Cookie` header writing or removing the session, returned by `SessionConfig::set`
+/// and `SessionConfig::remove`. Can be returned from a handler as part of the response.
+pub struct SetSession(HeaderValue);
+
+impl IntoResponseParts for SetSession {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        res.headers_mut().append(header::SET_COOKIE, self.0);
+        Ok(res)
+    }
+}
+
+/// Extracts session data from the encrypted cookie.
+///
+/// Contains `None` if there is no session cookie or it is invalid or expired.
+pub struct Session<T>(pub Option<T>);
+
+#[async_trait]
+impl<S, T> FromRequestParts<S> for Session<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        use axum::RequestPartsExt;
+        let Extension(config) = parts
+            .extract::<Extension<Arc<SessionConfig>>>()
+            .await
+            .ok()
+            .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "No session config"))?;
+
+        let data = parts
+            .headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|x| x.to_str().ok())
+            .flat_map(Cookie::split_parse)
+            .filter_map(|x| x.ok())
+            .find(|x| x.name() == config.name)
+            .and_then(|x| config.decode(x.into_owned()));
+
+        Ok(Self(data))
+    }
+}
+
+fn header_value(cookie: &Cookie<'_>) -> HeaderValue {
+    // the name and the path are validated by the config, the value is encrypted base64
+    HeaderValue::from_str(&cookie.to_string()).expect("Cookie is a valid header value")
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,143 @@
+//! Consistent hashing of keys (rooms, classrooms) to replicas, so every request of
+//! a real-time session lands on the replica holding its state.
+//!
+//! ```ignore
+//! let ring = ShardRing::new(discovery.replicas().await?);
+//! let router = router.layer(ShardRedirectLayer::new(ring.clone(), &self_url, room_id));
+//!
+//! // on membership change
+//! ring.set_members(replicas);
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use tracing::info;
+
+/// Ring of replicas with virtual nodes, changing the membership moves only the keys
+/// of the added or removed replicas.
+///
+/// Members are arbitrary strings, usually replica base URLs. They're normalized, so
+/// `HTTP://Replica-1:80/` and `http://replica-1` are the same member: the scheme and
+/// the host are lowercased, the default port and trailing slashes removed. Cheap to clone,
+/// clones share the membership.
+#[derive(Clone)]
+pub struct ShardRing {
+    inner: Arc<RwLock<Ring>>,
+}
+
+struct Ring {
+    vnodes: usize,
+    members: Vec<String>,
+    points: BTreeMap<u64, usize>,
+}
+
+impl Ring {
+    fn build(vnodes: usize, members: Vec<String>) -> Self {
+        let mut members = members
+            .iter()
+            .map(|member| normalize_member(member))
+            .collect::<Vec<_>>();
+        members.sort();
+        members.dedup();
+
+        let mut points = BTreeMap::new();
+        for (idx, member) in members.iter().enumerate() {
+            for vnode in 0..vnodes {
+                points.insert(hash(format!("{}#{}", member, vnode).as_bytes()), idx);
+            }
+        }
+
+        Self {
+            vnodes,
+            members,
+            points,
+        }
+    }
+}
+
+impl ShardRing {
+    /// Create new ring with 128 virtual nodes per member
+    pub fn new(members: Vec<String>) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Ring::build(128, members))),
+        }
+    }
+
+    /// Virtual nodes per member, more nodes spread the keys more evenly.
+    /// Every replica must use the same number.
+    pub fn with_vnodes(self, vnodes: usize) -> Self {
+        {
+            let mut ring = self.inner.write().expect("shard ring lock poisoned");
+            let members = std::mem::take(&mut ring.members);
+            *ring = Ring::build(vnodes, members);
+        }
+        self
+    }
+
+    /// Replace the members, e.g. on a service discovery update
+    pub fn set_members(&self, members: Vec<String>) {
+        let mut ring = self.inner.write().expect("shard ring lock poisoned");
+        let new_ring = Ring::build(ring.vnodes, members);
+        if new_ring.members != ring.members {
+            info!(members = ?new_ring.members, "Shard ring membership changed");
+            *ring = new_ring;
+        }
+    }
+
+    pub fn members(&self) -> Vec<String> {
+        let ring = self.inner.read().expect("shard ring lock poisoned");
+        ring.members.clone()
+    }
+
+    /// Member owning the key, `None` when the ring is empty
+    pub fn owner(&self, key: &str) -> Option<String> {
+        let ring = self.inner.read().expect("shard ring lock poisoned");
+        let hash = hash(key.as_bytes());
+        ring.points
+            .range(hash..)
+            .next()
+            .or_else(|| ring.points.iter().next())
+            .map(|(_, idx)| ring.members[*idx].clone())
+    }
+}
+
+/// Lowercase the scheme and the host, drop the default port and trailing slashes
+pub(crate) fn normalize_member(member: &str) -> String {
+    let member = member.trim().trim_end_matches('/');
+    let (scheme, rest) = match member.split_once("://") {
+        Some((scheme, rest)) => (Some(scheme.to_ascii_lowercase()), rest),
+        None => (None, member),
+    };
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+
+    let mut authority = authority.to_ascii_lowercase();
+    let default_port = match scheme.as_deref() {
+        Some("http") => Some(":80"),
+        Some("https") => Some(":443"),
+        _ => None,
+    };
+    if let Some(port) = default_port {
+        if authority.ends_with(port) {
+            authority.truncate(authority.len() - port.len());
+        }
+    }
+
+    match scheme {
+        Some(scheme) => format!("{}://{}{}", scheme, authority, path),
+        None => format!("{}{}", authority, path),
+    }
+}
+
+// FNV-1a with a final mix, std hashers aren't stable between Rust versions
+// and every replica must place keys the same way
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use cadence::prelude::*;
+use cadence::{MetricBuilder, StatsdClient};
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType};
+use prometheus::Registry;
+use tokio::{sync::oneshot, task::JoinHandle};
+use tracing::{error, info, warn};
+
+pub use cadence;
+
+/// Mirrors prometheus metrics to StatsD/DogStatsD, labels are sent as tags.
+///
+/// Metrics are gathered periodically: counters are sent as increments since the previous export,
+/// gauges as is, histograms and summaries as `<name>.count` increments and `<name>.avg`
+/// gauge of observations since the previous export. Histogram buckets are sent as
+/// `<name>.bucket` increments tagged with `le`.
+pub struct StatsdExporter {
+    client: StatsdClient,
+    registry: Option<Registry>,
+    interval: Duration,
+}
+
+impl StatsdExporter {
+    /// Create new exporter of the prometheus default registry, sending every 10 seconds
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - StatsD client, e.g. `StatsdClient::from_sink("svc", UdpMetricSink::from(..)?)`
+    pub fn new(client: StatsdClient) -> Self {
+        Self {
+            client,
+            registry: None,
+            interval: Duration::from_secs(10),
+        }
+    }
+
+    pub fn with_registry(mut self, registry: Registry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Start exporting in a separate tokio task
+    pub fn spawn(self) -> StatsdExporterHandle {
+        let (closer, mut rx) = oneshot::channel::<()>();
+
+        let join_handle = tokio::task::spawn(async move {
+            let mut state = State::default();
+            let mut interval = tokio::time::interval(self.interval);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        self.export(&mut state);
+                    }
+                    _ = &mut rx => {
+                        // flush what was collected since the last tick
+                        self.export(&mut state);
+                        break;
+                    }
+                }
+            }
+        });
+
+        StatsdExporterHandle {
+            join_handle,
+            closer,
+        }
+    }
+
+    fn export(&self, state: &mut State) {
+        let families = match &self.registry {
+            Some(registry) => registry.gather(),
+            None => prometheus::gather(),
+        };
+
+        for family in &families {
+            for metric in family.get_metric() {
+                self.export_metric(state, family, metric);
+            }
+        }
+    }
+
+    fn export_metric(&self, state: &mut State, family: &MetricFamily, metric: &Metric) {
+        let name = family.get_name();
+        let labels = metric.get_label();
+
+        match family.get_field_type() {
+            MetricType::COUNTER => {
+                let delta = state.delta(name, labels, "", metric.get_counter().get_value());
+                self.count(name, labels, delta);
+            }
+            MetricType::GAUGE => {
+                let builder = self
+                    .client
+                    .gauge_with_tags(name, metric.get_gauge().get_value());
+                send(with_tags(builder, labels));
+            }
+            MetricType::HISTOGRAM => {
+                let histogram = metric.get_histogram();
+                self.observations(
+                    state,
+                    name,
+                    labels,
+                    histogram.get_sample_count() as f64,
+                    histogram.get_sample_sum(),
+                );
+
+                let key = format!("{}.bucket", name);
+                for bucket in histogram.get_bucket() {
+                    let le = bucket.get_upper_bound().to_string();
+                    let delta =
+                        state.delta(&key, labels, &le, bucket.get_cumulative_count() as f64);
+                    if delta > 0 {
+                        let builder = self.client.count_with_tags(&key, delta);
+                        send(with_tags(builder, labels).with_tag("le", &le));
+                    }
+                }
+            }
+            MetricType::SUMMARY => {
+                let summary = metric.get_summary();
+                self.observations(
+                    state,
+                    name,
+                    labels,
+                    summary.get_sample_count() as f64,
+                    summary.get_sample_sum(),
+                );
+            }
+            MetricType::UNTYPED => {}
+        }
+    }
+
+    fn observations(
+        &self,
+        state: &mut State,
+        name: &str,
+        labels: &[LabelPair],
+        count: f64,
+        sum: f64,
+    ) {
+        let count_key = format!("{}.count", name);
+        let count = state.delta(&count_key, labels, "", count);
+        let sum = state.delta_f64(&format!("{}.sum", name), labels, "", sum);
+
+        self.count(&count_key, labels, count);
+        if count > 0 {
+            let avg_key = format!("{}.avg", name);
+            let builder = self.client.gauge_with_tags(&avg_key, sum / count as f64);
+            send(with_tags(builder, labels));
+        }
+    }
+
+    fn count(&self, key: &str, labels: &[LabelPair], delta: i64) {
+        if delta > 0 {
+            let builder = self.client.count_with_tags(key, delta);
+            send(with_tags(builder, labels));
+        }
+    }
+}
+
+/// Handle of a running `StatsdExporter`
+pub struct StatsdExporterHandle {
+    join_handle: JoinHandle<()>,
+    closer: oneshot::Sender<()>,
+}
+
+impl StatsdExporterHandle {
+    /// Export metrics one last time and stop the exporter
+    pub async fn shutdown(self) {
+        let _ = self.closer.send(());
+
+        match tokio::time::timeout(Duration::from_secs(3), self.join_handle).await {
+            Err(e) => {
+                error!("StatsD exporter timed out during shutdown, error = {:?}", e);
+            }
+            Ok(Err(e)) => {
+                error!("StatsD exporter failed during shutdown, error = {:?}", e);
+            }
+            Ok(Ok(())) => {
+                info!("StatsD exporter successfully exited");
+            }
+        }
+    }
+}
+
+/// Previously exported values of cumulative metrics
+#[derive(Default)]
+struct State {
+    last: HashMap<String, f64>,
+}
+
+impl State {
+    /// Whole increment since the last export, the fraction is carried over to the next one
+    fn delta(&mut self, key: &str, labels: &[LabelPair], extra: &str, value: f64) -> i64 {
+        let last = self
+            .last
+            .entry(series_key(key, labels, extra))
+            .or_insert(0.0);
+        // counter was reset
+        if value < *last {
+            *last = 0.0;
+        }
+        let delta = (value - *last).floor();
+        *last += delta;
+        delta as i64
+    }
+
+    fn delta_f64(&mut self, key: &str, labels: &[LabelPair], extra: &str, value: f64) -> f64 {
+        let series = series_key(key, labels, extra);
+        let last = self.last.insert(series, value).unwrap_or(0.0);
+        // counter was reset
+        if value < last {
+            value
+        } else {
+            value - last
+        }
+    }
+}
+
+fn series_key(key: &str, labels: &[LabelPair], extra: &str) -> String {
+    let mut series = key.to_owned();
+    for label in labels {
+        series.push(',');
+        series.push_str(label.get_name());
+        series.push('=');
+        series.push_str(label.get_value());
+    }
+    if !extra.is_empty() {
+        series.push(',');
+        series.push_str(extra);
+    }
+    series
+}
+
+fn with_tags<'m, 'c, T>(
+    builder: MetricBuilder<'m, 'c, T>,
+    labels: &'m [LabelPair],
+) -> MetricBuilder<'m, 'c, T>
+where
+    T: cadence::Metric + From<String>,
+{
+    labels.iter().fold(builder, |builder, label| {
+        builder.with_tag(label.get_name(), label.get_value())
+    })
+}
+
+fn send<T>(builder: MetricBuilder<'_, '_, T>)
+where
+    T: cadence::Metric + From<String>,
+{
+    if let Err(err) = builder.try_send() {
+        warn!("StatsD metric not sent: {}", err);
+    }
+}
@@ -0,0 +1,403 @@
+//! S3 compatible object storage helpers.
+//!
+//! ```ignore
+//! let storage = StorageConfig::new("ru-central1")
+//!     .with_endpoint("https://storage.yandexcloud.net")
+//!     .connect()
+//!     .await;
+//!
+//! let url = storage.presigned_get("recordings", &key, Duration::from_secs(3600)).await?;
+//! let size = storage.upload("recordings", &key, file).await?;
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use aws_sdk_s3::config::{retry::RetryConfig, Region};
+use aws_sdk_s3::presigning::{PresigningConfig, PresigningConfigError};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use bytes::BytesMut;
+use once_cell::sync::Lazy;
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::error;
+
+pub use aws_sdk_s3;
+
+// S3 limits of multipart uploads
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+const MAX_PARTS: usize = 10_000;
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    requests_vec: IntCounterVec,
+    duration_vec: HistogramVec,
+    uploaded_vec: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            requests_vec: register_int_counter_vec!(
+                "storage_requests_total",
+                "Object storage requests by result",
+                &["operation", "result"]
+            )
+            .expect("Can't create stats metrics"),
+            duration_vec: register_histogram_vec!(
+                "storage_request_duration",
+                "Duration of object storage requests including retries",
+                &["operation"]
+            )
+            .expect("Can't create stats metrics"),
+            uploaded_vec: register_int_counter_vec!(
+                "storage_uploaded_bytes_total",
+                "Bytes of uploaded parts",
+                &["bucket"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+fn observe<T, E>(operation: &str, started: Instant, result: &Result<T, E>) {
+    let status = if result.is_ok() { "success" } else { "failure" };
+    METRICS
+        .requests_vec
+        .with_label_values(&[operation, status])
+        .inc();
+    METRICS
+        .duration_vec
+        .with_label_values(&[operation])
+        .observe(started.elapsed().as_secs_f64());
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    /// Request to the storage failed after retries
+    Request {
+        operation: &'static str,
+        source: Box<dyn StdError + Send + Sync>,
+    },
+    /// Presigned URL expiration is out of the allowed range
+    Presigning(PresigningConfigError),
+    /// Reading the uploaded data failed
+    Io(std::io::Error),
+    /// Uploaded data is over `max_size`, 10000 parts of the configured size
+    TooLarge { max_size: u64 },
+}
+
+impl StorageError {
+    fn request<E>(operation: &'static str) -> impl FnOnce(E) -> Self
+    where
+        E: StdError + Send + Sync + 'static,
+    {
+        move |err| StorageError::Request {
+            operation,
+            source: Box::new(err),
+        }
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Request { operation, source } => {
+                write!(f, "storage {} request failed: {}", operation, source)
+            }
+            StorageError::Presigning(err) => write!(f, "invalid presigning config: {}", err),
+            StorageError::Io(err) => write!(f, "failed to read uploaded data: {}", err),
+            StorageError::TooLarge { max_size } => {
+                write!(f, "uploaded data exceeds {} bytes", max_size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            StorageError::Request { source, .. } => Some(source.as_ref()),
+            StorageError::Presigning(err) => Some(err),
+            StorageError::Io(err) => Some(err),
+            StorageError::TooLarge { .. } => None,
+        }
+    }
+}
+
+/// Settings of the S3 client, credentials are taken from the environment
+/// (`AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`)
+pub struct StorageConfig {
+    region: String,
+    endpoint: Option<String>,
+    max_attempts: u32,
+    part_size: usize,
+}
+
+impl StorageConfig {
+    /// Create new config with 3 attempts of retryable requests and 8 MiB upload parts
+    pub fn new(region: &str) -> Self {
+        Self {
+            region: region.to_owned(),
+            endpoint: None,
+            max_attempts: 3,
+            part_size: 8 * 1024 * 1024,
+        }
+    }
+
+    /// Endpoint of an S3 compatible storage, buckets are addressed by path
+    pub fn with_endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = Some(endpoint.to_owned());
+        self
+    }
+
+    /// Attempts of requests failing with timeouts, throttling or server errors,
+    /// retried with exponential backoff
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Size of multipart upload parts, at least 5 MiB as S3 requires.
+    /// Uploads are limited to 10000 parts, so larger data fails with `TooLarge`.
+    pub fn with_part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size.max(MIN_PART_SIZE);
+        self
+    }
+
+    pub async fn connect(self) -> Storage {
+        let sdk_config = aws_config::from_env()
+            .region(Region::new(self.region))
+            .retry_config(RetryConfig::standard().with_max_attempts(self.max_attempts))
+            .load()
+            .await;
+
+        let mut config = aws_sdk_s3::config::Builder::from(&sdk_config);
+        if let Some(endpoint) = self.endpoint {
+            config = config.endpoint_url(endpoint).force_path_style(true);
+        }
+
+        Storage {
+            client: Client::from_conf(config.build()),
+            part_size: self.part_size,
+        }
+    }
+}
+
+/// S3 client with the operations services share, requests are counted in
+/// `storage_requests_total{operation,result}` and timed in `storage_request_duration`
+#[derive(Clone)]
+pub struct Storage {
+    client: Client,
+    part_size: usize,
+}
+
+impl Storage {
+    /// Wrap a configured client, uploads are split into 8 MiB parts
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            part_size: 8 * 1024 * 1024,
+        }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// URL to download the object without credentials until it expires
+    pub async fn presigned_get(
+        &self,
+        bucket: &str,
+        key: &str,
+        expires_in: Duration,
+    ) -> Result<String, StorageError> {
+        let config = PresigningConfig::expires_in(expires_in).map_err(StorageError::Presigning)?;
+
+        let started = Instant::now();
+        let result = self
+            .client
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .presigned(config)
+            .await;
+        observe("presign_get", started, &result);
+
+        let request = result.map_err(StorageError::request("presign_get"))?;
+        Ok(request.uri().to_string())
+    }
+
+    /// URL to upload the object with `PUT` without credentials until it expires
+    pub async fn presigned_put(
+        &self,
+        bucket: &str,
+        key: &str,
+        expires_in: Duration,
+    ) -> Result<String, StorageError> {
+        let config = PresigningConfig::expires_in(expires_in).map_err(StorageError::Presigning)?;
+
+        let started = Instant::now();
+        let result = self
+            .client
+            .put_object()
+            .bucket(bucket)
+            .key(key)
+            .presigned(config)
+            .await;
+        observe("presign_put", started, &result);
+
+        let request = result.map_err(StorageError::request("presign_put"))?;
+        Ok(request.uri().to_string())
+    }
+
+    /// Upload the object from `reader` with a multipart upload, returns its size.
+    /// Data over 10000 parts fails with `TooLarge` as soon as the 10001st part is read.
+    ///
+    /// Uploaded parts are counted in `storage_uploaded_bytes_total{bucket}` as they're
+    /// uploaded. The upload is aborted if a part fails, so no incomplete parts are billed.
+    pub async fn upload<R>(
+        &self,
+        bucket: &str,
+        key: &str,
+        mut reader: R,
+    ) -> Result<u64, StorageError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let started = Instant::now();
+        let result = self
+            .client
+            .create_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await;
+        observe("create_multipart_upload", started, &result);
+
+        let upload = result.map_err(StorageError::request("create_multipart_upload"))?;
+        let upload_id = upload.upload_id().unwrap_or_default().to_owned();
+
+        match self
+            .upload_parts(bucket, key, &upload_id, &mut reader)
+            .await
+        {
+            Ok(size) => Ok(size),
+            Err(err) => {
+                let started = Instant::now();
+                let result = self
+                    .client
+                    .abort_multipart_upload()
+                    .bucket(bucket)
+                    .key(key)
+                    .upload_id(&upload_id)
+                    .send()
+                    .await;
+                observe("abort_multipart_upload", started, &result);
+
+                if let Err(e) = result {
+                    error!(
+                        bucket,
+                        key, "Failed to abort multipart upload, error = {}", e
+                    );
+                }
+                Err(err)
+            }
+        }
+    }
+
+    async fn upload_parts<R>(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        reader: &mut R,
+    ) -> Result<u64, StorageError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let uploaded = METRICS.uploaded_vec.with_label_values(&[bucket]);
+        let mut parts = Vec::new();
+        let mut size = 0;
+
+        loop {
+            let mut part = BytesMut::with_capacity(self.part_size);
+            while part.len() < self.part_size {
+                if reader.read_buf(&mut part).await.map_err(StorageError::Io)? == 0 {
+                    break;
+                }
+            }
+
+            // every upload has at least one part, even an empty one
+            if part.is_empty() && !parts.is_empty() {
+                break;
+            }
+            if parts.len() == MAX_PARTS {
+                return Err(StorageError::TooLarge { max_size: size });
+            }
+
+            let part_len = part.len() as u64;
+            let part_number = parts.len() as i32 + 1;
+
+            let started = Instant::now();
+            let result = self
+                .client
+                .upload_part()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .body(ByteStream::from(part.freeze()))
+                .send()
+                .await;
+            observe("upload_part", started, &result);
+
+            let output = result.map_err(StorageError::request("upload_part"))?;
+            parts.push(
+                CompletedPart::builder()
+                    .set_e_tag(output.e_tag().map(ToOwned::to_owned))
+                    .part_number(part_number)
+                    .build(),
+            );
+            uploaded.inc_by(part_len);
+            size += part_len;
+
+            if part_len < self.part_size as u64 {
+                break;
+            }
+        }
+
+        let started = Instant::now();
+        let result = self
+            .client
+            .complete_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await;
+        observe("complete_multipart_upload", started, &result);
+        result.map_err(StorageError::request("complete_multipart_upload"))?;
+
+        Ok(size)
+    }
+
+    /// Check the bucket is reachable with the configured credentials
+    pub async fn health_check(&self, bucket: &str) -> Result<(), StorageError> {
+        let started = Instant::now();
+        let result = self.client.head_bucket().bucket(bucket).send().await;
+        observe("head_bucket", started, &result);
+
+        result.map_err(StorageError::request("head_bucket"))?;
+        Ok(())
+    }
+}
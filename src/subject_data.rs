@@ -0,0 +1,468 @@
+//! Export, anonymization and deletion of a data subject's data across the service.
+//!
+//! Every storage holding personal data registers a provider, requests are served by
+//! admin routes and run in the background one job at a time.
+//!
+//! ```ignore
+//! let handle = SubjectData::new()
+//!     .with_provider(ProfilesProvider::new(db.clone()))
+//!     .with_provider(RecordingsProvider::new(storage.clone()))
+//!     .spawn();
+//!
+//! let routes = subject_data_routes(handle.jobs());
+//! let admin = admin_router(AdminConfig::new(accounts), routes);
+//! // on shutdown
+//! handle.shutdown(Duration::from_secs(30)).await;
+//! ```
+
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    async_trait,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing, Json, Router,
+};
+use once_cell::sync::Lazy;
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use svc_agent::AccountId;
+use svc_error::Error;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    jobs_vec: IntCounterVec,
+    provider_duration_vec: HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            jobs_vec: register_int_counter_vec!(
+                "subject_data_jobs_total",
+                "Subject data jobs by kind and result",
+                &["kind", "result"]
+            )
+            .expect("Can't create stats metrics"),
+            provider_duration_vec: register_histogram_vec!(
+                "subject_data_provider_duration",
+                "Duration of subject data provider calls",
+                &["provider", "kind"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+pub type ProviderError = Box<dyn StdError + Send + Sync>;
+
+/// Storage holding personal data of accounts, e.g. a table of profiles or a bucket of recordings
+#[async_trait]
+pub trait SubjectDataProvider: Send + Sync + 'static {
+    /// Key of the provider in job results and `provider` label of the metrics
+    fn name(&self) -> &'static str;
+
+    /// Everything stored about the account
+    async fn export(&self, account_id: &AccountId) -> Result<Value, ProviderError>;
+
+    /// Replace personal data of the account while keeping records other accounts depend on
+    async fn anonymize(&self, account_id: &AccountId) -> Result<(), ProviderError>;
+
+    async fn delete(&self, account_id: &AccountId) -> Result<(), ProviderError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Export,
+    Anonymize,
+    Delete,
+}
+
+impl JobKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobKind::Export => "export",
+            JobKind::Anonymize => "anonymize",
+            JobKind::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    /// Some providers failed, the others have completed
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: u64,
+    pub kind: JobKind,
+    pub account_id: AccountId,
+    pub status: JobStatus,
+    /// Exported data by provider
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub data: BTreeMap<&'static str, Value>,
+    /// Errors by provider
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<&'static str, String>,
+}
+
+#[derive(Debug)]
+pub enum SubjectDataError {
+    /// The queue is full or the runner is shut down
+    QueueFull,
+}
+
+impl fmt::Display for SubjectDataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubjectDataError::QueueFull => write!(f, "subject data job queue is full"),
+        }
+    }
+}
+
+impl std::error::Error for SubjectDataError {}
+
+/// Runner of subject data jobs.
+///
+/// A job calls every provider, a failed provider doesn't stop the others so the job can
+/// simply be submitted again. Jobs are kept in memory: the last ones are available until
+/// the service restarts. Jobs are counted in `subject_data_jobs_total{kind,result}` and
+/// provider calls are timed in `subject_data_provider_duration{provider,kind}`.
+pub struct SubjectData {
+    providers: Vec<Box<dyn SubjectDataProvider>>,
+    queue_size: usize,
+    max_jobs: usize,
+}
+
+impl Default for SubjectData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SubjectData {
+    /// Create new runner queueing up to 100 jobs and keeping the last 1000 ones
+    pub fn new() -> Self {
+        Self {
+            providers: Vec::new(),
+            queue_size: 100,
+            max_jobs: 1000,
+        }
+    }
+
+    pub fn with_provider<P: SubjectDataProvider>(mut self, provider: P) -> Self {
+        self.providers.push(Box::new(provider));
+        self
+    }
+
+    pub fn with_queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size.max(1);
+        self
+    }
+
+    /// Number of jobs kept, the oldest finished ones are forgotten first. Queued and running
+    /// jobs are never forgotten, so at least the queue size and the running job are kept.
+    pub fn with_max_jobs(mut self, max_jobs: usize) -> Self {
+        self.max_jobs = max_jobs;
+        self
+    }
+
+    /// Start running submitted jobs in a separate tokio task
+    pub fn spawn(self) -> SubjectDataHandle {
+        let (tx, mut rx) = mpsc::channel::<u64>(self.queue_size);
+        let jobs = SubjectDataJobs {
+            tx,
+            jobs: Arc::new(Mutex::new(BTreeMap::new())),
+            next_id: Arc::new(AtomicU64::new(1)),
+            max_jobs: self.max_jobs.max(self.queue_size + 1),
+        };
+        let providers = self.providers;
+
+        let (closer, mut closed) = oneshot::channel::<()>();
+
+        let runner = jobs.clone();
+        let join_handle = tokio::task::spawn(async move {
+            let mut closing = false;
+            loop {
+                let id = tokio::select! {
+                    id = rx.recv() => match id {
+                        Some(id) => id,
+                        None => break,
+                    },
+                    // keep running the queued jobs until the queue is empty
+                    _ = &mut closed, if !closing => {
+                        closing = true;
+                        rx.close();
+                        continue;
+                    }
+                };
+
+                runner.run(id, &providers).await;
+            }
+        });
+
+        SubjectDataHandle {
+            jobs,
+            join_handle,
+            closer,
+        }
+    }
+}
+
+/// Jobs of a running `SubjectData`
+#[derive(Clone)]
+pub struct SubjectDataJobs {
+    tx: mpsc::Sender<u64>,
+    jobs: Arc<Mutex<BTreeMap<u64, Job>>>,
+    next_id: Arc<AtomicU64>,
+    max_jobs: usize,
+}
+
+impl SubjectDataJobs {
+    /// Queue a job, fails without waiting when the queue is full
+    pub fn submit(&self, kind: JobKind, account_id: AccountId) -> Result<Job, SubjectDataError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let job = Job {
+            id,
+            kind,
+            account_id,
+            status: JobStatus::Queued,
+            data: BTreeMap::new(),
+            errors: BTreeMap::new(),
+        };
+
+        {
+            let mut jobs = self.jobs.lock().expect("subject data jobs lock poisoned");
+            jobs.insert(id, job.clone());
+
+            // jobs already accepted must run, so only finished ones are forgotten
+            let excess = jobs.len().saturating_sub(self.max_jobs);
+            let finished = jobs
+                .iter()
+                .filter(|(_, job)| matches!(job.status, JobStatus::Completed | JobStatus::Failed))
+                .map(|(id, _)| *id)
+                .take(excess)
+                .collect::<Vec<_>>();
+            for id in finished {
+                jobs.remove(&id);
+            }
+        }
+
+        if self.tx.try_send(id).is_err() {
+            self.jobs
+                .lock()
+                .expect("subject data jobs lock poisoned")
+                .remove(&id);
+            METRICS
+                .jobs_vec
+                .with_label_values(&[kind.as_str(), "dropped"])
+                .inc();
+            return Err(SubjectDataError::QueueFull);
+        }
+
+        info!(
+            target: "audit",
+            job_id = id,
+            kind = kind.as_str(),
+            account_id = %job.account_id,
+            "Subject data job queued"
+        );
+
+        Ok(job)
+    }
+
+    pub fn get(&self, id: u64) -> Option<Job> {
+        self.jobs
+            .lock()
+            .expect("subject data jobs lock poisoned")
+            .get(&id)
+            .cloned()
+    }
+
+    fn update<F: FnOnce(&mut Job)>(&self, id: u64, f: F) -> Option<Job> {
+        let mut jobs = self.jobs.lock().expect("subject data jobs lock poisoned");
+        jobs.get_mut(&id).map(|job| {
+            f(job);
+            job.clone()
+        })
+    }
+
+    async fn run(&self, id: u64, providers: &[Box<dyn SubjectDataProvider>]) {
+        let job = match self.update(id, |job| job.status = JobStatus::Running) {
+            Some(job) => job,
+            // queued jobs aren't forgotten
+            None => return,
+        };
+        let kind = job.kind.as_str();
+
+        let mut data = BTreeMap::new();
+        let mut errors = BTreeMap::new();
+
+        for provider in providers {
+            let started = Instant::now();
+            let result = match job.kind {
+                JobKind::Export => provider.export(&job.account_id).await.map(Some),
+                JobKind::Anonymize => provider.anonymize(&job.account_id).await.map(|()| None),
+                JobKind::Delete => provider.delete(&job.account_id).await.map(|()| None),
+            };
+            METRICS
+                .provider_duration_vec
+                .with_label_values(&[provider.name(), kind])
+                .observe(started.elapsed().as_secs_f64());
+
+            match result {
+                Ok(Some(value)) => {
+                    data.insert(provider.name(), value);
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    error!(
+                        job_id = id,
+                        provider = provider.name(),
+                        kind,
+                        "Subject data provider failed, error = {}",
+                        err
+                    );
+                    errors.insert(provider.name(), err.to_string());
+                }
+            }
+        }
+
+        let status = if errors.is_empty() {
+            JobStatus::Completed
+        } else {
+            JobStatus::Failed
+        };
+        let result = if errors.is_empty() {
+            "success"
+        } else {
+            "failure"
+        };
+
+        METRICS.jobs_vec.with_label_values(&[kind, result]).inc();
+        info!(
+            target: "audit",
+            job_id = id,
+            kind,
+            account_id = %job.account_id,
+            failed_providers = errors.len(),
+            "Subject data job finished"
+        );
+
+        self.update(id, |job| {
+            job.status = status;
+            job.data = data;
+            job.errors = errors;
+        });
+    }
+}
+
+/// Handle of a running `SubjectData`
+pub struct SubjectDataHandle {
+    jobs: SubjectDataJobs,
+    join_handle: JoinHandle<()>,
+    closer: oneshot::Sender<()>,
+}
+
+impl SubjectDataHandle {
+    pub fn jobs(&self) -> SubjectDataJobs {
+        self.jobs.clone()
+    }
+
+    /// Stop accepting jobs and run the queued ones, waiting up to `timeout`
+    pub async fn shutdown(self, timeout: Duration) {
+        let _ = self.closer.send(());
+
+        match tokio::time::timeout(timeout, self.join_handle).await {
+            Err(e) => {
+                error!(
+                    "Subject data runner timed out during shutdown, error = {:?}",
+                    e
+                );
+            }
+            Ok(Err(e)) => {
+                error!(
+                    "Subject data runner failed during shutdown, error = {:?}",
+                    e
+                );
+            }
+            Ok(Ok(())) => {
+                info!("Subject data runner successfully exited");
+            }
+        }
+    }
+}
+
+/// Routes submitting jobs with
+/// `POST /admin/subject-data/accounts/:account_id/{export,anonymize,delete}`
+/// and reporting them on `GET /admin/subject-data/jobs/:id`.
+///
+/// Submitting answers `202 Accepted` with the queued job. Meant to be passed to `admin_router`.
+pub fn subject_data_routes(jobs: SubjectDataJobs) -> Router {
+    Router::new()
+        .route(
+            "/admin/subject-data/accounts/:account_id/:kind",
+            routing::post(submit),
+        )
+        .route("/admin/subject-data/jobs/:id", routing::get(get_job))
+        .with_state(jobs)
+}
+
+async fn submit(
+    State(jobs): State<SubjectDataJobs>,
+    Path((account_id, kind)): Path<(String, JobKind)>,
+) -> Response {
+    let account_id = match account_id.parse::<AccountId>() {
+        Ok(account_id) => account_id,
+        Err(_) => {
+            return error_response(
+                "invalid_account_id",
+                "Invalid account id",
+                http_02::StatusCode::BAD_REQUEST,
+            )
+        }
+    };
+
+    match jobs.submit(kind, account_id) {
+        Ok(job) => (StatusCode::ACCEPTED, Json(job)).into_response(),
+        Err(SubjectDataError::QueueFull) => error_response(
+            "queue_full",
+            "Too many subject data jobs queued",
+            http_02::StatusCode::SERVICE_UNAVAILABLE,
+        ),
+    }
+}
+
+async fn get_job(State(jobs): State<SubjectDataJobs>, Path(id): Path<u64>) -> Response {
+    match jobs.get(id) {
+        Some(job) => Json(job).into_response(),
+        None => error_response(
+            "job_not_found",
+            "Subject data job not found",
+            http_02::StatusCode::NOT_FOUND,
+        ),
+    }
+}
+
+fn error_response(kind: &str, title: &str, status: http_02::StatusCode) -> Response {
+    let code = StatusCode::from_u16(status.as_u16()).expect("valid status code");
+    (code, Json(Error::new(kind, title, status))).into_response()
+}
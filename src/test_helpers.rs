@@ -0,0 +1,178 @@
+//! Golden file snapshots of HTTP responses for handler tests of downstream services.
+//!
+//! ```ignore
+//! let resp = router.oneshot(request).await?;
+//!
+//! GoldenFile::new("tests/golden/create_room.json")
+//!     .with_header(header::CONTENT_TYPE)
+//!     .with_redacted("id")
+//!     .with_redacted("created_at")
+//!     .assert_response(resp)
+//!     .await;
+//! ```
+//!
+//! Run the tests with `UPDATE_GOLDEN=1` to write the current responses to the files,
+//! then review and commit them.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use bytes::Bytes;
+use http::{HeaderName, Response};
+use http_body::Body as HttpBody;
+use http_body_util::BodyExt;
+use serde_json::{Map, Value};
+
+/// Env variable to set for writing the snapshots instead of comparing them
+pub const UPDATE_ENV: &str = "UPDATE_GOLDEN";
+
+const REDACTED: &str = "[redacted]";
+
+/// Snapshot of a response: the status, the selected headers and the body, normalized so it
+/// only changes when the response contract does
+pub struct GoldenFile {
+    path: PathBuf,
+    headers: Vec<HeaderName>,
+    redacted: Vec<String>,
+}
+
+impl GoldenFile {
+    /// Snapshot stored at `path`, relative to the crate root when tests are run by cargo
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            headers: vec![],
+            redacted: vec![],
+        }
+    }
+
+    /// Include the header in the snapshot, headers aren't included by default
+    pub fn with_header(mut self, name: HeaderName) -> Self {
+        self.headers.push(name);
+        self
+    }
+
+    /// Replace values of the JSON body field at any depth with `"[redacted]"`, for ids,
+    /// timestamps and other values changing between runs
+    pub fn with_redacted(mut self, field: &str) -> Self {
+        self.redacted.push(field.to_owned());
+        self
+    }
+
+    /// Compare the response with the snapshot, panicking with a diff when they differ
+    pub async fn assert_response<B>(&self, resp: Response<B>)
+    where
+        B: HttpBody,
+        B::Error: std::fmt::Debug,
+    {
+        let (parts, body) = resp.into_parts();
+        let body = body
+            .collect()
+            .await
+            .expect("failed to read response body")
+            .to_bytes();
+
+        let headers = self
+            .headers
+            .iter()
+            .filter_map(|name| {
+                let value = parts.headers.get(name)?;
+                Some((
+                    name.to_string(),
+                    Value::String(String::from_utf8_lossy(value.as_bytes()).into_owned()),
+                ))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let mut snapshot = Map::new();
+        snapshot.insert("status".to_owned(), parts.status.as_u16().into());
+        if !headers.is_empty() {
+            snapshot.insert("headers".to_owned(), headers.into_iter().collect());
+        }
+        snapshot.insert("body".to_owned(), self.body_value(&body));
+
+        let actual = serde_json::to_string_pretty(&Value::Object(snapshot))
+            .expect("snapshot is always serializable")
+            + "\n";
+        self.assert_snapshot(&actual);
+    }
+
+    fn body_value(&self, body: &Bytes) -> Value {
+        if body.is_empty() {
+            return Value::Null;
+        }
+
+        match serde_json::from_slice::<Value>(body) {
+            Ok(value) => self.normalize(value),
+            Err(_) => Value::String(String::from_utf8_lossy(body).into_owned()),
+        }
+    }
+
+    fn normalize(&self, value: Value) -> Value {
+        match value {
+            Value::Object(map) => {
+                // sorted regardless of serde_json preserving the order
+                let sorted = map.into_iter().collect::<BTreeMap<_, _>>();
+                let map = sorted
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let value = if self.redacted.contains(&key) && !value.is_null() {
+                            Value::String(REDACTED.to_owned())
+                        } else {
+                            self.normalize(value)
+                        };
+                        (key, value)
+                    })
+                    .collect();
+                Value::Object(map)
+            }
+            Value::Array(values) => {
+                Value::Array(values.into_iter().map(|v| self.normalize(v)).collect())
+            }
+            value => value,
+        }
+    }
+
+    fn assert_snapshot(&self, actual: &str) {
+        if std::env::var_os(UPDATE_ENV).is_some() {
+            if let Some(dir) = self.path.parent() {
+                std::fs::create_dir_all(dir).expect("failed to create golden file directory");
+            }
+            std::fs::write(&self.path, actual).expect("failed to write golden file");
+            return;
+        }
+
+        let expected = match std::fs::read_to_string(&self.path) {
+            Ok(expected) => expected,
+            Err(err) => panic!(
+                "failed to read golden file {}: {}, run with {}=1 to create it",
+                self.path.display(),
+                err,
+                UPDATE_ENV
+            ),
+        };
+
+        if expected != actual {
+            panic!(
+                "response doesn't match golden file {} (- expected, + actual), \
+                 run with {}=1 to update it:\n{}",
+                self.path.display(),
+                UPDATE_ENV,
+                line_diff(&expected, actual)
+            );
+        }
+    }
+}
+
+fn line_diff(expected: &str, actual: &str) -> String {
+    let mut out = String::new();
+    for line in diff::lines(expected, actual) {
+        let _ = match line {
+            diff::Result::Left(line) => writeln!(out, "-{}", line),
+            diff::Result::Right(line) => writeln!(out, "+{}", line),
+            diff::Result::Both(line, _) => writeln!(out, " {}", line),
+        };
+    }
+    out
+}
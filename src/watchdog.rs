@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{http::StatusCode, routing, Router};
+use tracing::{error, info};
+
+// shorter intervals make the checking thread spin
+const MIN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Detects tokio executor lockups: a runtime task ticks a heartbeat and a separate OS thread
+/// checks it, marking the service as not alive once the heartbeat stalls for `threshold`.
+///
+/// Serve `WatchdogHandle::liveness_route` as the liveness probe so Kubernetes restarts a pod
+/// whose runtime is stuck. When every worker is blocked the probe times out, when only some
+/// are, it answers 503.
+pub struct Watchdog {
+    interval: Duration,
+    threshold: Duration,
+}
+
+impl Watchdog {
+    /// Create new watchdog ticking every second and failing after 10 seconds of no ticks
+    pub fn new() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            threshold: Duration::from_secs(10),
+        }
+    }
+
+    /// Interval of the heartbeat ticks and the checks, at least 10 ms
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(MIN_INTERVAL);
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: Duration) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Start the heartbeat task on the current tokio runtime and the checking thread
+    pub fn spawn(self) -> std::io::Result<WatchdogHandle> {
+        let state = Arc::new(State {
+            started: Instant::now(),
+            last_tick_ms: AtomicU64::new(0),
+            alive: AtomicBool::new(true),
+        });
+
+        let heartbeat = state.clone();
+        let interval = self.interval;
+        tokio::task::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                heartbeat.tick();
+                // the watchdog was dropped
+                if Arc::strong_count(&heartbeat) == 1 {
+                    break;
+                }
+            }
+        });
+
+        let checker = Arc::downgrade(&state);
+        let threshold = self.threshold;
+        std::thread::Builder::new()
+            .name("watchdog".to_owned())
+            .spawn(move || {
+                while let Some(state) = checker.upgrade() {
+                    state.check(threshold);
+                    drop(state);
+                    std::thread::sleep(interval);
+                }
+            })?;
+
+        Ok(WatchdogHandle { state })
+    }
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct State {
+    started: Instant,
+    last_tick_ms: AtomicU64,
+    alive: AtomicBool,
+}
+
+impl State {
+    fn tick(&self) {
+        let now = self.started.elapsed().as_millis() as u64;
+        self.last_tick_ms.store(now, Ordering::Relaxed);
+    }
+
+    fn stalled_for(&self) -> Duration {
+        let last = Duration::from_millis(self.last_tick_ms.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last)
+    }
+
+    fn check(&self, threshold: Duration) {
+        let stalled_for = self.stalled_for();
+        let alive = stalled_for < threshold;
+        let was_alive = self.alive.swap(alive, Ordering::Relaxed);
+
+        if was_alive && !alive {
+            error!(
+                stalled_for = ?stalled_for,
+                threshold = ?threshold,
+                "Tokio runtime heartbeat stalled, liveness is failing. \
+                 A task is likely blocking runtime threads (sync IO, std locks, heavy computation)"
+            );
+        } else if !was_alive && alive {
+            info!("Tokio runtime heartbeat recovered");
+        }
+    }
+}
+
+/// Handle of a running `Watchdog`, it stops once every handle clone is dropped
+#[derive(Clone)]
+pub struct WatchdogHandle {
+    state: Arc<State>,
+}
+
+impl WatchdogHandle {
+    pub fn is_alive(&self) -> bool {
+        self.state.alive.load(Ordering::Relaxed)
+    }
+
+    /// Route answering `GET /healthz` with 200 while the runtime heartbeat is fine
+    /// and 503 after it stalled
+    pub fn liveness_route(&self) -> Router {
+        let handle = self.clone();
+        Router::new().route(
+            "/healthz",
+            routing::get(move || async move {
+                if handle.is_alive() {
+                    (StatusCode::OK, "ok")
+                } else {
+                    (StatusCode::SERVICE_UNAVAILABLE, "runtime heartbeat stalled")
+                }
+            }),
+        )
+    }
+}
@@ -0,0 +1,253 @@
+//! Throttling of database writes per pod, to take load off an overloaded database during
+//! an incident without scaling the service down.
+//!
+//! Nothing is throttled until a limit is set, usually through the admin routes:
+//!
+//! ```ignore
+//! let throttle = WriteThrottle::new();
+//! let admin = admin_router(admin_config, throttle.admin_routes());
+//!
+//! // in the write path
+//! throttle.acquire().await;
+//! sqlx::query("INSERT INTO event ...").execute(&pool).await?;
+//! ```
+//!
+//! ```sh
+//! curl -X PUT /admin/write-throttle -d '{"qps": 50}'
+//! ```
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{extract::State, routing, Json, Router};
+use http::StatusCode;
+use once_cell::sync::Lazy;
+use prometheus::{register_gauge, register_int_counter, Gauge, IntCounter};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tracing::warn;
+
+// one write per 100 seconds, lower limits would make writes wait for hours
+const MIN_QPS: f64 = 0.01;
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    limit: Gauge,
+    throttled: IntCounter,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            limit: register_gauge!(
+                "db_write_throttle_qps",
+                "Database writes per second allowed by the throttle, 0 when not limited"
+            )
+            .expect("Can't create stats metrics"),
+            throttled: register_int_counter!(
+                "db_writes_throttled_total",
+                "Database writes delayed or rejected by the throttle"
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Write rate limit of a pod
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WriteLimit {
+    /// Writes per second
+    pub qps: f64,
+    /// Writes allowed at once after a quiet period, `qps` rounded up by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+}
+
+impl WriteLimit {
+    fn validate(&self) -> Result<(), InvalidWriteLimit> {
+        if self.qps.is_finite() && self.qps >= MIN_QPS {
+            Ok(())
+        } else {
+            Err(InvalidWriteLimit { qps: self.qps })
+        }
+    }
+
+    fn burst(&self) -> f64 {
+        match self.burst {
+            Some(burst) => f64::from(burst.max(1)),
+            None => self.qps.ceil().max(1.0),
+        }
+    }
+}
+
+/// Write limit with `qps` not finite or under 0.01
+#[derive(Debug)]
+pub struct InvalidWriteLimit {
+    qps: f64,
+}
+
+impl fmt::Display for InvalidWriteLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid write limit qps = {}, must be at least {}",
+            self.qps, MIN_QPS
+        )
+    }
+}
+
+impl std::error::Error for InvalidWriteLimit {}
+
+struct Bucket {
+    limit: Option<WriteLimit>,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    /// Take a token, returns how long to wait for it, the token is reserved either way
+    fn take(&mut self, limit: WriteLimit) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.qps).min(limit.burst());
+        self.refilled = now;
+        self.tokens -= 1.0;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::try_from_secs_f64(-self.tokens / limit.qps).unwrap_or(Duration::MAX)
+        }
+    }
+}
+
+/// Token bucket shared by the write paths of a pod and the admin routes adjusting it
+#[derive(Clone)]
+pub struct WriteThrottle {
+    bucket: Arc<Mutex<Bucket>>,
+    // wakes waiting writes to take their turn again under the changed limit
+    changed: Arc<Notify>,
+}
+
+impl Default for WriteThrottle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WriteThrottle {
+    /// Create new throttle without a limit
+    pub fn new() -> Self {
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket {
+                limit: None,
+                tokens: 0.0,
+                refilled: Instant::now(),
+            })),
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn limit(&self) -> Option<WriteLimit> {
+        self.bucket.lock().unwrap_or_else(|e| e.into_inner()).limit
+    }
+
+    /// Replace the limit, `None` stops throttling. Writes already waiting take their turn
+    /// again under the new limit.
+    pub fn set_limit(&self, limit: Option<WriteLimit>) -> Result<(), InvalidWriteLimit> {
+        if let Some(limit) = &limit {
+            limit.validate()?;
+        }
+        warn!(?limit, "Database write throttle changed");
+
+        {
+            let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+            bucket.limit = limit;
+            bucket.tokens = limit.map(|limit| limit.burst()).unwrap_or(0.0);
+            bucket.refilled = Instant::now();
+        }
+        self.changed.notify_waiters();
+
+        METRICS
+            .limit
+            .set(limit.map(|limit| limit.qps).unwrap_or(0.0));
+        Ok(())
+    }
+
+    /// Wait for the turn of a write
+    pub async fn acquire(&self) {
+        let mut throttled = false;
+
+        loop {
+            let (wait, changed) = {
+                let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+                let wait = match bucket.limit {
+                    Some(limit) => bucket.take(limit),
+                    None => return,
+                };
+                // created under the lock, so a change right after it isn't missed
+                (wait, self.changed.notified())
+            };
+
+            if wait.is_zero() {
+                return;
+            }
+            if !throttled {
+                throttled = true;
+                METRICS.throttled.inc();
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => return,
+                _ = changed => {}
+            }
+        }
+    }
+
+    /// Take the turn of a write if it's due right now, for writes better failed than delayed
+    pub fn try_acquire(&self) -> bool {
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let limit = match bucket.limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+
+        if bucket.take(limit).is_zero() {
+            true
+        } else {
+            // give the reservation back
+            bucket.tokens += 1.0;
+            METRICS.throttled.inc();
+            false
+        }
+    }
+
+    /// Routes reading and replacing the limit on `GET` and `PUT /admin/write-throttle` and
+    /// removing it on `DELETE /admin/write-throttle`, meant to be passed to `admin_router`
+    pub fn admin_routes(&self) -> Router {
+        Router::new()
+            .route(
+                "/admin/write-throttle",
+                routing::get(|State(throttle): State<WriteThrottle>| async move {
+                    Json(throttle.limit())
+                })
+                .put(
+                    |State(throttle): State<WriteThrottle>, Json(limit): Json<WriteLimit>| async move {
+                        throttle
+                            .set_limit(Some(limit))
+                            .map(|()| StatusCode::NO_CONTENT)
+                            .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))
+                    },
+                )
+                .delete(|State(throttle): State<WriteThrottle>| async move {
+                    // removing the limit can't fail
+                    let _ = throttle.set_limit(None);
+                    StatusCode::NO_CONTENT
+                }),
+            )
+            .with_state(self.clone())
+    }
+}
@@ -0,0 +1,191 @@
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::time::Duration;
+
+use axum::extract::ws::{CloseFrame, Message};
+use once_cell::sync::Lazy;
+use prometheus::{register_histogram, Histogram};
+use tokio::time::{self, Instant};
+
+#[cfg(feature = "ws-registry")]
+use super::registry::{CloseReason, Connection};
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    rtt: Histogram,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            rtt: register_histogram!("ws_ping_rtt", "WebSocket ping round-trip time")
+                .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Close code sent to connections that stayed idle for too long (1001, going away)
+pub const IDLE_CLOSE_CODE: u16 = 1001;
+
+// deadline of intervals too long to add to the current time, like `Duration::MAX`,
+// pushed 30 years away so it never comes
+const FAR_FUTURE: Duration = Duration::from_secs(86400 * 365 * 30);
+
+/// Keeps a WebSocket connection alive by sending pings, measures round-trip time
+/// into the `ws_ping_rtt` histogram and detects idle connections.
+///
+/// Meant to be polled inside the connection's `select!` loop alongside
+/// the socket and `Connection::recv`:
+///
+/// ```ignore
+/// loop {
+///     tokio::select! {
+///         message = heartbeat.tick() => {
+///             let close = matches!(message, Message::Close(_));
+///             socket.send(message).await?;
+///             if close {
+///                 break;
+///             }
+///         }
+///         Some(Ok(message)) = socket.recv() => {
+///             heartbeat.on_message(&message);
+///             // ...
+///         }
+///     }
+/// }
+/// ```
+///
+/// With the `ws-registry` feature, `watch` polls a registry connection together with the
+/// heartbeat and closes it on `ConnectionRegistry::shutdown`.
+pub struct Heartbeat {
+    ping_interval: Duration,
+    idle_timeout: Duration,
+    last_activity: Instant,
+    next_ping: Instant,
+    seq: u64,
+    pending: Option<(u64, Instant)>,
+}
+
+impl Heartbeat {
+    /// # Arguments
+    ///
+    /// * `ping_interval` - how often pings are sent
+    /// * `idle_timeout` - the connection is closed if nothing was received from the peer for this long
+    pub fn new(ping_interval: Duration, idle_timeout: Duration) -> Self {
+        let now = Instant::now();
+
+        Self {
+            ping_interval,
+            idle_timeout,
+            last_activity: now,
+            next_ping: deadline(now, ping_interval),
+            seq: 0,
+            pending: None,
+        }
+    }
+
+    /// Wait for the next message to send: a ping, or a close frame
+    /// once the connection has been idle for longer than `idle_timeout`.
+    ///
+    /// Cancel safe.
+    pub async fn tick(&mut self) -> Message {
+        let idle_deadline = deadline(self.last_activity, self.idle_timeout);
+
+        if self.next_ping < idle_deadline {
+            time::sleep_until(self.next_ping).await;
+            self.next_ping = deadline(Instant::now(), self.ping_interval);
+            self.seq += 1;
+            self.pending = Some((self.seq, Instant::now()));
+            Message::Ping(self.seq.to_be_bytes().to_vec())
+        } else {
+            time::sleep_until(idle_deadline).await;
+            Message::Close(Some(CloseFrame {
+                code: IDLE_CLOSE_CODE,
+                reason: Cow::Borrowed("idle timeout"),
+            }))
+        }
+    }
+
+    /// Record a message received from the peer
+    pub fn on_message(&mut self, message: &Message) {
+        self.last_activity = Instant::now();
+
+        if let Message::Pong(payload) = message {
+            let seq = payload.as_slice().try_into().ok().map(u64::from_be_bytes);
+
+            match self.pending {
+                Some((pending, sent_at)) if Some(pending) == seq => {
+                    METRICS.rtt.observe(sent_at.elapsed().as_secs_f64());
+                    self.pending = None;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Next step of a registered connection, returned by `Heartbeat::watch`
+#[cfg(feature = "ws-registry")]
+pub enum HeartbeatEvent<M> {
+    /// Message from the registry to deliver to the peer
+    Deliver(M),
+    /// Ping or close frame to send to the peer, the connection is over after a close frame
+    Send(Message),
+}
+
+#[cfg(feature = "ws-registry")]
+impl Heartbeat {
+    /// Wait for the next message of the registry connection or of the heartbeat,
+    /// a shorthand of polling `Connection::recv` and `tick` together:
+    ///
+    /// ```ignore
+    /// let mut connection = registry.register(agent_id);
+    /// loop {
+    ///     tokio::select! {
+    ///         event = heartbeat.watch(&mut connection) => match event {
+    ///             HeartbeatEvent::Deliver(message) => socket.send(message.into()).await?,
+    ///             HeartbeatEvent::Send(message) => {
+    ///                 let close = matches!(message, Message::Close(_));
+    ///                 socket.send(message).await?;
+    ///                 if close {
+    ///                     break;
+    ///                 }
+    ///             }
+    ///         },
+    ///         Some(Ok(message)) = socket.recv() => {
+    ///             heartbeat.on_message(&message);
+    ///             // ...
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// Once the connection is disconnected, a close frame is sent with the reason of
+    /// `ConnectionRegistry::shutdown`, or 1000 (normal closure) if it was disconnected
+    /// or replaced otherwise, so dropping the connection after it lets the shutdown drain.
+    ///
+    /// Cancel safe.
+    pub async fn watch<M>(&mut self, connection: &mut Connection<M>) -> HeartbeatEvent<M> {
+        tokio::select! {
+            message = connection.recv() => match message {
+                Some(message) => HeartbeatEvent::Deliver(message),
+                None => {
+                    let reason = connection
+                        .close_reason()
+                        .unwrap_or_else(|| CloseReason::new(1000, "disconnected"));
+                    HeartbeatEvent::Send(Message::Close(Some(CloseFrame {
+                        code: reason.code,
+                        reason: reason.reason,
+                    })))
+                }
+            },
+            message = self.tick() => HeartbeatEvent::Send(message),
+        }
+    }
+}
+
+/// `after` from `from`, or a deadline that never comes if it can't be added
+fn deadline(from: Instant, after: Duration) -> Instant {
+    from.checked_add(after).unwrap_or_else(|| from + FAR_FUTURE)
+}
@@ -0,0 +1,113 @@
+use axum::extract::ws::Message;
+use once_cell::sync::Lazy;
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+// 16 B .. 1 MiB
+const SIZE_BUCKETS: [f64; 9] = [
+    16.0, 64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0,
+];
+
+struct Metrics {
+    messages: IntCounterVec,
+    size: HistogramVec,
+    close: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            messages: register_int_counter_vec!(
+                "ws_messages_total",
+                "WebSocket messages",
+                &["direction", "kind"]
+            )
+            .expect("Can't create stats metrics"),
+            size: register_histogram_vec!(
+                "ws_message_size",
+                "WebSocket message payload size",
+                &["direction"],
+                SIZE_BUCKETS.to_vec()
+            )
+            .expect("Can't create stats metrics"),
+            close: register_int_counter_vec!(
+                "ws_close_total",
+                "WebSocket close frames",
+                &["direction", "code"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Message counters of a single connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageStats {
+    pub received: u64,
+    pub received_bytes: u64,
+    pub sent: u64,
+    pub sent_bytes: u64,
+}
+
+/// Counts messages of a WebSocket connection.
+///
+/// Aggregates are exported as `ws_messages_total{direction,kind}`, `ws_message_size{direction}`
+/// and `ws_close_total{direction,code}`, per-connection numbers are available from `stats`
+/// (e.g. to log them when the connection is closed).
+#[derive(Debug, Default)]
+pub struct ConnectionMetrics {
+    stats: MessageStats,
+}
+
+impl ConnectionMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a message received from the peer
+    pub fn received(&mut self, message: &Message) {
+        let size = observe("in", message);
+        self.stats.received += 1;
+        self.stats.received_bytes += size;
+    }
+
+    /// Record a message sent to the peer
+    pub fn sent(&mut self, message: &Message) {
+        let size = observe("out", message);
+        self.stats.sent += 1;
+        self.stats.sent_bytes += size;
+    }
+
+    pub fn stats(&self) -> MessageStats {
+        self.stats
+    }
+}
+
+fn observe(direction: &str, message: &Message) -> u64 {
+    let (kind, size) = match message {
+        Message::Text(text) => ("text", text.len()),
+        Message::Binary(data) => ("binary", data.len()),
+        Message::Ping(data) => ("ping", data.len()),
+        Message::Pong(data) => ("pong", data.len()),
+        Message::Close(frame) => {
+            let code = frame
+                .as_ref()
+                .map(|frame| frame.code.to_string())
+                .unwrap_or_else(|| "none".to_owned());
+            METRICS
+                .close
+                .with_label_values(&[direction, code.as_str()])
+                .inc();
+            ("close", 0)
+        }
+    };
+
+    METRICS.messages.with_label_values(&[direction, kind]).inc();
+    METRICS
+        .size
+        .with_label_values(&[direction])
+        .observe(size as f64);
+
+    size as u64
+}
@@ -0,0 +1,20 @@
+#[cfg(feature = "ws-heartbeat")]
+pub use heartbeat::{Heartbeat, IDLE_CLOSE_CODE};
+
+#[cfg(all(feature = "ws-heartbeat", feature = "ws-registry"))]
+pub use heartbeat::HeartbeatEvent;
+
+#[cfg(feature = "ws-metrics")]
+pub use metrics::{ConnectionMetrics, MessageStats};
+
+#[cfg(feature = "ws-registry")]
+pub use registry::{CloseReason, Connection, ConnectionRegistry};
+
+#[cfg(feature = "ws-heartbeat")]
+mod heartbeat;
+
+#[cfg(feature = "ws-metrics")]
+mod metrics;
+
+#[cfg(feature = "ws-registry")]
+mod registry;
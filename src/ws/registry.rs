@@ -0,0 +1,337 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
+use svc_agent::AgentId;
+use tokio::sync::{mpsc, Notify};
+use tracing::{info, warn};
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    connections: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            connections: register_int_gauge_vec!(
+                "ws_connections",
+                "Active long-lived connections",
+                &["audience"]
+            )
+            .expect("Can't create stats metrics"),
+        }
+    }
+}
+
+/// Why the server closed a connection, to be sent to the peer in a close frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseReason {
+    pub code: u16,
+    pub reason: Cow<'static, str>,
+}
+
+impl CloseReason {
+    pub fn new(code: u16, reason: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+        }
+    }
+
+    /// 1012 (service restart) with "server restarting" reason
+    pub fn server_restarting() -> Self {
+        Self::new(1012, "server restarting")
+    }
+}
+
+struct Entry<M> {
+    id: u64,
+    sender: mpsc::Sender<M>,
+}
+
+struct Inner<M> {
+    next_id: u64,
+    connections: HashMap<AgentId, Entry<M>>,
+    // connection handles not dropped yet, including disconnected ones
+    live: usize,
+    close_reason: Option<CloseReason>,
+}
+
+struct Shared<M> {
+    inner: Mutex<Inner<M>>,
+    dropped: Notify,
+}
+
+/// Registry of active WebSocket/SSE connections keyed by `AgentId`.
+///
+/// Cloning is cheap, all clones share the same set of connections.
+/// Active connections are exported as the `ws_connections{audience}` gauge.
+pub struct ConnectionRegistry<M> {
+    shared: Arc<Shared<M>>,
+    buffer: usize,
+}
+
+impl<M> Clone for ConnectionRegistry<M> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            buffer: self.buffer,
+        }
+    }
+}
+
+impl<M: Clone + Send + 'static> ConnectionRegistry<M> {
+    /// Create new registry
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - number of messages queued per connection before new ones are dropped,
+    ///   at least 1
+    pub fn new(buffer: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                inner: Mutex::new(Inner {
+                    next_id: 0,
+                    connections: HashMap::new(),
+                    live: 0,
+                    close_reason: None,
+                }),
+                dropped: Notify::new(),
+            }),
+            // channels can't be created without capacity
+            buffer: buffer.max(1),
+        }
+    }
+
+    /// Register a connection for the agent.
+    ///
+    /// An agent holds at most one connection, registering again disconnects the previous one.
+    /// The connection is unregistered when the returned handle is dropped.
+    /// After `shutdown` the returned connection is already closed.
+    pub fn register(&self, agent_id: AgentId) -> Connection<M> {
+        let (sender, receiver) = mpsc::channel(self.buffer);
+
+        let mut inner = self.lock();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.live += 1;
+
+        if inner.close_reason.is_none() {
+            let previous = inner
+                .connections
+                .insert(agent_id.clone(), Entry { id, sender });
+            if previous.is_none() {
+                METRICS
+                    .connections
+                    .with_label_values(&[agent_id.as_account_id().audience()])
+                    .inc();
+            }
+        }
+
+        Connection {
+            agent_id,
+            id,
+            receiver,
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Send a message to the agent. Returns `false` if the agent is not connected
+    /// or its queue is full.
+    pub fn send(&self, agent_id: &AgentId, message: M) -> bool {
+        match self.lock().connections.get(agent_id) {
+            Some(entry) => try_send(agent_id, entry, message),
+            None => false,
+        }
+    }
+
+    /// Send a message to every connected agent. Returns the number of agents it was queued for.
+    pub fn broadcast(&self, message: M) -> usize {
+        self.lock()
+            .connections
+            .iter()
+            .filter(|(agent_id, entry)| try_send(agent_id, entry, message.clone()))
+            .count()
+    }
+
+    /// Send a message to every connected agent of the audience.
+    /// Returns the number of agents it was queued for.
+    pub fn broadcast_to_audience(&self, audience: &str, message: M) -> usize {
+        self.lock()
+            .connections
+            .iter()
+            .filter(|(agent_id, _)| agent_id.as_account_id().audience() == audience)
+            .filter(|(agent_id, entry)| try_send(agent_id, entry, message.clone()))
+            .count()
+    }
+
+    /// Forcibly disconnect the agent. Its `Connection::recv` will return `None`
+    /// once queued messages are consumed. Returns `false` if the agent was not connected.
+    pub fn disconnect(&self, agent_id: &AgentId) -> bool {
+        let removed = self.lock().connections.remove(agent_id);
+        if removed.is_some() {
+            dec_gauge(agent_id);
+        }
+        removed.is_some()
+    }
+
+    /// Disconnect every agent with the close reason and wait up to `drain`
+    /// for their connection handles to be dropped.
+    ///
+    /// Connection loops see `None` from `Connection::recv`, should send a close frame
+    /// built from `Connection::close_reason` and drop the handle.
+    /// Returns the number of connections still alive when the drain period ended.
+    pub async fn shutdown(&self, reason: CloseReason, drain: Duration) -> usize {
+        let started = Instant::now();
+        let (disconnected, at_signal) = {
+            let mut inner = self.lock();
+            inner.close_reason = Some(reason);
+            (std::mem::take(&mut inner.connections), inner.live)
+        };
+        for agent_id in disconnected.keys() {
+            dec_gauge(agent_id);
+        }
+        info!(
+            connections = disconnected.len(),
+            "Closing connections, draining for {:?}", drain
+        );
+        drop(disconnected);
+
+        let drained = async {
+            loop {
+                let dropped = self.shared.dropped.notified();
+                if self.lock().live == 0 {
+                    break;
+                }
+                dropped.await;
+            }
+        };
+
+        let _ = tokio::time::timeout(drain, drained).await;
+
+        let remaining = self.lock().live;
+        let drain_time = started.elapsed();
+        if remaining > 0 {
+            warn!(
+                at_signal,
+                closed = at_signal.saturating_sub(remaining),
+                remaining,
+                ?drain_time,
+                "Connections not drained in time"
+            );
+        } else {
+            info!(at_signal, ?drain_time, "Connections drained");
+        }
+        remaining
+    }
+
+    /// Whether the agent has an active connection
+    pub fn is_connected(&self, agent_id: &AgentId) -> bool {
+        self.lock().connections.contains_key(agent_id)
+    }
+
+    /// Total number of active connections
+    pub fn len(&self) -> usize {
+        self.lock().connections.len()
+    }
+
+    /// Whether there are no active connections
+    pub fn is_empty(&self) -> bool {
+        self.lock().connections.is_empty()
+    }
+
+    /// Number of active connections of the audience
+    pub fn audience_len(&self, audience: &str) -> usize {
+        self.lock()
+            .connections
+            .keys()
+            .filter(|agent_id| agent_id.as_account_id().audience() == audience)
+            .count()
+    }
+
+    /// Agents with active connections
+    pub fn agents(&self) -> Vec<AgentId> {
+        self.lock().connections.keys().cloned().collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner<M>> {
+        lock(&self.shared)
+    }
+}
+
+/// Handle of a registered connection, returned by `ConnectionRegistry::register`.
+///
+/// Dropping it removes the connection from the registry.
+pub struct Connection<M> {
+    agent_id: AgentId,
+    id: u64,
+    receiver: mpsc::Receiver<M>,
+    shared: Arc<Shared<M>>,
+}
+
+impl<M> Connection<M> {
+    pub fn agent_id(&self) -> &AgentId {
+        &self.agent_id
+    }
+
+    /// Receive the next message addressed to this connection.
+    ///
+    /// Returns `None` when the connection was disconnected through the registry
+    /// or replaced by a newer connection of the same agent.
+    pub async fn recv(&mut self) -> Option<M> {
+        self.receiver.recv().await
+    }
+
+    /// Close reason to send to the peer once the registry was shut down
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        lock(&self.shared).close_reason.clone()
+    }
+}
+
+impl<M> Drop for Connection<M> {
+    fn drop(&mut self) {
+        let mut inner = lock(&self.shared);
+        let is_current = matches!(
+            inner.connections.get(&self.agent_id),
+            Some(entry) if entry.id == self.id
+        );
+        if is_current {
+            inner.connections.remove(&self.agent_id);
+            dec_gauge(&self.agent_id);
+        }
+        inner.live -= 1;
+        drop(inner);
+
+        self.shared.dropped.notify_waiters();
+    }
+}
+
+fn lock<M>(shared: &Shared<M>) -> MutexGuard<'_, Inner<M>> {
+    shared
+        .inner
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn try_send<M>(agent_id: &AgentId, entry: &Entry<M>, message: M) -> bool {
+    match entry.sender.try_send(message) {
+        Ok(()) => true,
+        Err(mpsc::error::TrySendError::Full(_)) => {
+            warn!(%agent_id, "Connection queue is full, message dropped");
+            false
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => false,
+    }
+}
+
+fn dec_gauge(agent_id: &AgentId) {
+    METRICS
+        .connections
+        .with_label_values(&[agent_id.as_account_id().audience()])
+        .dec();
+}
@@ -0,0 +1,277 @@
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote, quote_spanned};
+use syn::{parse_macro_input, Error, ItemFn, LitInt, LitStr, Result};
+
+/// Declares a handler together with its route, generating `<handler>_route()` which
+/// returns the `svc_utils::routes::RouteSpec` of the handler with metrics, authn and the
+/// other policies applied:
+///
+/// ```ignore
+/// #[svc_route(method = "GET", path = "/rooms/:id", auth = "required", timeout = "5s")]
+/// async fn read_room(AccountIdExtractor(account_id): AccountIdExtractor) -> Response {
+///     // ...
+/// }
+///
+/// let router = RouteTable::new().route(read_room_route()).into_router();
+/// ```
+///
+/// Arguments:
+/// - `method`: `GET`, `POST`, `PUT`, `PATCH`, `DELETE`, `HEAD` or `OPTIONS`
+/// - `path`: axum route path
+/// - `auth`: `required` or `none`, `none` by default, `required` needs the
+///   `authn-extractor` feature of svc-utils
+/// - `timeout`, `cache_ttl`: duration like `500ms`, `5s` or `1m`
+/// - `body_limit`: limit of the request body in bytes
+/// - `metrics_label`: `path` label of the route metrics, the route path by default
+#[proc_macro_attribute]
+pub fn svc_route(args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut route = Route::default();
+    let parser = syn::meta::parser(|meta| route.parse(meta));
+    parse_macro_input!(args with parser);
+    let handler = parse_macro_input!(item as ItemFn);
+
+    match route.expand(&handler) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+#[derive(Default)]
+struct Route {
+    method: Option<LitStr>,
+    path: Option<LitStr>,
+    /// Span of `auth = "required"`
+    auth: Option<Span>,
+    timeout: Option<u64>,
+    cache_ttl: Option<u64>,
+    body_limit: Option<u64>,
+    metrics_label: Option<LitStr>,
+}
+
+impl Route {
+    fn parse(&mut self, meta: syn::meta::ParseNestedMeta) -> Result<()> {
+        if meta.path.is_ident("method") {
+            self.method = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("path") {
+            self.path = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("auth") {
+            let auth: LitStr = meta.value()?.parse()?;
+            self.auth = match auth.value().as_str() {
+                "required" => Some(auth.span()),
+                "none" => None,
+                _ => return Err(Error::new(auth.span(), "expected `required` or `none`")),
+            };
+        } else if meta.path.is_ident("timeout") {
+            self.timeout = Some(parse_duration(&meta.value()?.parse()?)?);
+        } else if meta.path.is_ident("cache_ttl") {
+            self.cache_ttl = Some(parse_duration(&meta.value()?.parse()?)?);
+        } else if meta.path.is_ident("body_limit") {
+            let limit: LitInt = meta.value()?.parse()?;
+            self.body_limit = Some(limit.base10_parse()?);
+        } else if meta.path.is_ident("metrics_label") {
+            self.metrics_label = Some(meta.value()?.parse()?);
+        } else {
+            return Err(meta.error("unsupported svc_route argument"));
+        }
+
+        Ok(())
+    }
+
+    fn expand(self, handler: &ItemFn) -> Result<TokenStream2> {
+        let method = self
+            .method
+            .ok_or_else(|| Error::new(Span::call_site(), "missing `method` argument"))?;
+        let path = self
+            .path
+            .ok_or_else(|| Error::new(Span::call_site(), "missing `path` argument"))?;
+
+        let method_fn = match method.value().to_ascii_uppercase().as_str() {
+            "GET" => format_ident!("get"),
+            "POST" => format_ident!("post"),
+            "PUT" => format_ident!("put"),
+            "PATCH" => format_ident!("patch"),
+            "DELETE" => format_ident!("delete"),
+            "HEAD" => format_ident!("head"),
+            "OPTIONS" => format_ident!("options"),
+            _ => return Err(Error::new(method.span(), "unsupported HTTP method")),
+        };
+
+        let vis = &handler.vis;
+        let name = &handler.sig.ident;
+        let mut spec = quote! {
+            ::svc_utils::routes::RouteSpec::new(
+                #path,
+                ::svc_utils::__private::axum::routing::#method_fn(#name),
+            )
+        };
+        // fails to compile with the argument highlighted when svc-utils lacks authn
+        if let Some(span) = self.auth {
+            spec = quote_spanned!(span=> ::svc_utils::__private::with_authn!(#spec));
+        }
+
+        let mut policies = Vec::new();
+        if let Some(ms) = self.timeout {
+            policies.push(quote!(.with_timeout(::std::time::Duration::from_millis(#ms))));
+        }
+        if let Some(ms) = self.cache_ttl {
+            policies.push(quote!(.with_cache_ttl(::std::time::Duration::from_millis(#ms))));
+        }
+        if let Some(limit) = self.body_limit {
+            policies.push(quote!(.with_body_limit(#limit)));
+        }
+        if let Some(label) = self.metrics_label {
+            policies.push(quote!(.with_metrics_label(#label)));
+        }
+
+        let route_fn = format_ident!("{}_route", name);
+        let doc = format!("Route of [`{}`]", name);
+
+        Ok(quote! {
+            #handler
+
+            #[doc = #doc]
+            #vis fn #route_fn() -> ::svc_utils::routes::RouteSpec {
+                #spec #(#policies)*
+            }
+        })
+    }
+}
+
+/// Parses durations like `500ms`, `5s`, `1m` into milliseconds
+fn parse_duration(lit: &LitStr) -> Result<u64> {
+    let value = lit.value();
+    let (number, multiplier) = if let Some(number) = value.strip_suffix("ms") {
+        (number, 1)
+    } else if let Some(number) = value.strip_suffix('s') {
+        (number, 1000)
+    } else if let Some(number) = value.strip_suffix('m') {
+        (number, 60 * 1000)
+    } else {
+        return Err(Error::new(
+            lit.span(),
+            "expected duration like `500ms`, `5s` or `1m`",
+        ));
+    };
+
+    number
+        .parse::<u64>()
+        .map_err(|_| Error::new(lit.span(), "expected duration like `500ms`, `5s` or `1m`"))?
+        .checked_mul(multiplier)
+        .ok_or_else(|| Error::new(lit.span(), "duration is too long"))
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse::Parser;
+
+    use super::*;
+
+    fn parse(args: TokenStream2) -> Result<Route> {
+        let mut route = Route::default();
+        syn::meta::parser(|meta| route.parse(meta)).parse2(args)?;
+        Ok(route)
+    }
+
+    fn expand(args: TokenStream2) -> Result<String> {
+        let handler: ItemFn = syn::parse_quote!(
+            async fn read_room() {}
+        );
+        Ok(parse(args)?.expand(&handler)?.to_string())
+    }
+
+    fn duration(value: &str) -> Result<u64> {
+        parse_duration(&LitStr::new(value, Span::call_site()))
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(duration("500ms").unwrap(), 500);
+        assert_eq!(duration("5s").unwrap(), 5000);
+        assert_eq!(duration("1m").unwrap(), 60_000);
+        assert_eq!(duration("0s").unwrap(), 0);
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for value in ["5", "5h", "s", "-5s", "1.5s", " 5s", "5 s"] {
+            assert!(duration(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn rejects_overflowing_durations() {
+        let err = duration(&format!("{}m", u64::MAX / 1000)).unwrap_err();
+        assert_eq!(err.to_string(), "duration is too long");
+        assert_eq!(duration(&format!("{}ms", u64::MAX)).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn parses_all_arguments() {
+        let route = parse(quote!(
+            method = "GET",
+            path = "/rooms/:id",
+            auth = "required",
+            timeout = "5s",
+            cache_ttl = "1m",
+            body_limit = 1024,
+            metrics_label = "rooms"
+        ))
+        .unwrap();
+
+        assert_eq!(route.method.unwrap().value(), "GET");
+        assert_eq!(route.path.unwrap().value(), "/rooms/:id");
+        assert!(route.auth.is_some());
+        assert_eq!(route.timeout, Some(5000));
+        assert_eq!(route.cache_ttl, Some(60_000));
+        assert_eq!(route.body_limit, Some(1024));
+        assert_eq!(route.metrics_label.unwrap().value(), "rooms");
+    }
+
+    #[test]
+    fn parses_auth() {
+        assert!(parse(quote!(auth = "none")).unwrap().auth.is_none());
+        assert!(parse(quote!()).unwrap().auth.is_none());
+
+        let err = parse(quote!(auth = "optional")).err().unwrap();
+        assert_eq!(err.to_string(), "expected `required` or `none`");
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        let err = parse(quote!(method = "GET", rate_limit = "10"))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "unsupported svc_route argument");
+
+        assert!(parse(quote!(method = GET)).is_err());
+        assert!(parse(quote!(body_limit = "1024")).is_err());
+        assert!(parse(quote!(body_limit = -1)).is_err());
+    }
+
+    #[test]
+    fn requires_method_and_path() {
+        let err = expand(quote!(path = "/rooms")).unwrap_err();
+        assert_eq!(err.to_string(), "missing `method` argument");
+
+        let err = expand(quote!(method = "GET")).unwrap_err();
+        assert_eq!(err.to_string(), "missing `path` argument");
+
+        let err = expand(quote!(method = "TRACE", path = "/rooms")).unwrap_err();
+        assert_eq!(err.to_string(), "unsupported HTTP method");
+    }
+
+    #[test]
+    fn expands_route_fn() {
+        let tokens = expand(quote!(method = "get", path = "/rooms", timeout = "5s")).unwrap();
+        assert!(tokens.contains("fn read_room_route ()"));
+        assert!(tokens.contains("routing :: get (read_room)"));
+        assert!(
+            tokens.contains(". with_timeout (:: std :: time :: Duration :: from_millis (5000u64))")
+        );
+        assert!(!tokens.contains("with_authn"));
+
+        let tokens = expand(quote!(method = "POST", path = "/rooms", auth = "required")).unwrap();
+        assert!(tokens.contains("__private :: with_authn !"));
+    }
+}